@@ -0,0 +1,65 @@
+//! Sequential vs parallel hashing of files of increasing size, to tune `PARALLEL_HASH_THRESHOLD`.
+//! Run with `cargo bench --features parallel-hash`. Set `HASH_BENCH_MAX_MIB` to go past 256 MiB.
+use banyan_shared::hash::{hash_file, HashFunction, HashStrategy, PARALLEL_HASH_THRESHOLD};
+use std::{
+    env,
+    fs::File,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// How many times to hash each file, keeping the fastest
+const RUNS: u32 = 5;
+
+/// The fastest of `RUNS` hashes of `file`
+fn fastest(file: &File, strategy: HashStrategy) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            hash_file(file, strategy, HashFunction::Sha2_256).expect("hashing failed");
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+/// Throughput in MiB/s
+fn mib_per_sec(size: u64, time: Duration) -> f64 {
+    size as f64 / (1 << 20) as f64 / time.as_secs_f64()
+}
+
+fn main() {
+    let max_mib: u64 = env::var("HASH_BENCH_MAX_MIB")
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(256);
+    let path = env::temp_dir().join("banyan-hash-bench");
+    println!(
+        "{:>12} {:>16} {:>16}  (threshold: {} bytes, {:?} cores)",
+        "size",
+        "sequential MiB/s",
+        "parallel MiB/s",
+        PARALLEL_HASH_THRESHOLD,
+        std::thread::available_parallelism().map(|n| n.get())
+    );
+    let mut size = 16 << 10;
+    while size <= max_mib << 20 {
+        // Not all zeroes, so nothing can skip work
+        let data: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+        File::create(&path)
+            .and_then(|mut file| file.write_all(&data))
+            .expect("couldn't write the bench file");
+        let file = File::open(&path).expect("couldn't open the bench file");
+        let sequential = fastest(&file, HashStrategy::Sequential);
+        let parallel = fastest(&file, HashStrategy::Parallel);
+        println!(
+            "{:>12} {:>16.1} {:>16.1}{}",
+            size,
+            mib_per_sec(size, sequential),
+            mib_per_sec(size, parallel),
+            if parallel < sequential { "  *" } else { "" }
+        );
+        size *= 4;
+    }
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,101 @@
+use banyan_shared::{
+    error::{BanyanError, Result},
+    eth::EthClient,
+    manifest::DealManifest,
+};
+use ethers::types::Address;
+use std::{env, process::ExitCode};
+
+/*
+ * Review, sign and submit deal manifests.
+ * Status: `verify` rebuilds the proposal in a manifest and prints it, and who signed it. `sign`
+ * does the same then signs the manifest in place as the reviewer, and `submit` proposes it, both
+ * with an EthClient configured from the environment (see the README). If `MANIFEST_REVIEWER` is
+ * set, `submit` only proposes manifests that address signed.
+ */
+
+const USAGE: &str = "Usage: deal_manifest verify <manifest>
+       deal_manifest sign <manifest>
+       deal_manifest submit <manifest> [gas limit] [gas price in wei]";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["verify", manifest] => verify(manifest).await,
+        ["sign", manifest] => sign(manifest).await,
+        ["submit", manifest, gas @ ..] if gas.len() <= 2 => {
+            match (parse_gas(gas.first()), parse_gas(gas.get(1))) {
+                (Ok(gas_limit), Ok(gas_price)) => submit(manifest, gas_limit, gas_price).await,
+                _ => return usage(),
+            }
+        }
+        _ => return usage(),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Rebuild the proposal in a manifest, and print it and who signed it
+async fn verify(path: &str) -> Result<()> {
+    let manifest = DealManifest::load(path)?;
+    manifest.verify_async().await?;
+    println!("{}", manifest);
+    match manifest.signer()? {
+        Some(signer) => println!("Signed by {:?}", signer),
+        None => println!("Not signed"),
+    }
+    Ok(())
+}
+
+/// Rebuild the proposal in a manifest, and sign the manifest as its reviewer
+async fn sign(path: &str) -> Result<()> {
+    let manifest = DealManifest::load(path)?;
+    let client = EthClient::from_env().await?;
+    let signed = client.sign_manifest(manifest).await?;
+    signed.save(path)?;
+    if let Some(signer) = signed.signer()? {
+        println!("{}\nSigned by {:?}", signed, signer);
+    }
+    Ok(())
+}
+
+/// Rebuild the proposal in a manifest, and propose it
+async fn submit(path: &str, gas_limit: Option<u64>, gas_price: Option<u64>) -> Result<()> {
+    let manifest = DealManifest::load(path)?;
+    let reviewer = reviewer()?;
+    let client = EthClient::from_env().await?;
+    let deal_id = client
+        .propose_manifest(&manifest, reviewer, gas_limit, gas_price)
+        .await?;
+    println!("{}\nProposed as deal {}", manifest, deal_id.0);
+    Ok(())
+}
+
+/// Who must have signed a manifest before it's submitted, if anyone
+fn reviewer() -> Result<Option<Address>> {
+    env::var("MANIFEST_REVIEWER")
+        .ok()
+        .map(|reviewer| {
+            reviewer.parse().map_err(|_| {
+                BanyanError::Parse("MANIFEST_REVIEWER must be a valid Ethereum Address".to_string())
+            })
+        })
+        .transpose()
+}
+
+/// An optional gas argument
+fn parse_gas(arg: Option<&&str>) -> std::result::Result<Option<u64>, std::num::ParseIntError> {
+    arg.map(|arg| arg.parse()).transpose()
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
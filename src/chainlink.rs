@@ -0,0 +1,541 @@
+use crate::{
+    error::{BanyanError, Result},
+    eth::{mined_in, EthClient},
+    types::*,
+};
+use ethers::{
+    contract::EthEvent as _,
+    types::{Address, H256, U256},
+};
+use ethers_contract_derive::EthEvent;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    time::{Duration, Instant},
+};
+
+/*
+ * Support for the Chainlink verification flow of the Banyan Contract.
+ * Status: The oracle asks the contract to verify a deal. The contract sends a Chainlink request
+ * to our External Adapter, which checks every proof window and reports back through `fulfill`.
+ * The outcome is then used to settle the deal with `complete`.
+ */
+
+/// How often to check whether a verification request has been fulfilled
+const VERIFICATION_POLL_INTERVAL: Duration = Duration::from_secs(7);
+
+/// The Event emitted by the Banyan Contract when it sends a Chainlink request
+#[derive(Clone, Debug, Copy, EthEvent)]
+pub struct ChainlinkRequested {
+    #[ethevent(indexed)]
+    pub id: H256,
+}
+
+/// The Event emitted by the Banyan Contract when a Chainlink request is fulfilled
+#[derive(Clone, Debug, Copy, EthEvent)]
+pub struct ChainlinkFulfilled {
+    #[ethevent(indexed)]
+    pub id: H256,
+}
+
+/// The Event emitted by the Banyan Contract when a Chainlink request is cancelled
+#[derive(Clone, Debug, Copy, EthEvent)]
+pub struct ChainlinkCancelled {
+    #[ethevent(indexed)]
+    pub id: H256,
+}
+
+/// The Event emitted by the Banyan Contract when verification is requested for a deal
+#[derive(Clone, Debug, Copy, EthEvent)]
+pub struct RequestVerification {
+    #[ethevent(indexed)]
+    pub request_id: H256,
+    pub offer_id: U256,
+}
+
+/// VerificationRequest - A Chainlink request to verify the proofs of a deal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerificationRequest {
+    /// The Chainlink request ID
+    pub request_id: H256,
+    /// The deal being verified
+    pub deal_id: DealID,
+    /// The block the request was made in
+    pub block_num: BlockNum,
+}
+
+/// VerificationStatus - Where a verification request is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VerificationStatus {
+    /// The oracle hasn't responded yet
+    Pending,
+    /// The oracle responded in the given block
+    Fulfilled(BlockNum),
+    /// The request was cancelled in the given block
+    Cancelled(BlockNum),
+}
+
+/// VerificationResponse - What the oracle reported for a deal, as stored by `fulfill`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerificationResponse {
+    /// The deal that was verified
+    pub deal_id: DealID,
+    /// How many windows had a valid proof
+    pub success_count: u64,
+    /// How many windows the deal had
+    pub num_windows: u64,
+    /// The status code the External Adapter responded with
+    pub status: u16,
+    /// The result message the External Adapter responded with
+    pub result: String,
+}
+
+/// FinalizationReport - The outcome of verifying a deal, measured against a required success rate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinalizationReport {
+    /// The deal that was verified
+    pub deal_id: DealID,
+    /// How many windows had a valid proof
+    pub success_count: u64,
+    /// How many windows the deal had
+    pub num_windows: u64,
+    /// The success rate recorded on chain by `_proofSuccessRate`, as a percentage.
+    /// This is None until the deal has been completed, as the contract reads 0 until then.
+    pub proof_success_rate: Option<u64>,
+    /// The success rate the deal needs to pass, as a percentage
+    pub required_rate: u64,
+}
+
+impl FinalizationReport {
+    /// The percentage of windows with a valid proof, rounded down.
+    /// Prefers the rate recorded on chain, and falls back to the oracle's counts.
+    pub fn success_rate(&self) -> u64 {
+        match self.proof_success_rate {
+            Some(rate) => rate,
+            // In u128 so no count read from chain can overflow
+            None => (u128::from(self.success_count) * 100)
+                .checked_div(u128::from(self.num_windows))
+                .map_or(0, |rate| u64::try_from(rate).unwrap_or(u64::MAX)),
+        }
+    }
+
+    /// Whether the deal met its required success rate
+    pub fn passed(&self) -> bool {
+        self.success_rate() >= self.required_rate
+    }
+}
+
+impl Display for FinalizationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "Deal ID: {}", self.deal_id)?;
+        writeln!(
+            f,
+            "Successful Windows: {}/{}",
+            self.success_count, self.num_windows
+        )?;
+        writeln!(f, "Success Rate: {}%", self.success_rate())?;
+        writeln!(f, "Required Rate: {}%", self.required_rate)?;
+        write!(f, "Passed: {}", self.passed())
+    }
+}
+
+impl EthClient {
+    /* Chainlink Verification */
+
+    /// request_verification - Ask the Banyan Contract to verify a deal's proofs through Chainlink
+    /// # Arguments
+    /// * `deal_id` - The Deal ID to verify
+    /// * `job_id` - The ID of the Chainlink job that runs our External Adapter
+    /// * `gas_limit` - An (Optional) Gas Limit for the transaction
+    /// * `gas_price` - An (Optional) Gas Price for the transaction
+    /// # Returns
+    /// * `VerificationRequest` - The request, for tracking it through fulfilment
+    /// # Errors
+    /// * `BanyanError::InvalidDealStatus` - If the deal is already over
+    pub async fn request_verification(
+        &self,
+        deal_id: DealID,
+        job_id: &str,
+        gas_limit: Option<u64>,
+        gas_price: Option<u64>,
+    ) -> Result<VerificationRequest> {
+        self.check_deal_status(deal_id, "request verification of", |status| {
+            !status.is_terminal()
+        })
+        .await?;
+        let contract = self.contract();
+        // Older contracts also want the block to verify up to
+        let data = if self.contract_version().verification_takes_block() {
+            let block_num = self.get_latest_block_num().await?;
+            contract.encode(
+                "requestVerification",
+                (
+                    job_id.to_string(),
+                    block_num.to_string(),
+                    deal_id.to_string(),
+                ),
+            )?
+        } else {
+            contract.encode(
+                "requestVerification",
+                (job_id.to_string(), deal_id.to_string()),
+            )?
+        };
+        let receipt = self
+            .send_contract_transaction(data, gas_limit, gas_price)
+            .await?;
+        let request_id = receipt
+            .logs
+            .iter()
+            .find(|log| {
+                log.address == contract.address()
+                    && log.topics.len() == 2
+                    && log.topics[0] == ChainlinkRequested::signature()
+            })
+            .map(|log| log.topics[1])
+            .ok_or_else(|| {
+                BanyanError::Chain(format!(
+                    "No Chainlink request found in transaction {:?}",
+                    receipt.transaction_hash
+                ))
+            })?;
+        Ok(VerificationRequest {
+            request_id,
+            deal_id,
+            block_num: mined_in(&receipt)?,
+        })
+    }
+
+    /// get_verification_status - Check whether a verification request has been fulfilled
+    /// # Arguments
+    /// * `request` - The request returned by `request_verification`
+    pub async fn get_verification_status(
+        &self,
+        request: &VerificationRequest,
+    ) -> Result<VerificationStatus> {
+        let fulfilled = self
+            .contract()
+            .event::<ChainlinkFulfilled>()
+            .from_block(request.block_num.0)
+            .topic1(request.request_id)
+            .query_with_meta()
+            .await?;
+        if let Some((_, meta)) = fulfilled.first() {
+            return Ok(VerificationStatus::Fulfilled(BlockNum(
+                meta.block_number.as_u64(),
+            )));
+        }
+        let cancelled = self
+            .contract()
+            .event::<ChainlinkCancelled>()
+            .from_block(request.block_num.0)
+            .topic1(request.request_id)
+            .query_with_meta()
+            .await?;
+        if let Some((_, meta)) = cancelled.first() {
+            return Ok(VerificationStatus::Cancelled(BlockNum(
+                meta.block_number.as_u64(),
+            )));
+        }
+        Ok(VerificationStatus::Pending)
+    }
+
+    /// wait_for_verification - Wait for the oracle to respond to a verification request
+    /// # Arguments
+    /// * `request` - The request returned by `request_verification`
+    /// * `timeout` - How long to wait before giving up
+    /// # Returns
+    /// * `VerificationResponse` - What the oracle reported
+    /// # Errors
+    /// * If the request was cancelled, or the timeout passed without a response
+    pub async fn wait_for_verification(
+        &self,
+        request: &VerificationRequest,
+        timeout: Duration,
+    ) -> Result<VerificationResponse> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.get_verification_status(request).await? {
+                VerificationStatus::Fulfilled(_) => {
+                    return self.get_verification_response(request.deal_id).await
+                }
+                VerificationStatus::Cancelled(bn) => {
+                    return Err(BanyanError::Chain(format!(
+                        "Verification request {:?} was cancelled in block {}",
+                        request.request_id, bn
+                    )))
+                }
+                VerificationStatus::Pending if Instant::now() >= deadline => {
+                    return Err(BanyanError::Chain(format!(
+                        "Timed out waiting for verification request {:?}",
+                        request.request_id
+                    )))
+                }
+                VerificationStatus::Pending => {
+                    tokio::time::sleep(VERIFICATION_POLL_INTERVAL.min(timeout)).await
+                }
+            }
+        }
+    }
+
+    /// fulfill_verification - Report the outcome of a verification request, as the oracle
+    /// # Arguments
+    /// * `request_id` - The Chainlink request ID being fulfilled
+    /// * `response` - The outcome of verifying the deal
+    /// * `gas_limit` - An (Optional) Gas Limit for the transaction
+    /// * `gas_price` - An (Optional) Gas Price for the transaction
+    /// # Returns
+    /// * `BlockNum` - The block number the response was recorded in
+    pub async fn fulfill_verification(
+        &self,
+        request_id: H256,
+        response: VerificationResponse,
+        gas_limit: Option<u64>,
+        gas_price: Option<u64>,
+    ) -> Result<BlockNum> {
+        let data = self.contract().encode(
+            "fulfill",
+            (
+                request_id,
+                response.deal_id,
+                U256::from(response.success_count),
+                U256::from(response.num_windows),
+                response.status,
+                response.result,
+            ),
+        )?;
+        let receipt = self
+            .send_contract_transaction(data, gas_limit, gas_price)
+            .await?;
+        mined_in(&receipt)
+    }
+
+    /// get_verification_response - Read the oracle's latest response for a deal
+    /// # Arguments
+    /// * `deal_id` - The Deal ID to read the response for
+    pub async fn get_verification_response(&self, deal_id: DealID) -> Result<VerificationResponse> {
+        let (_, success_count, num_windows, status, result) = self
+            .contract()
+            .method::<_, (U256, U256, U256, U256, String)>("responses", deal_id)?
+            .call()
+            .await?;
+        Ok(VerificationResponse {
+            deal_id,
+            success_count: checked_uint(success_count, "Success count")?,
+            num_windows: checked_uint(num_windows, "Window count")?,
+            status: checked_uint(status, "Verification status")?,
+            result,
+        })
+    }
+
+    /// get_proof_success_rate - Read the success rate the contract recorded for a deal
+    /// # Arguments
+    /// * `deal_id` - The Deal ID to read the success rate for
+    pub async fn get_proof_success_rate(&self, deal_id: DealID) -> Result<u64> {
+        let rate = self
+            .contract()
+            .method::<_, U256>("_proofSuccessRate", deal_id)?
+            .call()
+            .await?;
+        checked_uint(rate, "Proof success rate")
+    }
+
+    /// get_finalization_report - Measure the outcome of verifying a deal against a required rate
+    /// # Arguments
+    /// * `deal_id` - The Deal ID to report on
+    /// * `required_rate` - The success rate the deal needs to pass, as a percentage
+    pub async fn get_finalization_report(
+        &self,
+        deal_id: DealID,
+        required_rate: u64,
+    ) -> Result<FinalizationReport> {
+        let response = self.get_verification_response(deal_id).await?;
+        let proof_success_rate = match self.get_offer(deal_id).await?.deal_status {
+            DealStatus::DealCompleted | DealStatus::DealFinalized => {
+                Some(self.get_proof_success_rate(deal_id).await?)
+            }
+            _ => None,
+        };
+        Ok(FinalizationReport {
+            deal_id,
+            success_count: response.success_count,
+            num_windows: response.num_windows,
+            proof_success_rate,
+            required_rate,
+        })
+    }
+
+    /// complete_deal - Settle a deal that has ended, using the oracle's verification outcome
+    /// # Arguments
+    /// * `deal_id` - The Deal ID to settle
+    /// * `required_rate` - The success rate the deal needs to pass, as a percentage
+    /// * `gas_limit` - An (Optional) Gas Limit for the transaction
+    /// * `gas_price` - An (Optional) Gas Price for the transaction
+    /// # Returns
+    /// * `BlockNum` - The block number the deal was settled in
+    /// # Errors
+    /// * `BanyanError::InvalidDealStatus` - If the deal is already completed, or can't be
+    pub async fn complete_deal(
+        &self,
+        deal_id: DealID,
+        required_rate: u64,
+        gas_limit: Option<u64>,
+        gas_price: Option<u64>,
+    ) -> Result<BlockNum> {
+        self.check_deal_status(deal_id, "complete", |status| {
+            status.can_reach(DealStatus::DealCompleted)
+        })
+        .await?;
+        let data = self
+            .contract()
+            .encode("complete", (deal_id, U256::from(required_rate)))?;
+        let receipt = self
+            .send_contract_transaction(data, gas_limit, gas_price)
+            .await?;
+        mined_in(&receipt)
+    }
+
+    /// withdraw_link - Withdraw the contract's LINK balance. Only the contract owner may do this.
+    /// # Arguments
+    /// * `gas_limit` - An (Optional) Gas Limit for the transaction
+    /// * `gas_price` - An (Optional) Gas Price for the transaction
+    pub async fn withdraw_link(
+        &self,
+        gas_limit: Option<u64>,
+        gas_price: Option<u64>,
+    ) -> Result<BlockNum> {
+        let data = self.contract().encode("withdrawLink", ())?;
+        let receipt = self
+            .send_contract_transaction(data, gas_limit, gas_price)
+            .await?;
+        mined_in(&receipt)
+    }
+
+    /// Get the address of the LINK token the contract pays its oracle with
+    pub async fn get_chainlink_token(&self) -> Result<Address> {
+        Ok(self
+            .contract()
+            .method::<_, Address>("getChainlinkToken", ())?
+            .call()
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(
+        success_count: u64,
+        num_windows: u64,
+        proof_success_rate: Option<u64>,
+    ) -> FinalizationReport {
+        FinalizationReport {
+            deal_id: DealID(1),
+            success_count,
+            num_windows,
+            proof_success_rate,
+            required_rate: 80,
+        }
+    }
+
+    #[test]
+    fn finalization_report_rates() {
+        // Before completion the rate comes from the oracle's counts
+        assert_eq!(report(8, 10, None).success_rate(), 80);
+        assert!(report(8, 10, None).passed());
+        assert_eq!(report(7, 9, None).success_rate(), 77);
+        assert!(!report(7, 9, None).passed());
+        assert_eq!(report(0, 0, None).success_rate(), 0);
+        // Counts too big to multiply by 100 in a u64 don't overflow
+        assert_eq!(report(u64::MAX, u64::MAX, None).success_rate(), 100);
+        assert_eq!(report(u64::MAX, 1, None).success_rate(), u64::MAX);
+        // Once the contract records a rate, that's the one that counts, even if it's 0
+        assert_eq!(report(8, 10, Some(90)).success_rate(), 90);
+        assert_eq!(report(8, 10, Some(0)).success_rate(), 0);
+        assert!(!report(8, 10, Some(0)).passed());
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    /// Test requesting verification and tracking the request through fulfilment
+    async fn verification_on_test_chain() -> Result<(), BanyanError> {
+        use crate::{contracts::ContractError, deals::DealProposalBuilder, test_chain::TestChain};
+        use ethers::signers::Signer;
+
+        let chain = TestChain::spawn().await?;
+        let creator = chain.creator_client().await?;
+        let oracle = chain.oracle_client().await?;
+        let deal = DealProposalBuilder::default()
+            .with_file(std::fs::File::open("test_files/ethereum.pdf")?)
+            .with_executor_address(format!("{:?}", chain.executor().address()))
+            .with_erc20_token_denomination(format!("{:?}", chain.token_address()))
+            .with_price_per_tib("1".parse()?)
+            .build()?;
+        let deal_id = creator.propose_deal(deal, None, None).await?;
+        assert_eq!(creator.get_chainlink_token().await?, chain.link_address());
+
+        // Only the oracle may ask for verification
+        assert!(creator
+            .request_verification(deal_id, "job", None, None)
+            .await
+            .is_err());
+        let request = oracle
+            .request_verification(deal_id, "job", None, None)
+            .await?;
+        assert_eq!(request.deal_id, deal_id);
+        assert_eq!(request.block_num, chain.block_number());
+        assert_eq!(
+            creator.get_verification_status(&request).await?,
+            VerificationStatus::Pending
+        );
+
+        let response = VerificationResponse {
+            deal_id,
+            success_count: 8,
+            num_windows: 10,
+            status: 200,
+            result: "ok".to_string(),
+        };
+        let block_num = oracle
+            .fulfill_verification(request.request_id, response.clone(), None, None)
+            .await?;
+        assert_eq!(
+            creator.get_verification_status(&request).await?,
+            VerificationStatus::Fulfilled(block_num)
+        );
+        assert_eq!(
+            creator
+                .wait_for_verification(&request, Duration::from_secs(1))
+                .await?,
+            response
+        );
+        // A request can only be fulfilled once
+        assert!(oracle
+            .fulfill_verification(request.request_id, response, None, None)
+            .await
+            .is_err());
+
+        let report = creator.get_finalization_report(deal_id, 75).await?;
+        assert_eq!(report.proof_success_rate, None);
+        assert_eq!(report.success_rate(), 80);
+        assert!(report.passed());
+        assert!(!creator.get_finalization_report(deal_id, 90).await?.passed());
+
+        // The deal hasn't ended yet
+        let err = creator
+            .complete_deal(deal_id, 75, None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BanyanError::Contract(ContractError::Reverted { reason, .. })
+                if reason == "Not yet deal end time"
+        ));
+        // Only the owner can withdraw LINK
+        let err = oracle.withdraw_link(None, None).await.unwrap_err();
+        assert!(err.is_unauthorized());
+        creator.withdraw_link(None, None).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,534 @@
+use crate::{
+    error::{BanyanError, Result},
+    hash::{FileHasher, FileHashes},
+};
+use blake3::{
+    hazmat::{merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode},
+    Hash as B3Hash, Hasher as B3Hasher,
+};
+use ethers::utils::hex;
+use multihash::{Code, MultihashDigest};
+use serde::{Deserialize, Serialize};
+use sha2::digest::generic_array::GenericArray;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/*
+ * Hashing huge files in a way that survives being interrupted.
+ * Status: The file is hashed as a run of equal, power of two sized Blake3 subtrees. After every
+ * few subtrees, the chaining value of each subtree so far and the Sha2-256 state (always at a
+ * block boundary) are written to a checkpoint file. A new run picks up from the checkpoint, once
+ * it's satisfied the part of the file already hashed hasn't changed since.
+ */
+
+/// How much of the file each Blake3 subtree covers by default, and so how much work can be lost
+pub const DEFAULT_SUBTREE_LEN: u64 = 1 << 28;
+
+/// How much of the file to hash between checkpoints by default
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1 << 30;
+
+/// The Sha2-256 initial hash value
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// How big of a buffer to use when reading from the file - 64KiB
+const READ_CHUNK_SIZE: usize = 65536;
+
+/// PrefixCheck - How to tell the part of a file a checkpoint covers hasn't changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrefixCheck {
+    /// The file's size and modification time must be what they were at the checkpoint
+    #[default]
+    Metadata,
+    /// Re-hash this many of the subtrees the checkpoint covers, chosen at random, and compare them
+    Sample(usize),
+}
+
+/// What's written to disk at a checkpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Checkpoint {
+    /// The size of the file when it was checkpointed
+    file_size: u64,
+    /// When the file was last modified when it was checkpointed, as seconds and nanoseconds
+    modified: Option<(u64, u32)>,
+    /// The size of each Blake3 subtree
+    subtree_len: u64,
+    /// The Sha2-256 state after every subtree so far
+    sha256_state: [u32; 8],
+    /// The hex Blake3 chaining value of every subtree so far
+    subtree_cvs: Vec<String>,
+}
+
+/// ResumableHasher - Hashes a file, checkpointing as it goes so an interrupted hash can resume
+/// The Multihash is always Sha2-256, whose state is all a checkpoint can save.
+pub struct ResumableHasher {
+    /// The file to hash
+    path: PathBuf,
+    /// Where to keep the checkpoint. It's removed once the file is hashed.
+    checkpoint_path: PathBuf,
+    /// How much of the file each Blake3 subtree covers
+    subtree_len: u64,
+    /// How much of the file to hash between checkpoints
+    checkpoint_interval: u64,
+    /// How to tell the checkpointed part of the file hasn't changed
+    prefix_check: PrefixCheck,
+    /// Where the last run picked up from
+    resumed_from: u64,
+}
+
+impl ResumableHasher {
+    /// Create a new ResumableHasher
+    /// # Arguments
+    /// * `path` - The file to hash
+    /// * `checkpoint_path` - Where to keep the checkpoint
+    pub fn new(path: impl Into<PathBuf>, checkpoint_path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            checkpoint_path: checkpoint_path.into(),
+            subtree_len: DEFAULT_SUBTREE_LEN,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            prefix_check: PrefixCheck::default(),
+            resumed_from: 0,
+        }
+    }
+
+    /// Set how much of the file each Blake3 subtree covers. This must be a power of two of at
+    /// least `blake3::CHUNK_LEN` bytes, and the same as when the checkpoint was written.
+    pub fn with_subtree_len(mut self, subtree_len: u64) -> Self {
+        self.subtree_len = subtree_len;
+        self
+    }
+
+    /// Set how much of the file to hash between checkpoints
+    pub fn with_checkpoint_interval(mut self, checkpoint_interval: u64) -> Self {
+        self.checkpoint_interval = checkpoint_interval;
+        self
+    }
+
+    /// Set how to tell the checkpointed part of the file hasn't changed
+    pub fn with_prefix_check(mut self, prefix_check: PrefixCheck) -> Self {
+        self.prefix_check = prefix_check;
+        self
+    }
+
+    /// Where the last run picked up from. 0 if it started from the beginning.
+    pub fn resumed_from(&self) -> u64 {
+        self.resumed_from
+    }
+
+    /// hashes - Hash the file, picking up from the checkpoint if there's a good one
+    /// # Returns
+    /// * `FileHashes` - The hashes of the whole file
+    /// # Errors
+    /// * `BanyanError::Math` - If the subtree length isn't a power of two of at least 1KiB
+    /// * `BanyanError::Io` - If the file can't be read, or the checkpoint can't be written
+    pub fn hashes(&mut self) -> Result<FileHashes> {
+        self.hash_until(None)
+    }
+
+    /// Hash the file, stopping with a checkpoint once `stop_at` bytes have been hashed
+    fn hash_until(&mut self, stop_at: Option<u64>) -> Result<FileHashes> {
+        if !self.subtree_len.is_power_of_two() || self.subtree_len < blake3::CHUNK_LEN as u64 {
+            return Err(BanyanError::Math(format!(
+                "The subtree length must be a power of two of at least {} bytes, not {}",
+                blake3::CHUNK_LEN,
+                self.subtree_len
+            )));
+        }
+        let mut file = File::open(&self.path)?;
+        let metadata = file.metadata()?;
+        let file_size = metadata.len();
+        self.resumed_from = 0;
+        // A single subtree has to be hashed as the root, so there's nothing to checkpoint
+        if file_size <= self.subtree_len {
+            return FileHasher::new(&file).hashes();
+        }
+
+        let mut checkpoint = match self.load_checkpoint(&mut file, file_size)? {
+            Some(checkpoint) => checkpoint,
+            None => Checkpoint {
+                file_size,
+                modified: None,
+                subtree_len: self.subtree_len,
+                sha256_state: SHA256_IV,
+                subtree_cvs: vec![],
+            },
+        };
+        checkpoint.file_size = file_size;
+        checkpoint.modified = modified(&metadata);
+        let mut offset = checkpoint.subtree_cvs.len() as u64 * self.subtree_len;
+        self.resumed_from = offset;
+        let mut sha256 = Sha256State::resume(checkpoint.sha256_state, offset);
+        let mut subtree_cvs = checkpoint
+            .subtree_cvs
+            .iter()
+            .map(|cv| decode_cv(cv))
+            .collect::<Result<Vec<_>>>()?;
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+        let mut since_checkpoint = 0;
+        while offset < file_size {
+            let len = self.subtree_len.min(file_size - offset);
+            subtree_cvs.push(hash_subtree(&mut reader, offset, len, Some(&mut sha256))?);
+            offset += len;
+            since_checkpoint += len;
+            let stopping = stop_at.is_some_and(|stop_at| offset >= stop_at);
+            if offset < file_size && (since_checkpoint >= self.checkpoint_interval || stopping) {
+                checkpoint.sha256_state = sha256.state;
+                checkpoint.subtree_cvs = subtree_cvs.iter().map(hex::encode).collect();
+                self.save_checkpoint(&checkpoint)?;
+                since_checkpoint = 0;
+                if stopping {
+                    return Err(BanyanError::Cancelled(format!(
+                        "Hashing stopped at byte {} of {}",
+                        offset, file_size
+                    )));
+                }
+            }
+        }
+
+        let multihash = Code::Sha2_256
+            .wrap(&sha256.finalize())
+            .map_err(|e| BanyanError::Parse(e.to_string()))?;
+        let hashes = FileHashes {
+            multihash,
+            blake3: root_of(&subtree_cvs)?,
+            size: file_size,
+        };
+        match fs::remove_file(&self.checkpoint_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(hashes),
+        }
+    }
+
+    /// Read the checkpoint, if there's one for this file whose prefix hasn't changed
+    fn load_checkpoint(&self, file: &mut File, file_size: u64) -> Result<Option<Checkpoint>> {
+        let checkpoint: Checkpoint = match fs::read(&self.checkpoint_path) {
+            // A checkpoint that can't be read is as good as no checkpoint
+            Ok(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(checkpoint) => checkpoint,
+                Err(_) => return Ok(None),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let covered = checkpoint.subtree_cvs.len() as u64 * checkpoint.subtree_len;
+        if checkpoint.subtree_len != self.subtree_len || covered >= file_size {
+            return Ok(None);
+        }
+        let unchanged = match self.prefix_check {
+            PrefixCheck::Metadata => {
+                checkpoint.file_size == file_size
+                    && checkpoint.modified.is_some()
+                    && checkpoint.modified == modified(&file.metadata()?)
+            }
+            PrefixCheck::Sample(samples) => {
+                let mut unchanged = true;
+                for index in sample_indices(checkpoint.subtree_cvs.len(), samples) {
+                    let offset = index as u64 * self.subtree_len;
+                    file.seek(SeekFrom::Start(offset))?;
+                    let cv = hash_subtree(
+                        &mut BufReader::new(&mut *file),
+                        offset,
+                        self.subtree_len,
+                        None,
+                    )?;
+                    if hex::encode(cv) != checkpoint.subtree_cvs[index] {
+                        unchanged = false;
+                        break;
+                    }
+                }
+                unchanged
+            }
+        };
+        Ok(unchanged.then_some(checkpoint))
+    }
+
+    /// Write the checkpoint, replacing the last one all at once. It's staged at the checkpoint's
+    /// path with `.partial` added, so it can't clobber a file that only differs by extension.
+    fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let mut partial = self.checkpoint_path.as_os_str().to_owned();
+        partial.push(".partial");
+        let json = serde_json::to_vec(checkpoint).map_err(|e| BanyanError::Parse(e.to_string()))?;
+        fs::write(&partial, json)?;
+        fs::rename(partial, &self.checkpoint_path)?;
+        Ok(())
+    }
+}
+
+/// hash_file_resumable - Hash a file, resuming from and updating a checkpoint next to it
+/// # Arguments
+/// * `path` - The file to hash. Its checkpoint is kept at the same path with `.checkpoint` added.
+/// # Errors
+/// * `BanyanError::Io` - If the file can't be read, or the checkpoint can't be written
+pub fn hash_file_resumable(path: &Path) -> Result<FileHashes> {
+    let mut checkpoint_path = path.as_os_str().to_owned();
+    checkpoint_path.push(".checkpoint");
+    ResumableHasher::new(path, checkpoint_path).hashes()
+}
+
+/// Read a subtree of `len` bytes starting at `offset`, returning its Blake3 chaining value and
+/// feeding it to `sha256` if there is one
+fn hash_subtree<R: Read>(
+    reader: &mut R,
+    offset: u64,
+    len: u64,
+    mut sha256: Option<&mut Sha256State>,
+) -> Result<ChainingValue> {
+    let mut hasher = B3Hasher::new();
+    hasher.set_input_offset(offset);
+    let mut buffer = vec![0; READ_CHUNK_SIZE];
+    let mut remaining = len;
+    while remaining > 0 {
+        let want = remaining.min(READ_CHUNK_SIZE as u64) as usize;
+        reader.read_exact(&mut buffer[..want])?;
+        hasher.update(&buffer[..want]);
+        if let Some(sha256) = sha256.as_mut() {
+            sha256.update(&buffer[..want]);
+        }
+        remaining -= want as u64;
+    }
+    Ok(hasher.finalize_non_root())
+}
+
+/// The Blake3 root of a run of equal subtrees (but for the last, which may be shorter).
+/// There must be at least two.
+fn root_of(subtree_cvs: &[ChainingValue]) -> Result<B3Hash> {
+    let (last, rest) = subtree_cvs
+        .split_last()
+        .ok_or_else(|| BanyanError::Math("There are no subtrees to merge".to_string()))?;
+    // Merge the subtrees into the same left-balanced tree Blake3 would, keeping the last apart
+    let mut stack: Vec<ChainingValue> = vec![];
+    for (i, cv) in rest.iter().enumerate() {
+        let (mut cv, mut subtrees) = (*cv, i + 1);
+        while subtrees & 1 == 0 {
+            let left = stack.pop().ok_or_else(unbalanced)?;
+            cv = merge_subtrees_non_root(&left, &cv, Mode::Hash);
+            subtrees >>= 1;
+        }
+        stack.push(cv);
+    }
+    let mut right = *last;
+    while let Some(left) = stack.pop() {
+        if stack.is_empty() {
+            return Ok(merge_subtrees_root(&left, &right, Mode::Hash));
+        }
+        right = merge_subtrees_non_root(&left, &right, Mode::Hash);
+    }
+    Err(unbalanced())
+}
+
+/// The error for a tree we can't merge
+fn unbalanced() -> BanyanError {
+    BanyanError::Math("Too few subtrees to merge into a root".to_string())
+}
+
+/// Parse a hex chaining value from a checkpoint
+fn decode_cv(cv: &str) -> Result<ChainingValue> {
+    hex::decode(cv)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| BanyanError::Parse(format!("Invalid chaining value in checkpoint: {}", cv)))
+}
+
+/// When a file was last modified, if the platform says
+fn modified(metadata: &fs::Metadata) -> Option<(u64, u32)> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+/// Pick up to `samples` distinct indices below `count`, differently every time
+fn sample_indices(count: usize, samples: usize) -> Vec<usize> {
+    if samples >= count {
+        return (0..count).collect();
+    }
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    let mut indices = vec![];
+    let mut draw = 0u64;
+    while indices.len() < samples {
+        let hash = blake3::hash(&[seed.to_le_bytes(), (draw as u128).to_le_bytes()].concat());
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hash.as_bytes()[..8]);
+        let index = (u64::from_le_bytes(bytes) % count as u64) as usize;
+        if !indices.contains(&index) {
+            indices.push(index);
+        }
+        draw += 1;
+    }
+    indices
+}
+
+/// Sha2-256 whose state can be saved and restored at a block boundary
+struct Sha256State {
+    /// The state after every whole block so far
+    state: [u32; 8],
+    /// The number of bytes fed in so far
+    len: u64,
+    /// Bytes fed in that don't make a whole block yet
+    buffer: Vec<u8>,
+}
+
+impl Sha256State {
+    /// Pick up from `state`, after `len` bytes. `len` must be a whole number of blocks.
+    fn resume(state: [u32; 8], len: u64) -> Self {
+        Self {
+            state,
+            len,
+            buffer: Vec::with_capacity(64),
+        }
+    }
+
+    /// Feed the next piece of the data in
+    fn update(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        if !self.buffer.is_empty() {
+            let take = (64 - self.buffer.len()).min(bytes.len());
+            self.buffer.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+            if self.buffer.len() < 64 {
+                return;
+            }
+            compress(&mut self.state, &self.buffer);
+            self.buffer.clear();
+        }
+        let whole = bytes.len() - bytes.len() % 64;
+        compress(&mut self.state, &bytes[..whole]);
+        self.buffer.extend_from_slice(&bytes[whole..]);
+    }
+
+    /// The digest of everything fed in
+    fn finalize(mut self) -> [u8; 32] {
+        let bit_len = self.len.wrapping_mul(8);
+        self.buffer.push(0x80);
+        while self.buffer.len() % 64 != 56 {
+            self.buffer.push(0);
+        }
+        self.buffer.extend_from_slice(&bit_len.to_be_bytes());
+        let buffer = std::mem::take(&mut self.buffer);
+        compress(&mut self.state, &buffer);
+        let mut digest = [0; 32];
+        for (word, out) in self.state.iter().zip(digest.chunks_mut(4)) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+/// Run the Sha2-256 compression function over whole blocks
+fn compress(state: &mut [u32; 8], blocks: &[u8]) {
+    for block in blocks.chunks_exact(64) {
+        sha2::compress256(state, std::slice::from_ref(GenericArray::from_slice(block)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Write a file of `len` bytes that aren't all the same
+    fn test_file(dir: &Path, len: usize) -> PathBuf {
+        let path = dir.join("file");
+        let data: Vec<u8> = (0..len).map(|i| (i * 7 % 253) as u8).collect();
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        path
+    }
+
+    #[test]
+    fn same_hashes_as_one_pass() {
+        let subtree_len = 4096;
+        let dir = tempfile::tempdir().unwrap();
+        for len in [
+            0,
+            100,
+            4096,
+            4097,
+            8192,
+            3 * 4096 + 5,
+            8 * 4096,
+            10 * 4096 + 123,
+        ] {
+            let path = test_file(dir.path(), len);
+            let expected = FileHasher::new(File::open(&path).unwrap())
+                .hashes()
+                .unwrap();
+            let hashes = ResumableHasher::new(&path, path.with_extension("checkpoint"))
+                .with_subtree_len(subtree_len)
+                .hashes()
+                .unwrap();
+            assert_eq!(hashes, expected, "for a file of {} bytes", len);
+        }
+        let mut sha256 = Sha256State::resume(SHA256_IV, 0);
+        for piece in [&b"ab"[..], &[1; 100], &[2; 64], b"c"] {
+            sha256.update(piece);
+        }
+        let bytes = [&b"ab"[..], &[1; 100], &[2; 64], b"c"].concat();
+        assert_eq!(
+            Code::Sha2_256.wrap(&sha256.finalize()).unwrap(),
+            Code::Sha2_256.digest(&bytes)
+        );
+        assert!(ResumableHasher::new("Cargo.toml", "unused")
+            .with_subtree_len(3000)
+            .hashes()
+            .is_err());
+    }
+
+    #[test]
+    fn resume_after_an_interruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_file(dir.path(), 10 * 4096 + 123);
+        let checkpoint_path = path.with_extension("checkpoint");
+        let expected = FileHasher::new(File::open(&path).unwrap())
+            .hashes()
+            .unwrap();
+        let hasher = || {
+            ResumableHasher::new(&path, &checkpoint_path)
+                .with_subtree_len(4096)
+                .with_checkpoint_interval(2 * 4096)
+        };
+
+        // Stopping leaves a checkpoint, which the next run picks up from
+        let mut stopped = hasher();
+        assert!(matches!(
+            stopped.hash_until(Some(5 * 4096)),
+            Err(BanyanError::Cancelled(_))
+        ));
+        let mut resumed = hasher();
+        assert_eq!(resumed.hashes().unwrap(), expected);
+        assert_eq!(resumed.resumed_from(), 5 * 4096);
+        assert!(!checkpoint_path.exists());
+
+        // If the prefix was changed, the hash starts over
+        let _ = hasher().hash_until(Some(6 * 4096));
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all(b"changed").unwrap();
+        drop(file);
+        let expected = FileHasher::new(File::open(&path).unwrap())
+            .hashes()
+            .unwrap();
+        let mut sampled = hasher().with_prefix_check(PrefixCheck::Sample(100));
+        assert_eq!(sampled.hashes().unwrap(), expected);
+        assert_eq!(sampled.resumed_from(), 0);
+
+        // But a sampled check lets an unchanged prefix through
+        let _ = hasher().hash_until(Some(6 * 4096));
+        let mut sampled = hasher().with_prefix_check(PrefixCheck::Sample(2));
+        assert_eq!(sampled.hashes().unwrap(), expected);
+        assert_eq!(sampled.resumed_from(), 6 * 4096);
+
+        // Saving a checkpoint leaves files that only differ by extension alone
+        let neighbour = path.with_extension("partial");
+        fs::write(&neighbour, b"not a checkpoint").unwrap();
+        let _ = hasher().hash_until(Some(4 * 4096));
+        assert!(checkpoint_path.exists());
+        assert_eq!(fs::read(&neighbour).unwrap(), b"not a checkpoint");
+    }
+}
@@ -0,0 +1,516 @@
+use crate::{
+    error::{BanyanError, Result},
+    types::{DealStatus, OnChainDealInfo},
+};
+use ethers::{
+    abi::{self, Abi, InvalidOutputType, ParamType, Token, Tokenizable},
+    types::{Bytes, U256},
+};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/*
+ * A registry of the Banyan Contract deployments this crate knows how to talk to.
+ * Status: Every deployment so far predates `version()`, so they're told apart by the function
+ * selectors in their bytecode, and "0.1.0", "0.2.0" and "0.3.0" are our own labels for them, not
+ * anything a contract reports. Deployments that report a version are looked up by it, so they
+ * must be registered under exactly the string `version()` returns.
+ * IMPORTANT: Add an entry here (and a layout, if getOffer changed) whenever the contract is updated.
+ */
+
+lazy_static! {
+    /// Every contract version we support, oldest first
+    pub static ref CONTRACT_VERSIONS: Vec<ContractVersion> = vec![
+        ContractVersion::new(
+            "0.1.0",
+            include_str!("../abi/test.json"),
+            DealInfoLayout::IdFirst,
+        ),
+        // Contract Address: 0x7Da936F4A55D5044e1838Cc959935085662392F1
+        ContractVersion::new(
+            "0.2.0",
+            include_str!("../abi/jonah_test.json"),
+            DealInfoLayout::StatusLast,
+        ),
+        ContractVersion::new(
+            "0.3.0",
+            include_str!("../abi/Escrow.json"),
+            DealInfoLayout::StatusLast,
+        ),
+    ];
+}
+
+/// The contract version an EthClient assumes until it has asked the chain
+pub fn default_contract_version() -> &'static ContractVersion {
+    find_contract_version("0.2.0").expect("The default contract version must be registered")
+}
+
+/// find_contract_version - Look up a contract version by the string its `version()` returns
+/// # Arguments
+/// * `version` - The version reported by the deployed contract
+/// # Returns
+/// * `ContractVersion` - The matching registry entry, or an error naming the versions we support
+pub fn find_contract_version(version: &str) -> Result<&'static ContractVersion> {
+    CONTRACT_VERSIONS
+        .iter()
+        .find(|v| v.version == version)
+        .ok_or_else(|| {
+            BanyanError::Chain(format!(
+                "Unsupported Banyan contract version {:?}. Supported versions: {}",
+                version,
+                supported_versions()
+            ))
+        })
+}
+
+/// detect_contract_version_from_code - Work out which version a deployment is from its bytecode.
+/// A version matches if every one of its functions has a selector in the code. If several
+/// match, the one with the most functions wins, since older ABIs are mostly subsets of newer ones.
+/// # Arguments
+/// * `code` - The deployed (runtime) bytecode of the contract
+pub fn detect_contract_version_from_code(code: &[u8]) -> Result<&'static ContractVersion> {
+    if code.is_empty() {
+        return Err(BanyanError::Chain(
+            "No contract is deployed at this address".to_string(),
+        ));
+    }
+    CONTRACT_VERSIONS
+        .iter()
+        .filter(|v| v.matches_code(code))
+        .max_by_key(|v| v.abi.functions().count())
+        .ok_or_else(|| {
+            BanyanError::Chain(format!(
+                "The deployed contract doesn't match any known Banyan contract version. Supported versions: {}",
+                supported_versions()
+            ))
+        })
+}
+
+/// A comma separated list of the versions in the registry, for error messages
+fn supported_versions() -> String {
+    CONTRACT_VERSIONS
+        .iter()
+        .map(|v| v.version)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// ContractVersion - Everything that differs between deployments of the Banyan Contract
+#[derive(Debug, Clone)]
+pub struct ContractVersion {
+    /// The version string of the deployment: what its `version()` returns, or for deployments
+    /// without one, a label of our own
+    pub version: &'static str,
+    /// The ABI of the deployment
+    pub abi: Abi,
+    /// How the deployment lays out the deal info returned by `getOffer`
+    pub deal_info_layout: DealInfoLayout,
+}
+
+impl ContractVersion {
+    /// Create a new registry entry
+    /// # Arguments
+    /// * `version` - The version string of the deployment
+    /// * `abi_json` - The deployment's ABI, either bare or as a Hardhat artifact
+    /// * `deal_info_layout` - How the deployment lays out the deal info returned by `getOffer`
+    /// # Panics
+    /// * If the ABI can't be parsed
+    fn new(version: &'static str, abi_json: &str, deal_info_layout: DealInfoLayout) -> Self {
+        let mut json: Value = serde_json::from_str(abi_json).expect("Failed to parse ABI");
+        // Hardhat artifacts keep the ABI under the `abi` key
+        if let Some(abi) = json.get_mut("abi") {
+            json = abi.take();
+        }
+        ContractVersion {
+            version,
+            abi: serde_json::from_value(json).expect("Failed to parse ABI"),
+            deal_info_layout,
+        }
+    }
+
+    /// Whether the selector of every function in our ABI appears in the given bytecode
+    pub fn matches_code(&self, code: &[u8]) -> bool {
+        self.abi
+            .functions()
+            .all(|f| code.windows(4).any(|w| w == f.short_signature()))
+    }
+
+    /// decode_revert - Work out why the contract rejected a call
+    /// # Arguments
+    /// * `calldata` - The ABI encoded call that reverted
+    /// * `revert_data` - What the contract reverted with
+    /// # Returns
+    /// * `ContractError` - The decoded revert, with the function and arguments that caused it
+    pub fn decode_revert(&self, calldata: &[u8], revert_data: &[u8]) -> ContractError {
+        let call = self.decode_call(calldata);
+        let (selector, data) = match revert_data {
+            [a, b, c, d, data @ ..] => ([*a, *b, *c, *d], data),
+            _ => {
+                return ContractError::Unknown {
+                    call,
+                    data: revert_data.to_vec().into(),
+                }
+            }
+        };
+        if selector == ethers::utils::id("Error(string)") {
+            if let Ok(Some(Token::String(reason))) =
+                abi::decode(&[ParamType::String], data).map(|mut t| t.pop())
+            {
+                return ContractError::Reverted { call, reason };
+            }
+        }
+        if selector == ethers::utils::id("Panic(uint256)") {
+            if let Ok(Some(Token::Uint(code))) =
+                abi::decode(&[ParamType::Uint(256)], data).map(|mut t| t.pop())
+            {
+                return ContractError::Panicked { call, code };
+            }
+        }
+        let custom = self
+            .abi
+            .errors()
+            .find(|e| e.signature()[..4] == selector)
+            .and_then(|e| Some((e.name.clone(), e.decode(data).ok()?)));
+        match custom {
+            Some((name, args)) => ContractError::Custom { call, name, args },
+            None => ContractError::Unknown {
+                call,
+                data: revert_data.to_vec().into(),
+            },
+        }
+    }
+
+    /// Work out which function some calldata calls, and with what
+    fn decode_call(&self, calldata: &[u8]) -> FailedCall {
+        let function = self
+            .abi
+            .functions()
+            .find(|f| calldata.len() >= 4 && calldata[..4] == f.short_signature());
+        match function {
+            Some(f) => FailedCall {
+                function: f.name.clone(),
+                args: f.decode_input(&calldata[4..]).unwrap_or_default(),
+            },
+            None => FailedCall {
+                function: format!("0x{}", hex(&calldata[..calldata.len().min(4)])),
+                args: vec![],
+            },
+        }
+    }
+
+    /// Whether `saveProof` takes the target block as an argument.
+    /// Newer contracts derive the target block on chain.
+    pub fn proof_takes_target_block(&self) -> bool {
+        self.abi
+            .function("saveProof")
+            .map(|f| f.inputs.len() == 3)
+            .unwrap_or(false)
+    }
+
+    /// Whether `requestVerification` takes the block to verify up to as an argument.
+    /// Newer contracts verify up to the block the request is mined in.
+    pub fn verification_takes_block(&self) -> bool {
+        self.abi
+            .function("requestVerification")
+            .map(|f| f.inputs.len() == 3)
+            .unwrap_or(false)
+    }
+}
+
+/// FailedCall - A call to the Banyan Contract that reverted
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedCall {
+    /// The name of the function that was called
+    pub function: String,
+    /// The arguments it was called with
+    pub args: Vec<Token>,
+}
+
+impl Display for FailedCall {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let args = self.args.iter().map(display_token).collect::<Vec<_>>();
+        write!(f, "{}({})", self.function, args.join(", "))
+    }
+}
+
+/// ContractError - Why the Banyan Contract rejected a call
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractError {
+    /// A `require` or `revert` with a reason - `Error(string)`
+    Reverted { call: FailedCall, reason: String },
+    /// One of the custom errors in the contract's ABI, such as `UNAUTHORIZED()`
+    Custom {
+        call: FailedCall,
+        name: String,
+        args: Vec<Token>,
+    },
+    /// A failed assertion, arithmetic overflow and the like - `Panic(uint256)`
+    Panicked { call: FailedCall, code: U256 },
+    /// A revert we can't decode, including ones without any data
+    Unknown { call: FailedCall, data: Bytes },
+}
+
+impl ContractError {
+    /// The call that reverted
+    pub fn call(&self) -> &FailedCall {
+        match self {
+            ContractError::Reverted { call, .. }
+            | ContractError::Custom { call, .. }
+            | ContractError::Panicked { call, .. }
+            | ContractError::Unknown { call, .. } => call,
+        }
+    }
+
+    /// Whether the caller isn't allowed to make the call - the contract's `UNAUTHORIZED()` error
+    pub fn is_unauthorized(&self) -> bool {
+        matches!(self, ContractError::Custom { name, .. } if name == "UNAUTHORIZED")
+    }
+}
+
+impl Display for ContractError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            ContractError::Reverted { call, reason } => write!(f, "{} reverted: {}", call, reason),
+            ContractError::Custom { call, name, args } => {
+                let args = args.iter().map(display_token).collect::<Vec<_>>();
+                write!(f, "{} reverted with {}({})", call, name, args.join(", "))
+            }
+            ContractError::Panicked { call, code } => {
+                write!(f, "{} panicked with code {:#04x}", call, code)
+            }
+            ContractError::Unknown { call, data } if data.is_empty() => {
+                write!(f, "{} reverted without a reason", call)
+            }
+            ContractError::Unknown { call, data } => {
+                write!(f, "{} reverted with 0x{}", call, hex(data))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ContractError {}
+
+/// How many bytes of a `bytes` argument to show in error messages. Proofs can be kilobytes long.
+const MAX_DISPLAYED_BYTES: usize = 32;
+
+/// Render an ABI token the way it would be written in Solidity
+fn display_token(token: &Token) -> String {
+    match token {
+        Token::Address(address) => format!("{:?}", address),
+        Token::Uint(n) | Token::Int(n) => n.to_string(),
+        Token::Bool(b) => b.to_string(),
+        Token::String(s) => format!("{:?}", s),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) if bytes.len() > MAX_DISPLAYED_BYTES => {
+            format!(
+                "0x{}... ({} bytes)",
+                hex(&bytes[..MAX_DISPLAYED_BYTES]),
+                bytes.len()
+            )
+        }
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", hex(bytes)),
+        Token::Array(tokens) | Token::FixedArray(tokens) => {
+            let tokens = tokens.iter().map(display_token).collect::<Vec<_>>();
+            format!("[{}]", tokens.join(", "))
+        }
+        Token::Tuple(tokens) => {
+            let tokens = tokens.iter().map(display_token).collect::<Vec<_>>();
+            format!("({})", tokens.join(", "))
+        }
+    }
+}
+
+/// Render bytes as lowercase hex
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// DealInfoLayout - The shapes of the tuple returned by `getOffer` over the contract's history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DealInfoLayout {
+    /// `(id, start, length, frequency, price, collateral, token, cid, size, blake3, creator, executor)`.
+    /// These deployments don't track a status, so deals read back as `DealStatus::Non`.
+    IdFirst,
+    /// `(start, length, frequency, price, collateral, token, cid, size, blake3, creator, executor, status)`
+    StatusLast,
+}
+
+impl DealInfoLayout {
+    /// Whether deployments with this layout track a deal's status
+    pub fn tracks_status(&self) -> bool {
+        matches!(self, DealInfoLayout::StatusLast)
+    }
+
+    /// Decode the output of `getOffer` into an OnChainDealInfo
+    /// # Arguments
+    /// * `token` - The output of `getOffer`, as a Token::Tuple
+    pub fn decode(&self, token: Token) -> Result<OnChainDealInfo, InvalidOutputType> {
+        match (self, token) {
+            (DealInfoLayout::StatusLast, token) => OnChainDealInfo::from_token(token),
+            (DealInfoLayout::IdFirst, Token::Tuple(mut tokens)) if tokens.len() == 12 => {
+                tokens.remove(0);
+                tokens.push(DealStatus::Non.into_token());
+                OnChainDealInfo::from_token(Token::Tuple(tokens))
+            }
+            (DealInfoLayout::IdFirst, other) => Err(InvalidOutputType(format!(
+                "Expected a `Tuple` of 12 tokens, got {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::*;
+    use cid::Cid;
+    use ethers::types::{Address, U256};
+    use multihash::{Code, MultihashDigest};
+
+    #[test]
+    /// Every registered ABI parses and has the functions an EthClient relies on
+    fn registry_abis_parse() {
+        for version in CONTRACT_VERSIONS.iter() {
+            assert!(version.abi.function("startOffer").is_ok());
+            assert!(version.abi.function("getOffer").is_ok());
+        }
+        assert_eq!(default_contract_version().version, "0.2.0");
+        assert!(default_contract_version().proof_takes_target_block());
+        assert!(!find_contract_version("0.3.0")
+            .unwrap()
+            .proof_takes_target_block());
+        assert!(default_contract_version().verification_takes_block());
+        assert!(!find_contract_version("0.3.0")
+            .unwrap()
+            .verification_takes_block());
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let err = find_contract_version("9.9.9").unwrap_err().to_string();
+        assert!(err.contains("9.9.9"));
+        assert!(err.contains("0.1.0, 0.2.0, 0.3.0"));
+        assert!(detect_contract_version_from_code(&[]).is_err());
+        assert!(detect_contract_version_from_code(&[0x60, 0x80, 0x60, 0x40]).is_err());
+    }
+
+    #[test]
+    /// Bytecode containing every selector of a newer ABI picks the newer version
+    fn detect_from_selectors() {
+        let code_for = |version: &str| -> Vec<u8> {
+            find_contract_version(version)
+                .unwrap()
+                .abi
+                .functions()
+                .flat_map(|f| {
+                    let mut push = vec![0x63];
+                    push.extend_from_slice(&f.short_signature());
+                    push
+                })
+                .collect()
+        };
+        for version in ["0.2.0", "0.3.0"] {
+            assert_eq!(
+                detect_contract_version_from_code(&code_for(version))
+                    .unwrap()
+                    .version,
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn decode_reverts() {
+        let version = find_contract_version("0.3.0").unwrap();
+        let calldata = version
+            .abi
+            .function("complete")
+            .unwrap()
+            .encode_input(&[Token::Uint(U256::from(7)), Token::Uint(U256::from(80))])
+            .unwrap();
+
+        let mut revert = ethers::utils::id("Error(string)").to_vec();
+        revert.extend(abi::encode(&[Token::String(
+            "Not yet deal end time".to_string(),
+        )]));
+        let err = version.decode_revert(&calldata, &revert);
+        assert_eq!(
+            err,
+            ContractError::Reverted {
+                call: FailedCall {
+                    function: "complete".to_string(),
+                    args: vec![Token::Uint(U256::from(7)), Token::Uint(U256::from(80))],
+                },
+                reason: "Not yet deal end time".to_string(),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "complete(7, 80) reverted: Not yet deal end time"
+        );
+
+        let err = version.decode_revert(&calldata, &[0x07, 0x5f, 0xd2, 0xb1]);
+        assert!(err.is_unauthorized());
+        assert_eq!(
+            err.to_string(),
+            "complete(7, 80) reverted with UNAUTHORIZED()"
+        );
+
+        let mut panic = ethers::utils::id("Panic(uint256)").to_vec();
+        panic.extend(abi::encode(&[Token::Uint(U256::from(0x11))]));
+        assert_eq!(
+            version.decode_revert(&calldata, &panic).to_string(),
+            "complete(7, 80) panicked with code 0x11"
+        );
+
+        let err = version.decode_revert(&[1, 2, 3, 4], &[]);
+        assert_eq!(err.call().function, "0x01020304");
+        assert_eq!(err.to_string(), "0x01020304() reverted without a reason");
+    }
+
+    #[test]
+    fn decode_layouts() {
+        let info = OnChainDealInfo {
+            deal_start_block: BlockNum(10),
+            deal_length_in_blocks: BlockNum(100),
+            proof_frequency_in_blocks: BlockNum(5),
+            price: U256::from(1),
+            collateral: U256::from(2),
+            erc20_token_denomination: Address::repeat_byte(1),
+            ipfs_file_cid: CidWrapper(Cid::new_v1(0x55, Code::Sha2_256.digest(b"banyan"))),
+            file_size: U256::from(1024),
+            blake3_checksum: Blake3Hash(blake3::hash(b"banyan")),
+            creator_address: Address::repeat_byte(2),
+            executor_address: Address::repeat_byte(3),
+            deal_status: DealStatus::DealActive,
+        };
+        let token = info.clone().into_token();
+        assert_eq!(
+            DealInfoLayout::StatusLast.decode(token.clone()).unwrap(),
+            info
+        );
+
+        // The old layout leads with the id and has no status
+        let mut tokens = match token {
+            Token::Tuple(tokens) => tokens,
+            _ => unreachable!(),
+        };
+        tokens.pop();
+        tokens.insert(0, DealID(7).into_token());
+        let decoded = DealInfoLayout::IdFirst
+            .decode(Token::Tuple(tokens.clone()))
+            .unwrap();
+        assert_eq!(
+            decoded,
+            OnChainDealInfo {
+                deal_status: DealStatus::Non,
+                ..info
+            }
+        );
+        tokens.pop();
+        assert!(DealInfoLayout::IdFirst
+            .decode(Token::Tuple(tokens))
+            .is_err());
+    }
+}
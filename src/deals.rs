@@ -1,17 +1,82 @@
 use crate::{
-    hash::FileHasher,
-    types::{Blake3Hash, BlockNum, CidWrapper, DealProposal, TokenMultiplier},
+    error::{BanyanError, Result},
+    hash::{
+        hash_file, AsyncFileHasher, FileHasher, FileHashes, HashFunction, HashStrategy,
+        PieceCommitment,
+    },
+    manifest::DealManifest,
+    pricing::{DealPricing, PricingModel, DEFAULT_SECONDS_PER_BLOCK},
+    token::{Rounding, TokenAmount},
+    types::{Blake3Hash, BlockNum, CidCodec, CidWrapper, DealProposal},
 };
-use anyhow::{Error, Result};
-use cid::Cid;
+use cid::{Cid, Version};
 use ethers::types::{Address, U256};
+use std::{
+    fs::{File, OpenOptions},
+    io::Read,
+    path::PathBuf,
+};
+use tokio::io::AsyncRead;
 
 /* Implements the deal proposal struct. */
 
+/// DealInput - The data a deal is built for, and where to read it from
+pub enum DealInput {
+    /// An open file
+    File(std::fs::File),
+    /// A file to open when the deal is built
+    Path(PathBuf),
+    /// Anything that can be read, e.g. a buffer in memory
+    Reader(Box<dyn Read + Send>),
+    /// Anything that can be read asynchronously, e.g. a `tokio::fs::File` or a network stream.
+    /// Only `DealProposalBuilder::build_async` can build a deal for this.
+    AsyncReader(Box<dyn AsyncRead + Send + Unpin>),
+    /// Data already on IPFS, whose hashes are known so nothing needs to be read
+    Cid {
+        /// The CID of the data
+        cid: Cid,
+        /// The size of the data in bytes
+        size: u64,
+        /// The Blake3 hash of the data, which proofs are checked against
+        blake3_checksum: Blake3Hash,
+    },
+}
+
 impl DealProposal {
     pub fn builder() -> DealProposalBuilder {
         DealProposalBuilder::default()
     }
+
+    /// with_piece_commitment - Attach the Filecoin piece commitment of the deal's data, e.g. from
+    /// `hash::piece_commitment`, so Filecoin deals for the data can be checked against it
+    /// # Errors
+    /// * `BanyanError::Parse` - If the piece was made from a payload of a different size
+    pub fn with_piece_commitment(mut self, piece_commitment: PieceCommitment) -> Result<Self> {
+        if U256::from(piece_commitment.payload_size) != self.file_size {
+            return Err(BanyanError::Parse(format!(
+                "Piece {} was made from {} bytes, but the deal is for {}",
+                piece_commitment.piece_cid, piece_commitment.payload_size, self.file_size
+            )));
+        }
+        self.piece_commitment = Some(piece_commitment);
+        Ok(self)
+    }
+}
+
+/// PreparedDeal - A deal proposal, along with what's needed to prove it later
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparedDeal {
+    /// The proposal, ready for `propose_deal`
+    pub proposal: DealProposal,
+    /// Where the bao outboard tree of the data was written, for `gen_proof`
+    pub obao_path: PathBuf,
+}
+
+impl PreparedDeal {
+    /// Open the bao outboard tree of the data, e.g. to pass to `gen_proof`
+    pub fn open_obao(&self) -> Result<File> {
+        Ok(File::open(&self.obao_path)?)
+    }
 }
 
 /// DealProposalBuilder - A builder for a deal proposal
@@ -23,14 +88,26 @@ pub struct DealProposalBuilder {
     pub deal_length_in_blocks: u64,
     /// The frequency of proofs to be submitted, as an int
     pub proof_frequency_in_blocks: u64,
-    /// The amount of tokens to be paid to the executor per TiB, as a float
-    pub price_per_tib: f64,
-    /// The amount of tokens in collateral the executor must provide per TiB, as a float
-    pub collateral_per_tib: f64,
+    /// How the amount of tokens to be paid to the executor is charged
+    pub pricing: PricingModel,
+    /// How the amount of tokens in collateral the executor must provide is charged
+    pub collateral_pricing: PricingModel,
+    /// The chain's block time, to estimate the length of the deal in days for daily rates
+    pub seconds_per_block: u64,
+    /// How to round the price and collateral to the token's smallest unit
+    pub rounding: Rounding,
     /// The Address of the token to use as denominator for the price and collateral, as a string
     pub erc20_token_denomination: String,
-    /// The Handle for the file to build a deal for
-    pub file: Option<std::fs::File>,
+    /// The data to build a deal for
+    pub input: Option<DealInput>,
+    /// Whether to hash a file input in parallel
+    pub hash_strategy: HashStrategy,
+    /// The hash function the CID of the data is made with
+    pub hash_function: HashFunction,
+    /// The version of the CID of the data
+    pub cid_version: Version,
+    /// The codec of the CID of the data
+    pub cid_codec: CidCodec,
 }
 impl Default for DealProposalBuilder {
     fn default() -> Self {
@@ -39,10 +116,16 @@ impl Default for DealProposalBuilder {
             deal_length_in_blocks: 10,
             /// TODO: Call API to get a good value
             proof_frequency_in_blocks: 5,
-            price_per_tib: 0.0,
-            collateral_per_tib: 0.0,
+            pricing: PricingModel::default(),
+            collateral_pricing: PricingModel::default(),
+            seconds_per_block: DEFAULT_SECONDS_PER_BLOCK,
+            rounding: Rounding::default(),
             erc20_token_denomination: "0x0000000000000000000000000000000000000000".to_string(),
-            file: None,
+            input: None,
+            hash_strategy: HashStrategy::default(),
+            hash_function: HashFunction::default(),
+            cid_version: Version::V1,
+            cid_codec: CidCodec::default(),
         }
     }
 }
@@ -53,8 +136,8 @@ impl DealProposalBuilder {
     /// * `executor_address` - The address of the executor to to propose the deal to, as a string
     /// * `deal_length_in_blocks` - The length of the deal in blocks, as an int
     /// * `proof_frequency_in_blocks` - The frequency of proofs to be submitted, as an int
-    /// * `price_per_tib` - The amount of tokens to be paid to the executor per TiB, as a float
-    /// * `collateral_per_tib` - The amount of tokens in collateral the executor must provide per TiB, as a float
+    /// * `price_per_tib` - The amount of tokens to be paid to the executor per TiB
+    /// * `collateral_per_tib` - The amount of tokens in collateral the executor must provide per TiB
     /// * `erc20_token_denomination` - The Address of the token to use as denominator for the price and collateral, as a string
     /// # Returns
     /// * `DealProposalBuilder` - A DealProposalBuilder struct
@@ -64,31 +147,74 @@ impl DealProposalBuilder {
         executor_address: String,
         deal_length_in_blocks: u64,
         proof_frequency_in_blocks: u64,
-        price_per_tib: f64,
-        collateral_per_tib: f64,
+        price_per_tib: TokenAmount,
+        collateral_per_tib: TokenAmount,
         erc20_token_denomination: String,
     ) -> DealProposalBuilder {
         DealProposalBuilder {
             executor_address,
             deal_length_in_blocks,
             proof_frequency_in_blocks,
-            price_per_tib,
-            collateral_per_tib,
+            pricing: PricingModel::PerTib(price_per_tib),
+            collateral_pricing: PricingModel::PerTib(collateral_per_tib),
+            seconds_per_block: DEFAULT_SECONDS_PER_BLOCK,
+            rounding: Rounding::default(),
             erc20_token_denomination,
-            file: None,
+            input: None,
+            hash_strategy: HashStrategy::default(),
+            hash_function: HashFunction::default(),
+            cid_version: Version::V1,
+            cid_codec: CidCodec::default(),
         }
     }
 
     /* Builder Populators */
 
     /// Set the file handle for the DealProposalBuilder
-    /// This is required to build a DealProposal
+    /// This, or another input, is required to build a DealProposal
     /// # Arguments
     /// * `file_handle` - A file handle to the file to build a deal for
     /// # Returns
     /// * `DealProposalBuilder` - A DealProposalBuilder struct
-    pub fn with_file(mut self, file: std::fs::File) -> DealProposalBuilder {
-        self.file = Some(file);
+    pub fn with_file(self, file: std::fs::File) -> DealProposalBuilder {
+        self.with_input(DealInput::File(file))
+    }
+
+    /// Set the path of the file to build a deal for. It's opened when the deal is built.
+    pub fn with_path(self, path: impl Into<PathBuf>) -> DealProposalBuilder {
+        self.with_input(DealInput::Path(path.into()))
+    }
+
+    /// Set a reader to build a deal for the contents of, e.g. a `&[u8]` or `Cursor<Vec<u8>>`
+    pub fn with_reader(self, reader: impl Read + Send + 'static) -> DealProposalBuilder {
+        self.with_input(DealInput::Reader(Box::new(reader)))
+    }
+
+    /// Set an async reader to build a deal for the contents of. Build with `build_async`.
+    pub fn with_async_reader(
+        self,
+        reader: impl AsyncRead + Send + Unpin + 'static,
+    ) -> DealProposalBuilder {
+        self.with_input(DealInput::AsyncReader(Box::new(reader)))
+    }
+
+    /// Build a deal for data already on IPFS, without reading it
+    /// # Arguments
+    /// * `cid` - The CID of the data
+    /// * `size` - The size of the data in bytes
+    /// * `blake3_checksum` - The Blake3 hash of the data. The CID doesn't tell us this, and proofs
+    ///   are checked against it.
+    pub fn with_cid(self, cid: Cid, size: u64, blake3_checksum: Blake3Hash) -> DealProposalBuilder {
+        self.with_input(DealInput::Cid {
+            cid,
+            size,
+            blake3_checksum,
+        })
+    }
+
+    /// Set the data to build a deal for
+    pub fn with_input(mut self, input: DealInput) -> DealProposalBuilder {
+        self.input = Some(input);
         self
     }
 
@@ -113,15 +239,66 @@ impl DealProposalBuilder {
         self
     }
 
-    /// Set the Price Per TiB for the DealProposalBuilder
-    pub fn with_price_per_tib(mut self, price_per_tib: f64) -> DealProposalBuilder {
-        self.price_per_tib = price_per_tib;
+    /// Set the Price Per TiB for the DealProposalBuilder, whatever the length of the deal
+    pub fn with_price_per_tib(mut self, price_per_tib: TokenAmount) -> DealProposalBuilder {
+        self.pricing = PricingModel::PerTib(price_per_tib);
+        self
+    }
+
+    /// Set the Pricing Model for the DealProposalBuilder
+    pub fn with_pricing(mut self, pricing: PricingModel) -> DealProposalBuilder {
+        self.pricing = pricing;
         self
     }
 
     /// Set the Collateral Per TiB for the DealProposalBuilder
-    pub fn with_collateral_per_tib(mut self, collateral_per_tib: f64) -> DealProposalBuilder {
-        self.collateral_per_tib = collateral_per_tib;
+    pub fn with_collateral_per_tib(
+        mut self,
+        collateral_per_tib: TokenAmount,
+    ) -> DealProposalBuilder {
+        self.collateral_pricing = PricingModel::PerTib(collateral_per_tib);
+        self
+    }
+
+    /// Set the Collateral Pricing Model for the DealProposalBuilder
+    pub fn with_collateral_pricing(
+        mut self,
+        collateral_pricing: PricingModel,
+    ) -> DealProposalBuilder {
+        self.collateral_pricing = collateral_pricing;
+        self
+    }
+
+    /// Set the chain's block time, used to estimate the length of the deal in days
+    pub fn with_seconds_per_block(mut self, seconds_per_block: u64) -> DealProposalBuilder {
+        self.seconds_per_block = seconds_per_block;
+        self
+    }
+
+    /// Set how the price and collateral are rounded to the token's smallest unit
+    pub fn with_rounding(mut self, rounding: Rounding) -> DealProposalBuilder {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Set whether a file or path input is hashed in parallel. By default, big files are when
+    /// the `parallel-hash` feature is on, which memory-maps them: see `HashStrategy::Parallel`.
+    pub fn with_hash_strategy(mut self, hash_strategy: HashStrategy) -> DealProposalBuilder {
+        self.hash_strategy = hash_strategy;
+        self
+    }
+
+    /// Set the hash function the CID of the data is made with. Sha2-256 by default.
+    pub fn with_hash_function(mut self, hash_function: HashFunction) -> DealProposalBuilder {
+        self.hash_function = hash_function;
+        self
+    }
+
+    /// Set the version and codec of the CID of the data. CIDv1 of the raw data by default.
+    /// CIDv0 can only be dag-pb over Sha2-256, and anything else fails to build.
+    pub fn with_cid_format(mut self, version: Version, codec: CidCodec) -> DealProposalBuilder {
+        self.cid_version = version;
+        self.cid_codec = codec;
         self
     }
 
@@ -134,51 +311,192 @@ impl DealProposalBuilder {
         self
     }
 
+    /// Configure a DealProposalBuilder to rebuild the proposal in a manifest, from its source file
+    /// and settings. See `DealManifest::verify` to check the rebuilt proposal is the reviewed one.
+    /// # Errors
+    /// * `BanyanError::Parse` - If the manifest's CID version isn't 0 or 1
+    pub fn from_manifest(manifest: &DealManifest) -> Result<DealProposalBuilder> {
+        let settings = &manifest.settings;
+        Ok(DealProposalBuilder {
+            executor_address: settings.executor_address.clone(),
+            deal_length_in_blocks: settings.deal_length_in_blocks,
+            proof_frequency_in_blocks: settings.proof_frequency_in_blocks,
+            pricing: settings.pricing.clone(),
+            collateral_pricing: settings.collateral_pricing.clone(),
+            seconds_per_block: settings.seconds_per_block,
+            rounding: settings.rounding,
+            erc20_token_denomination: settings.erc20_token_denomination.clone(),
+            input: Some(DealInput::Path(manifest.source.clone())),
+            cid_version: settings.cid_version()?,
+            cid_codec: settings.cid_codec,
+            hash_function: settings.hash_function,
+            ..DealProposalBuilder::default()
+        })
+    }
+
     /* Build Methods */
 
-    /// Build a DealProposal from a DealProposalConfig
+    /// Build a DealProposal from a DealProposalConfig, reading its input to the end
+    ///
+    /// # Returns
+    ///
+    /// * `DealProposal` - The DealProposal
+    ///
+    /// # Errors
+    /// * `BanyanError::Parse` - If no input was provided, the input is an async reader, an
+    ///   address doesn't parse, or CIDv0 was asked for with another codec or hash function
+    /// * `BanyanError::Io` - If the input can't be read
+    /// * `BanyanError::Math` - If the price and collateral are in tokens with different decimals,
+    ///   or don't fit in a U256
+    pub fn build(mut self) -> Result<DealProposal> {
+        let (ipfs_file_cid, blake3_checksum, file_size) = match self.take_input()? {
+            DealInput::File(file) => {
+                self.hashed(hash_file(&file, self.hash_strategy, self.hash_function)?)?
+            }
+            DealInput::Path(path) => self.hashed(hash_file(
+                &File::open(path)?,
+                self.hash_strategy,
+                self.hash_function,
+            )?)?,
+            DealInput::Reader(reader) => self.hashed(
+                FileHasher::new(reader)
+                    .with_hash_function(self.hash_function)
+                    .hashes()?,
+            )?,
+            DealInput::AsyncReader(_) => {
+                return Err(BanyanError::Parse(
+                    "An async reader can only be built with the build_async method".to_string(),
+                ))
+            }
+            DealInput::Cid {
+                cid,
+                size,
+                blake3_checksum,
+            } => (CidWrapper(cid), blake3_checksum, size),
+        };
+        self.build_for(ipfs_file_cid, blake3_checksum, file_size)
+    }
+
+    /// Build a DealProposal and the bao outboard tree for its data, reading its input once
     ///
     /// # Arguments
     ///
-    /// * `file` - The file the deal should be created for
+    /// * `obao_path` - Where to write the outboard tree. Any file there is replaced.
     ///
     /// # Returns
     ///
-    /// * `DealProposal` - The DealProposal
+    /// * `PreparedDeal` - The DealProposal and where its outboard tree is
     ///
     /// # Errors
-    /// TODO: Add Errors
-    pub fn build(&self) -> Result<DealProposal, Error> {
-        let file = self.file.as_ref().ok_or_else(|| {
-            Error::msg(
-                "No file handle provided. Please provide a file handle using the with_file method",
+    /// * `BanyanError::Parse` - If no input was provided, the input has to be read asynchronously
+    ///   or is a CID (which there's nothing to read from), or an address doesn't parse
+    /// * `BanyanError::Io` - If the input can't be read, or the outboard tree can't be written
+    /// * `BanyanError::Math` - As for `build`
+    pub fn prepare(mut self, obao_path: impl Into<PathBuf>) -> Result<PreparedDeal> {
+        let obao_path = obao_path.into();
+        let reader: Box<dyn Read + Send> = match self.take_input()? {
+            DealInput::File(file) => Box::new(file),
+            DealInput::Path(path) => Box::new(File::open(path)?),
+            DealInput::Reader(reader) => reader,
+            DealInput::AsyncReader(_) | DealInput::Cid { .. } => {
+                return Err(BanyanError::Parse(
+                    "Only a file, path or reader can be prepared in one pass".to_string(),
+                ))
+            }
+        };
+        let obao = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&obao_path)?;
+        let hashes = FileHasher::new(reader)
+            .with_hash_function(self.hash_function)
+            .hashes_with_outboard(obao)?;
+        let (ipfs_file_cid, blake3_checksum, file_size) = self.hashed(hashes)?;
+        Ok(PreparedDeal {
+            proposal: self.build_for(ipfs_file_cid, blake3_checksum, file_size)?,
+            obao_path,
+        })
+    }
+
+    /// Build a DealProposal from a DealProposalConfig, reading its input without blocking.
+    /// Takes any input, and returns the same errors as `build` except for async readers.
+    pub async fn build_async(mut self) -> Result<DealProposal> {
+        let (ipfs_file_cid, blake3_checksum, file_size) = match self.take_input()? {
+            DealInput::File(file) => self.hash_async(tokio::fs::File::from_std(file)).await?,
+            DealInput::Path(path) => self.hash_async(tokio::fs::File::open(path).await?).await?,
+            DealInput::AsyncReader(reader) => self.hash_async(reader).await?,
+            // Anything else is in memory or already hashed
+            input => return self.with_input(input).build(),
+        };
+        self.build_for(ipfs_file_cid, blake3_checksum, file_size)
+    }
+
+    /// Take the input out of the builder, or say how to provide one
+    fn take_input(&mut self) -> Result<DealInput> {
+        self.input.take().ok_or_else(|| {
+            BanyanError::Parse(
+                "No input provided. Please provide a path, file, reader, async reader or CID using \
+                 with_path, with_file, with_reader, with_async_reader or with_cid"
+                    .to_string(),
             )
-        })?;
-        let _file_size = file.metadata().unwrap().len();
-        let num_tib = _file_size as f64 / 1024.0 / 1024.0 / 1024.0 / 1024.0;
+        })
+    }
+
+    /// Hash an async input, returning its CID, Blake3 Hash and size
+    async fn hash_async(
+        &self,
+        input: impl AsyncRead + Unpin,
+    ) -> Result<(CidWrapper, Blake3Hash, u64)> {
+        let hashes = AsyncFileHasher::new(input)
+            .with_hash_function(self.hash_function)
+            .hashes()
+            .await?;
+        self.hashed(hashes)
+    }
+
+    /// The CID, Blake3 Hash and size of data we read, with the CID in the version and codec asked for
+    fn hashed(&self, hashes: FileHashes) -> Result<(CidWrapper, Blake3Hash, u64)> {
+        Ok((
+            CidWrapper::new(self.cid_version, self.cid_codec, hashes.multihash)?,
+            Blake3Hash(hashes.blake3),
+            hashes.size,
+        ))
+    }
+
+    /// Build a DealProposal for data with the given CID, hash and size
+    fn build_for(
+        &self,
+        ipfs_file_cid: CidWrapper,
+        blake3_checksum: Blake3Hash,
+        _file_size: u64,
+    ) -> Result<DealProposal> {
         /* Build the DealProposal */
 
         // parse the executor address as a Token
-        let executor_address = self.executor_address.parse::<Address>().unwrap();
+        let executor_address = parse_address("executor", &self.executor_address)?;
 
         // Set the duration of the deal
         let deal_length_in_blocks = BlockNum(self.deal_length_in_blocks as u64);
         let proof_frequency_in_blocks = BlockNum(self.proof_frequency_in_blocks as u64);
 
-        // Calculate the on-Chain price and collateral
-        let token_multiplier = TokenMultiplier::default();
-        let price = token_multiplier * (num_tib * self.price_per_tib);
-        let collateral = token_multiplier * (num_tib * self.collateral_per_tib);
-        let erc20_token_denomination: Address = self.erc20_token_denomination.parse().unwrap();
+        // Calculate the on-Chain price and collateral, in the token's smallest unit
+        let pricing = DealPricing::quote(
+            &self.pricing,
+            &self.collateral_pricing,
+            _file_size,
+            deal_length_in_blocks,
+            self.seconds_per_block,
+            self.rounding,
+        )?;
+        let price = pricing.price.total.amount();
+        let collateral = pricing.collateral.total.amount();
+        let erc20_token_denomination =
+            parse_address("ERC20 token", &self.erc20_token_denomination)?;
 
         let file_size = U256::from(_file_size);
 
-        // Calculate the Multi and Blake3 Hashes
-        let (mh, b3h) = FileHasher::new(file).hash()?;
-
-        // Calculate the CID of the file using Sha2-256 and Multihash
-        let blake3_checksum = Blake3Hash(b3h);
-        let ipfs_file_cid = CidWrapper(Cid::new_v1(0x55, mh));
         Ok(DealProposal {
             executor_address,
             deal_length_in_blocks,
@@ -189,13 +507,23 @@ impl DealProposalBuilder {
             file_size,
             ipfs_file_cid,
             blake3_checksum,
+            pricing: Some(pricing),
+            piece_commitment: None,
         })
     }
 }
 
+/// Parse an address given to the builder, saying which one it was if it's invalid
+fn parse_address(name: &str, address: &str) -> Result<Address> {
+    address
+        .parse()
+        .map_err(|e| BanyanError::Parse(format!("Invalid {} address {:?}: {}", name, address, e)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pricing::BYTES_PER_TIB;
     use std::fs::File;
 
     #[test]
@@ -217,4 +545,267 @@ mod tests {
             "4bdfe5f0ed92451b9a1a7cf979f538cc31e8440ac1de85d27fe3d5a207b01dd4"
         );
     }
+
+    #[tokio::test]
+    async fn every_input_builds_the_same_deal() {
+        let path = "test_files/ethereum.pdf";
+        let bytes = std::fs::read(path).unwrap();
+        let builder = || DealProposal::builder().with_price_per_tib("1".parse().unwrap());
+        let deal = builder()
+            .with_file(File::open(path).unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(deal.file_size, U256::from(bytes.len()));
+        assert_eq!(deal.blake3_checksum.0, blake3::hash(&bytes));
+
+        assert_eq!(builder().with_path(path).build().unwrap(), deal);
+        assert_eq!(
+            builder()
+                .with_reader(std::io::Cursor::new(bytes.clone()))
+                .build()
+                .unwrap(),
+            deal
+        );
+        let from_cid = builder()
+            .with_cid(
+                deal.ipfs_file_cid.0,
+                bytes.len() as u64,
+                deal.blake3_checksum,
+            )
+            .build()
+            .unwrap();
+        assert_eq!(from_cid, deal);
+
+        // Async inputs need an async build, which takes every other input too
+        let async_file = || tokio::fs::File::from_std(File::open(path).unwrap());
+        assert!(builder().with_async_reader(async_file()).build().is_err());
+        assert_eq!(
+            builder()
+                .with_async_reader(async_file())
+                .build_async()
+                .await
+                .unwrap(),
+            deal
+        );
+        assert_eq!(builder().with_path(path).build_async().await.unwrap(), deal);
+        assert_eq!(
+            builder()
+                .with_reader(std::io::Cursor::new(bytes))
+                .build_async()
+                .await
+                .unwrap(),
+            deal
+        );
+        assert!(builder().build_async().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cids_in_every_format() {
+        use multihash::MultihashDigest;
+
+        let path = "test_files/ethereum.pdf";
+        let bytes = std::fs::read(path).unwrap();
+        let default = DealProposal::builder().with_path(path).build().unwrap();
+        assert_eq!(default.ipfs_file_cid.version(), Version::V1);
+        assert_eq!(default.ipfs_file_cid.cid().codec(), CidCodec::Raw.code());
+
+        for hash_function in HashFunction::ALL {
+            for codec in [CidCodec::Raw, CidCodec::DagPb, CidCodec::DagCbor] {
+                let builder = || {
+                    DealProposal::builder()
+                        .with_hash_function(hash_function)
+                        .with_cid_format(Version::V1, codec)
+                };
+                let deal = builder().with_path(path).build().unwrap();
+                let cid = deal.ipfs_file_cid.cid();
+                assert_eq!(cid.codec(), codec.code());
+                assert_eq!(*cid.hash(), hash_function.code().digest(&bytes));
+                assert_eq!(deal.blake3_checksum, default.blake3_checksum);
+                let from_async = builder()
+                    .with_async_reader(tokio::fs::File::open(path).await.unwrap())
+                    .build_async()
+                    .await
+                    .unwrap();
+                assert_eq!(from_async, deal);
+            }
+        }
+
+        // CIDv0 is only for dag-pb over Sha2-256
+        let v0 = DealProposal::builder()
+            .with_path(path)
+            .with_cid_format(Version::V0, CidCodec::DagPb)
+            .build()
+            .unwrap();
+        assert!(v0.ipfs_file_cid.to_string().starts_with("Qm"));
+        assert!(matches!(
+            DealProposal::builder()
+                .with_path(path)
+                .with_cid_format(Version::V0, CidCodec::Raw)
+                .build(),
+            Err(BanyanError::Parse(_))
+        ));
+        assert!(DealProposal::builder()
+            .with_path(path)
+            .with_hash_function(HashFunction::Blake3)
+            .with_cid_format(Version::V0, CidCodec::DagPb)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn piece_commitments_are_attached() {
+        use crate::hash::piece_commitment;
+
+        let path = "test_files/ethereum.pdf";
+        let deal = DealProposal::builder().with_path(path).build().unwrap();
+        assert_eq!(deal.piece_commitment, None);
+        let piece = piece_commitment(File::open(path).unwrap()).unwrap();
+        let deal = deal.with_piece_commitment(piece).unwrap();
+        assert_eq!(deal.piece_commitment, Some(piece));
+        assert!(deal.to_string().ends_with(&format!("Piece CID: {}", piece)));
+        let json = serde_json::to_string(&deal).unwrap();
+        assert_eq!(serde_json::from_str::<DealProposal>(&json).unwrap(), deal);
+
+        let other = piece_commitment(&b"not the same data"[..]).unwrap();
+        assert!(matches!(
+            deal.with_piece_commitment(other),
+            Err(BanyanError::Parse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn prepared_deals_can_be_proven() {
+        let path = "test_files/ethereum.pdf";
+        let dir = tempfile::tempdir().unwrap();
+        let obao_path = dir.path().join("ethereum.pdf.obao");
+        let prepared = DealProposal::builder()
+            .with_path(path)
+            .prepare(&obao_path)
+            .unwrap();
+        assert_eq!(prepared.obao_path, obao_path);
+        assert_eq!(
+            prepared.proposal,
+            DealProposal::builder().with_path(path).build().unwrap()
+        );
+
+        // The outboard tree is enough to prove any chunk of the file
+        let file_size =
+            crate::types::checked_uint::<u64>(prepared.proposal.file_size, "File size").unwrap();
+        let block_hash = ethers::types::H256::repeat_byte(7);
+        let slice = crate::proofs::gen_proof(
+            BlockNum(0),
+            block_hash,
+            File::open(path).unwrap(),
+            prepared.open_obao().unwrap(),
+            file_size,
+        )
+        .await
+        .unwrap();
+        let (offset, size) =
+            crate::proofs::compute_random_block_choice_from_hash(block_hash, file_size);
+        let mut decoded = vec![];
+        bao::decode::SliceDecoder::new(
+            slice.as_slice(),
+            &prepared.proposal.blake3_checksum.0,
+            offset,
+            size,
+        )
+        .read_to_end(&mut decoded)
+        .unwrap();
+        assert_eq!(decoded.len() as u64, size);
+
+        // There's nothing to read for a CID
+        let cid = prepared.proposal.ipfs_file_cid.0;
+        let blake3_checksum = prepared.proposal.blake3_checksum;
+        assert!(DealProposal::builder()
+            .with_cid(cid, file_size, blake3_checksum)
+            .prepare(&obao_path)
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_addresses_are_errors() {
+        let file = File::open("abi/escrow.json").unwrap();
+        let err = DealProposal::builder()
+            .with_file(file)
+            .with_executor_address("0xnope".to_string())
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BanyanError::Parse(msg) if msg.contains("executor")));
+        let err = DealProposal::builder().build().unwrap_err();
+        assert!(
+            matches!(err, BanyanError::Parse(msg) if msg.contains("with_path")
+            && msg.contains("with_async_reader")
+            && msg.contains("with_cid"))
+        );
+    }
+
+    #[test]
+    fn prices_are_exact() {
+        let file_size = File::open("abi/escrow.json")
+            .unwrap()
+            .metadata()
+            .unwrap()
+            .len();
+        // 3 units per byte, so the price is exactly 3 units per byte of the file
+        let per_tib = TokenAmount::new(U256::from(3 * BYTES_PER_TIB), 18).unwrap();
+        let deal_proposal = DealProposal::builder()
+            .with_file(File::open("abi/escrow.json").unwrap())
+            .with_price_per_tib(per_tib.clone())
+            .build()
+            .unwrap();
+        assert_eq!(deal_proposal.price, U256::from(3 * file_size));
+        // Free deals stay free
+        assert!(deal_proposal.collateral.is_zero());
+
+        // A price per TiB of a whole token is a fraction of a unit for the file
+        let deal_proposal = |rounding| {
+            DealProposal::builder()
+                .with_file(File::open("abi/escrow.json").unwrap())
+                .with_price_per_tib(TokenAmount::new(U256::one(), 0).unwrap())
+                .with_collateral_per_tib(TokenAmount::new(U256::one(), 0).unwrap())
+                .with_rounding(rounding)
+                .build()
+                .unwrap()
+        };
+        assert!(deal_proposal(Rounding::Down).price.is_zero());
+        assert_eq!(deal_proposal(Rounding::Up).price, U256::one());
+
+        let err = DealProposal::builder()
+            .with_file(File::open("abi/escrow.json").unwrap())
+            .with_price_per_tib("1 USDC".parse().unwrap())
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BanyanError::Math(_)));
+    }
+
+    #[test]
+    fn longer_deals_cost_more() {
+        let deal_proposal = |blocks| {
+            DealProposal::builder()
+                .with_file(File::open("abi/escrow.json").unwrap())
+                .with_deal_length_in_blocks(blocks)
+                .with_pricing(PricingModel::PerTibPerDay("1".parse().unwrap()))
+                .with_collateral_pricing(PricingModel::Flat("2".parse().unwrap()))
+                .with_rounding(Rounding::Down)
+                .build()
+                .unwrap()
+        };
+        let short = deal_proposal(7_200);
+        let long = deal_proposal(72_000);
+        assert!(!short.price.is_zero());
+        // Ten times the days is ten times the price, give or take rounding
+        assert_eq!(long.price / 10, short.price);
+        assert_eq!(short.collateral, U256::exp10(18) * 2);
+        assert_eq!(long.collateral, short.collateral);
+
+        // The proposal explains its price
+        let pricing = long.pricing.unwrap();
+        assert_eq!(pricing.price.total.amount(), long.price);
+        assert_eq!(pricing.price.deal_length_in_blocks, BlockNum(72_000));
+        assert!(pricing
+            .price
+            .to_string()
+            .contains("72000 blocks * 12 s per block"));
+    }
 }
@@ -0,0 +1,245 @@
+use crate::{
+    contracts::ContractError,
+    types::{DealID, DealStatus},
+    validation::Violation,
+};
+use ethers::{
+    abi::{AbiError, Error as AbiDecodeError, InvalidOutputType},
+    contract::ContractError as CallError,
+    providers::{Middleware, ProviderError},
+};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/*
+ * The one error type every public API in this crate returns.
+ * Status: A variant per subsystem, so a long running service can tell a flaky node from a bad
+ * file and decide whether to retry, skip or give up. The messages are meant for humans.
+ */
+
+/// A Result defaulting to a BanyanError
+pub type Result<T, E = BanyanError> = std::result::Result<T, E>;
+
+/// BanyanError - Everything that can go wrong in this crate, by the subsystem it went wrong in
+#[derive(Debug)]
+pub enum BanyanError {
+    /// Talking to the Ethereum node failed, or the chain isn't in the state we expected
+    Chain(String),
+    /// The Banyan Contract rejected a call
+    Contract(ContractError),
+    /// A deal's status doesn't allow what was asked of it, so it wasn't sent to the chain
+    InvalidDealStatus {
+        deal_id: DealID,
+        status: DealStatus,
+        action: String,
+    },
+    /// A deal proposal broke the rules it was checked against, so it wasn't sent to the chain
+    InvalidProposal(Vec<Violation>),
+    /// A request to the Estuary API failed
+    Estuary(String),
+    /// Storing or fetching content on IPFS failed
+    Ipfs(String),
+    /// Generating or checking a proof failed
+    Proof(String),
+    /// A value couldn't be parsed, encoded or decoded
+    Parse(String),
+    /// Arithmetic overflowed, underflowed or divided by zero
+    Math(String),
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+    /// Reading or writing the local deal store failed
+    Store(String),
+    /// Something was cancelled before it finished
+    Cancelled(String),
+    /// A message couldn't be handed to a worker, e.g. nothing handles it or the worker stopped
+    Dispatch(String),
+    /// An off-chain deal offer, or an acceptance of one, isn't signed by who it should be or
+    /// can't be accepted
+    Offer(String),
+    /// A deal manifest no longer builds the proposal that was reviewed, so it wasn't sent to the
+    /// chain
+    Manifest(String),
+}
+
+impl BanyanError {
+    /// Whether this is the Banyan Contract's `UNAUTHORIZED()` error
+    pub fn is_unauthorized(&self) -> bool {
+        matches!(self, BanyanError::Contract(e) if e.is_unauthorized())
+    }
+}
+
+impl Display for BanyanError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            BanyanError::Chain(msg) => write!(f, "Chain error: {}", msg),
+            BanyanError::Contract(e) => write!(f, "Contract error: {}", e),
+            BanyanError::InvalidDealStatus {
+                deal_id,
+                status,
+                action,
+            } => write!(
+                f,
+                "Deal status error: cannot {} deal {}, it is {}",
+                action, deal_id, status
+            ),
+            BanyanError::InvalidProposal(violations) => {
+                write!(f, "Invalid deal proposal: ")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", violation)?;
+                }
+                Ok(())
+            }
+            BanyanError::Estuary(msg) => write!(f, "Estuary error: {}", msg),
+            BanyanError::Ipfs(msg) => write!(f, "IPFS error: {}", msg),
+            BanyanError::Proof(msg) => write!(f, "Proof error: {}", msg),
+            BanyanError::Parse(msg) => write!(f, "Parse error: {}", msg),
+            BanyanError::Math(msg) => write!(f, "Math error: {}", msg),
+            BanyanError::Io(e) => write!(f, "IO error: {}", e),
+            BanyanError::Store(msg) => write!(f, "Store error: {}", msg),
+            BanyanError::Cancelled(msg) => write!(f, "Cancelled: {}", msg),
+            BanyanError::Dispatch(msg) => write!(f, "Dispatch error: {}", msg),
+            BanyanError::Offer(msg) => write!(f, "Offer error: {}", msg),
+            BanyanError::Manifest(msg) => write!(f, "Manifest error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BanyanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BanyanError::Contract(e) => Some(e),
+            BanyanError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ContractError> for BanyanError {
+    fn from(e: ContractError) -> Self {
+        BanyanError::Contract(e)
+    }
+}
+
+impl From<std::io::Error> for BanyanError {
+    fn from(e: std::io::Error) -> Self {
+        BanyanError::Io(e)
+    }
+}
+
+impl From<ProviderError> for BanyanError {
+    fn from(e: ProviderError) -> Self {
+        BanyanError::Chain(e.to_string())
+    }
+}
+
+impl<M: Middleware> From<CallError<M>> for BanyanError {
+    fn from(e: CallError<M>) -> Self {
+        match e {
+            CallError::DecodingError(e) => e.into(),
+            CallError::AbiError(e) => e.into(),
+            CallError::DetokenizationError(e) => e.into(),
+            other => BanyanError::Chain(other.to_string()),
+        }
+    }
+}
+
+impl From<AbiError> for BanyanError {
+    fn from(e: AbiError) -> Self {
+        BanyanError::Parse(e.to_string())
+    }
+}
+
+impl From<AbiDecodeError> for BanyanError {
+    fn from(e: AbiDecodeError) -> Self {
+        BanyanError::Parse(e.to_string())
+    }
+}
+
+impl From<InvalidOutputType> for BanyanError {
+    fn from(e: InvalidOutputType) -> Self {
+        BanyanError::Parse(e.to_string())
+    }
+}
+
+/// So numbers checked with `checked_uint` can be read out of ethers Tokens
+impl From<BanyanError> for InvalidOutputType {
+    fn from(e: BanyanError) -> Self {
+        match e {
+            BanyanError::Parse(msg) => InvalidOutputType(msg),
+            other => InvalidOutputType(other.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for BanyanError {
+    fn from(e: serde_json::Error) -> Self {
+        BanyanError::Parse(e.to_string())
+    }
+}
+
+impl From<toml::de::Error> for BanyanError {
+    fn from(e: toml::de::Error) -> Self {
+        BanyanError::Parse(e.to_string())
+    }
+}
+
+impl From<toml::ser::Error> for BanyanError {
+    fn from(e: toml::ser::Error) -> Self {
+        BanyanError::Parse(e.to_string())
+    }
+}
+
+impl From<sled::Error> for BanyanError {
+    fn from(e: sled::Error) -> Self {
+        BanyanError::Store(e.to_string())
+    }
+}
+
+impl From<cid::Error> for BanyanError {
+    fn from(e: cid::Error) -> Self {
+        BanyanError::Ipfs(e.to_string())
+    }
+}
+
+/// Estuary is the only thing we talk to over plain HTTP
+impl From<reqwest::Error> for BanyanError {
+    fn from(e: reqwest::Error) -> Self {
+        BanyanError::Estuary(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::FailedCall;
+    use std::error::Error;
+
+    #[test]
+    fn errors_keep_their_subsystem() {
+        let err: BanyanError = std::io::Error::new(std::io::ErrorKind::NotFound, "gone").into();
+        assert!(matches!(err, BanyanError::Io(_)));
+        assert_eq!(err.to_string(), "IO error: gone");
+        assert!(err.source().is_some());
+
+        let err: BanyanError = InvalidOutputType("Expected `String`".to_string()).into();
+        assert!(matches!(err, BanyanError::Parse(_)));
+
+        let err: BanyanError = ContractError::Custom {
+            call: FailedCall {
+                function: "withdrawLink".to_string(),
+                args: vec![],
+            },
+            name: "UNAUTHORIZED".to_string(),
+            args: vec![],
+        }
+        .into();
+        assert!(err.is_unauthorized());
+        assert_eq!(
+            err.to_string(),
+            "Contract error: withdrawLink() reverted with UNAUTHORIZED()"
+        );
+        assert!(!BanyanError::Chain("Timed out".to_string()).is_unauthorized());
+    }
+}
@@ -1,6 +1,6 @@
-use anyhow::{Error, Result};
+use crate::error::{BanyanError, Result};
 use reqwest::{multipart, Body, Client};
-use serde::{Deserialize, Deserializer};
+use serde::{de::Error as _, Deserialize, Deserializer};
 use serde_json::{Map, Value};
 use std::env::var;
 use std::fmt;
@@ -30,8 +30,11 @@ pub fn des_cid_from_map<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let map: Map<String, Value> = Map::deserialize(deserializer).unwrap();
-    let cid_str = map.get("/").unwrap().as_str().unwrap();
+    let map: Map<String, Value> = Map::deserialize(deserializer)?;
+    let cid_str = map
+        .get("/")
+        .and_then(Value::as_str)
+        .ok_or_else(|| D::Error::custom("Expected a CID as a map with a \"/\" key"))?;
     Ok(cid_str.to_string())
 }
 
@@ -51,12 +54,9 @@ impl Default for EstuaryClient {
     /// ```
     /// # Panics
     /// This function will panic if the `ESTUARY_API_HOSTNAME` environment variable is not set.
+    /// Use `EstuaryClient::from_env` to handle that instead.
     fn default() -> Self {
-        Self {
-            estuary_api_hostname: var("ESTUARY_API_HOSTNAME")
-                .unwrap_or_else(|_| panic!("ESTUARY_API_HOSTNAME environment variable is not set")),
-            estuary_api_key: var("ESTUARY_API_KEY").ok(),
-        }
+        Self::from_env().unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
@@ -80,6 +80,24 @@ impl EstuaryClient {
         }
     }
 
+    /// Create a new EstuaryClient from the Environment
+    /// ```no_run
+    /// use banyan_shared::estuary::EstuaryClient;
+    /// let estuary_client = EstuaryClient::from_env().unwrap();
+    /// ```
+    /// # Errors
+    /// * `BanyanError::Estuary` - If the `ESTUARY_API_HOSTNAME` environment variable is not set
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            estuary_api_hostname: var("ESTUARY_API_HOSTNAME").map_err(|_| {
+                BanyanError::Estuary(
+                    "ESTUARY_API_HOSTNAME environment variable is not set".to_string(),
+                )
+            })?,
+            estuary_api_key: var("ESTUARY_API_KEY").ok(),
+        })
+    }
+
     /* Struct Methods */
 
     /// Get the Estuary API Hostname
@@ -87,14 +105,20 @@ impl EstuaryClient {
         self.estuary_api_hostname.clone()
     }
 
+    /// The API key, which every authenticated endpoint needs
+    fn api_key(&self) -> Result<&str> {
+        self.estuary_api_key
+            .as_deref()
+            .ok_or_else(|| BanyanError::Estuary("No Estuary API Key is set".to_string()))
+    }
+
     /// Stage a File on Estuary
     /// # Arguments
     /// * `file` - The handle to the file to stage
     /// * `deal_id` - The Deal ID to use for the file
     /// * `b3_hash` - The Blake3 Hash of the file
     /// ```no_run
-    /// use anyhow::{Result, Error};
-    /// use  banyan_shared::estuary::EstuaryClient;
+    /// use banyan_shared::{error::Result, estuary::EstuaryClient};
     /// #[tokio::main]
     /// async fn main() -> Result<()> {
     ///     let client = EstuaryClient::default();
@@ -106,7 +130,8 @@ impl EstuaryClient {
     ///    Ok(())
     /// }
     /// ```
-    /// # Panics
+    /// # Errors
+    /// * If no API key is set
     /// * If there is an error reading the file
     /// * If there is an error sending the request
     /// Stage a File on Estuary
@@ -115,17 +140,14 @@ impl EstuaryClient {
     /// * `deal_id_str` - The (optional) Deal ID to use for the file, as a String
     /// * `b3_hash_str` - The (optional) Blake3 Hash of the file, as a Hex String
     /// # Returns
-    /// * `Result<(), Error>` - Errors if there is an error staging the file
+    /// * `Result<()>` - Errors if there is an error staging the file
     pub async fn stage_file(
         &self,
         file_path: String,
         deal_id_str: Option<String>,
         b3_hash_str: Option<String>,
-    ) -> Result<(), Error> {
-        if self.estuary_api_key.is_none() {
-            panic!("No Estuary API Key is set");
-        }
-        let estuary_api_key = self.estuary_api_key.clone().unwrap();
+    ) -> Result<()> {
+        let estuary_api_key = self.api_key()?;
         // Initialize an HTTP Client
         let client = Client::new();
         // Read the File as a Tokio File
@@ -166,7 +188,7 @@ impl EstuaryClient {
             // No Need to listen to the Response - We're good!
             Ok(())
         } else {
-            Err(Error::msg(format!(
+            Err(BanyanError::Estuary(format!(
                 "Error staging file: {}",
                 res.status().as_str()
             )))
@@ -175,8 +197,7 @@ impl EstuaryClient {
 
     /// Get the First 500 pieces of Content from Estuary
     /// ```no_run
-    /// use anyhow::{Result, Error};
-    /// use  banyan_shared::estuary::EstuaryClient;
+    /// use banyan_shared::{error::Result, estuary::EstuaryClient};
     /// #[tokio::main]
     /// async fn main() -> Result<()> {
     ///     let client = EstuaryClient::default();
@@ -184,14 +205,12 @@ impl EstuaryClient {
     ///     Ok(())
     /// }
     /// ```
-    /// # Panics
+    /// # Errors
+    /// * If no API key is set
     /// * If there is an error sending the request
     /// * If there is an error parsing the response
-    pub async fn get_content(&self) -> Result<Vec<Content>, Error> {
-        if self.estuary_api_key.is_none() {
-            panic!("No Estuary API Key is set");
-        }
-        let estuary_api_key = self.estuary_api_key.clone().unwrap();
+    pub async fn get_content(&self) -> Result<Vec<Content>> {
+        let estuary_api_key = self.api_key()?;
         // Initialize an HTTP Client
         let client = Client::new();
         // Initialize the Request
@@ -210,7 +229,7 @@ impl EstuaryClient {
             let content: Vec<Content> = res.json().await?;
             Ok(content)
         } else {
-            Err(Error::msg(format!(
+            Err(BanyanError::Estuary(format!(
                 "Error getting content: {}",
                 res.status().as_str()
             )))
@@ -240,6 +259,18 @@ mod test {
         return;
     }
 
+    #[tokio::test]
+    /// Staging a file without an API key is an error, not a panic
+    async fn stage_file_without_api_key() {
+        let client = EstuaryClient::new("http://localhost:3004".to_string(), None);
+        let err = client
+            .stage_file("Cargo.toml".to_string(), None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BanyanError::Estuary(_)));
+        assert!(client.get_content().await.is_err());
+    }
+
     #[tokio::test]
     /// Try to get content from Estuary
     async fn get_contents() {
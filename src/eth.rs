@@ -1,19 +1,24 @@
 use crate::{
+    contracts::{self, ContractVersion},
+    error::{BanyanError, Result},
+    manifest::DealManifest,
+    multicall::MULTICALL3_ADDRESS,
+    offer::{DealOffer, SignedDealOffer, SignedOfferAcceptance},
     proofs::{self, gen_proof},
     types::*,
+    validation::ValidationPolicy,
 };
-use anyhow::{anyhow, Error, Result};
 use ethers::{
-    abi::Abi,
+    abi::{self, ParamType, Token},
     contract::Contract,
     middleware::SignerMiddleware,
     prelude::H256,
-    providers::{Http, Middleware, Provider},
+    providers::{Http, HttpClientError, Middleware, Provider, ProviderError},
     signers::{LocalWallet, Signer},
-    types::{Address, Bytes, Filter, Log, TransactionRequest, U256},
+    types::{Address, Bytes, Filter, Log, TransactionReceipt, TransactionRequest, U256},
 };
 use ethers_contract_derive::EthEvent;
-use lazy_static::lazy_static;
+use serde_json::Value;
 use std::convert::TryFrom;
 use std::env;
 
@@ -21,19 +26,10 @@ use dotenv::dotenv;
 use std::{
     fs::File,
     io::{Cursor, Read, Seek},
-    ops::{Add, Div, Mul, Sub},
+    time::Duration,
 };
 const WORD: usize = 32;
 
-// Load the Banyan Contract ABI into Memory
-// IMPORTANT: The ABI must be updated if the contract is updated
-lazy_static! {
-    // IMPORTANT: This is a reference to a Test Contract's ABI
-    // TODO: Change to the real contract's ABI, and update onChainDealInfo
-    // Contract Address: 0x7Da936F4A55D5044e1838Cc959935085662392F1
-    static ref BANYAN_ABI_STR_REF: &'static str = include_str!("../abi/jonah_test.json");
-}
-
 /// The Event emitted by the Banyan Contract when a Deal is submitted
 #[derive(Clone, Debug, Copy, EthEvent)]
 struct NewOffer {
@@ -54,19 +50,46 @@ pub struct EthClient {
     signer: Option<SignerMiddleware<Provider<Http>, LocalWallet>>,
     /// A Deployed Solidity Contract Address. This is required to interact with the Banyan Contract.
     contract: Contract<Provider<Http>>,
+    /// The version of the Banyan Contract deployed at that address
+    contract_version: &'static ContractVersion,
+    /// The (Optional) Multicall3 contract used to batch reads. Reads are sent one by one without it.
+    multicall_address: Option<Address>,
+    /// The rules every deal is checked against before it's proposed
+    validation_policy: ValidationPolicy,
 }
 
 impl Default for EthClient {
-    /// Build a new EthClient from the environment
-    // TODO kind sweet error handling
+    /// Build a new EthClient from the environment, without talking to the chain. It assumes the
+    /// default contract version; use `EthClient::from_env` to detect the deployed one.
+    /// # Panics
+    /// * If the environment isn't configured. Use `EthClient::from_env` to handle that instead.
     fn default() -> Self {
+        EthClient::new_from_env().unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+// TODO: Update docs
+/// The EthProvider is a wrapper around the ethers-rs Provider that handles all Ethereum
+/// interactions.
+impl EthClient {
+    /// Build a new EthClient from the environment, and detect the version of the deployed contract
+    /// # Errors
+    /// * If `ETH_API_KEY` or `ETH_CONTRACT_ADDRESS` isn't set
+    /// * If `ETH_CONTRACT_ADDRESS` or `ETH_PRIVATE_KEY` doesn't parse
+    /// * As for `EthClient::detect_contract_version`
+    pub async fn from_env() -> Result<Self> {
+        EthClient::new_from_env()?.detect_contract_version().await
+    }
+
+    /// Build a new EthClient from the environment, assuming the default contract version
+    fn new_from_env() -> Result<Self> {
         dotenv().ok();
-        dbg!("Initializing EthClient from environment");
         // Read the Api Url from the environment. Default to the mainnet Infura API
-        let api_url = env::var("ETH_API_URL")
-            .unwrap_or_else(|_| "https://mainnet.infura.io/v3/".parse().unwrap());
+        let api_url =
+            env::var("ETH_API_URL").unwrap_or_else(|_| "https://mainnet.infura.io/v3/".to_string());
         // Read the Api Key from the environment. Raise an error if it is not set
-        let api_key = env::var("ETH_API_KEY").expect("ETH_API_KEY must be set");
+        let api_key = env::var("ETH_API_KEY")
+            .map_err(|_| BanyanError::Chain("ETH_API_KEY must be set".to_string()))?;
         // Try and Read the Chain ID from the environment. Default to 1 (mainnet)
         let chain_id = env::var("ETH_CHAIN_ID")
             .unwrap_or_else(|_| "1".to_string())
@@ -76,19 +99,17 @@ impl Default for EthClient {
         // TODO also this is dangerous!!!! should not store privkey in env!!!
         let private_key = env::var("ETH_PRIVATE_KEY").ok();
         // Read the Contract Address from the environment
-        // TODO: Explicit Error Raise on Unparsable Address
-        let contract_address: Address = (env::var("ETH_CONTRACT_ADDRESS")
-            .expect("ETH_CONTRACT_ADDRESS must be set"))
-        .parse()
-        .expect("ETH_CONTRACT_ADDRESS must be a valid Ethereum Address");
-        EthClient::new(api_url, api_key, chain_id, private_key, contract_address).unwrap()
+        let contract_address: Address = env::var("ETH_CONTRACT_ADDRESS")
+            .map_err(|_| BanyanError::Chain("ETH_CONTRACT_ADDRESS must be set".to_string()))?
+            .parse()
+            .map_err(|_| {
+                BanyanError::Parse(
+                    "ETH_CONTRACT_ADDRESS must be a valid Ethereum Address".to_string(),
+                )
+            })?;
+        EthClient::new(api_url, api_key, chain_id, private_key, contract_address)
     }
-}
 
-// TODO: Update docs
-/// The EthProvider is a wrapper around the ethers-rs Provider that handles all Ethereum
-/// interactions.
-impl EthClient {
     /// Create a new EthClient - Uses EthClientBuilder::new()
     /// # Arguments
     /// * `api_url` - The URL of the Ethereum API to connect to. This is required to interact with
@@ -100,6 +121,9 @@ impl EthClient {
     ///                 This is required for interacting with payable functions.
     /// * `contract_address` - The (Optional) Deployed Solidity Contract Address to interact with.
     /// // * `timeout` - The (Optional) Timeout for the Eth Client. 15 seconds by default.
+    ///
+    /// This doesn't talk to the chain, so it assumes the default contract version.
+    /// Use `EthClient::connect` to detect the version of the deployed contract.
     /// ```no_run
     /// use banyan_shared::eth::EthClient;
     /// use ethers::types::Address;
@@ -113,8 +137,8 @@ impl EthClient {
     ///    // Some(10),
     /// ).unwrap();
     /// ```
-    /// # Panics
-    /// * If the API URL is invalid
+    /// # Errors
+    /// * If the API URL or private key is invalid
     pub fn new(
         api_url: String,
         api_key: String,
@@ -122,10 +146,11 @@ impl EthClient {
         private_key: Option<String>,
         contract_address: Address,
         //timeout: Option<u64>,
-    ) -> Result<EthClient, Error> {
+    ) -> Result<EthClient> {
         // Determine an API URL and Initialize the Provider
         let url = format!("{}{}", api_url, api_key);
-        let provider = Provider::<Http>::try_from(url).expect("Failed to create provider");
+        let provider = Provider::<Http>::try_from(url.as_str())
+            .map_err(|e| BanyanError::Parse(format!("Invalid API URL {:?}: {}", api_url, e)))?;
 
         // Get the Chain ID. If None, set to 1
         let chain_id = chain_id.unwrap_or(1);
@@ -134,7 +159,7 @@ impl EthClient {
         let signer = if let Some(private_key) = &private_key {
             let wallet = private_key
                 .parse::<LocalWallet>()
-                .expect("Failed to parse private key");
+                .map_err(|e| BanyanError::Parse(format!("Failed to parse private key: {}", e)))?;
             Some(SignerMiddleware::new(
                 provider.clone(),
                 wallet.with_chain_id(chain_id),
@@ -144,8 +169,12 @@ impl EthClient {
         };
 
         // Check if we have a contract address to set up a Contract
-        let abi: Abi = serde_json::from_str(&BANYAN_ABI_STR_REF).expect("Failed to parse ABI");
-        let contract = Contract::new(contract_address, abi, provider.clone());
+        let contract_version = contracts::default_contract_version();
+        let contract = Contract::new(
+            contract_address,
+            contract_version.abi.clone(),
+            provider.clone(),
+        );
 
         // Determine the timeout as a Duration in seconds, assign default if not provided
         // let timeout = Duration::from_secs(timeout.unwrap_or(15));
@@ -154,10 +183,142 @@ impl EthClient {
             chain_id,
             signer,
             contract,
+            contract_version,
+            multicall_address: Some(MULTICALL3_ADDRESS),
+            validation_policy: ValidationPolicy::default(),
             //timeout,
         })
     }
 
+    /// Create a new EthClient and detect the version of the deployed contract.
+    /// Takes the same arguments as `EthClient::new`.
+    /// ```no_run
+    /// use banyan_shared::eth::EthClient;
+    /// use ethers::types::Address;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let eth_client = EthClient::connect(
+    ///         "https://mainnet.infura.io/v3/".to_string(),
+    ///         "API_KEY".to_string(),
+    ///         Some(1),
+    ///         Some("PRIVATE_KEY".to_string()),
+    ///         "CONTRACT_ADDRESS".parse::<Address>().unwrap(),
+    ///     )
+    ///     .await
+    ///     .unwrap();
+    ///     println!("Contract version: {}", eth_client.contract_version().version);
+    /// }
+    /// ```
+    /// # Errors
+    /// * If the deployed contract isn't a version we support
+    pub async fn connect(
+        api_url: String,
+        api_key: String,
+        chain_id: Option<u64>,
+        private_key: Option<String>,
+        contract_address: Address,
+    ) -> Result<EthClient> {
+        EthClient::new(api_url, api_key, chain_id, private_key, contract_address)?
+            .detect_contract_version()
+            .await
+    }
+
+    /// Ask the chain which version of the Banyan Contract we're talking to, and use its ABI.
+    /// Contracts that implement `version()` are looked up by what it returns. Older ones
+    /// revert, and are recognized by the function selectors in their bytecode instead.
+    /// # Errors
+    /// * If the contract reports a version we don't support
+    /// * If there's no contract at the address, or it doesn't look like any version we support
+    pub async fn detect_contract_version(self) -> Result<Self> {
+        let address = self.contract.address();
+        let tx = TransactionRequest::new()
+            .to(address)
+            .data(ethers::utils::id("version()").to_vec());
+        let reported = match self.provider.call(&tx.into(), None).await {
+            Ok(output) if !output.is_empty() => Some(
+                abi::decode(&[ParamType::String], &output)
+                    .ok()
+                    .and_then(|mut tokens| tokens.pop())
+                    .and_then(Token::into_string)
+                    .ok_or_else(|| {
+                        BanyanError::Parse(format!(
+                            "Contract at {:?} returned an invalid version()",
+                            address
+                        ))
+                    })?,
+            ),
+            // No `version()`: the call reverted, or there's nothing at the address to answer it
+            Ok(_) => None,
+            Err(e) if revert_data(&e).is_some() => None,
+            // Anything else is the node, not the contract, so don't guess
+            Err(e) => return Err(e.into()),
+        };
+        let contract_version = match reported {
+            Some(version) => contracts::find_contract_version(&version),
+            None => {
+                let code = self.provider.get_code(address, None).await?;
+                contracts::detect_contract_version_from_code(&code)
+            }
+        }
+        .map_err(|e| match e {
+            BanyanError::Chain(msg) => {
+                BanyanError::Chain(format!("Contract at {:?}: {}", address, msg))
+            }
+            other => other,
+        })?;
+        Ok(self.with_contract_version(contract_version))
+    }
+
+    /// Talk to the contract as a specific version, rather than the one we detected or assumed
+    /// # Arguments
+    /// * `contract_version` - The version of the deployed contract
+    pub fn with_contract_version(mut self, contract_version: &'static ContractVersion) -> Self {
+        self.contract = Contract::new(
+            self.contract.address(),
+            contract_version.abi.clone(),
+            self.provider.clone(),
+        );
+        self.contract_version = contract_version;
+        self
+    }
+
+    /// Poll the node for pending transactions and event filters at a different interval.
+    /// The provider's default suits public chains; nodes that mine instantly, like a local dev
+    /// chain, don't need to be waited on that long.
+    /// # Arguments
+    /// * `interval` - How long to wait between polls
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.provider = self.provider.interval(interval);
+        self.signer = self
+            .signer
+            .map(|signer| SignerMiddleware::new(self.provider.clone(), signer.signer().clone()));
+        self.contract = Contract::new(
+            self.contract.address(),
+            self.contract_version.abi.clone(),
+            self.provider.clone(),
+        );
+        self
+    }
+
+    /// Batch reads through the Multicall3 contract at a different address, or not at all.
+    /// Multicall3 is deployed at the same address on most chains, which is what we use by default.
+    /// # Arguments
+    /// * `multicall_address` - The address of a Multicall3 deployment, or None to disable batching
+    pub fn with_multicall_address(mut self, multicall_address: Option<Address>) -> Self {
+        self.multicall_address = multicall_address;
+        self
+    }
+
+    /// Check deals against a different set of rules before proposing them.
+    /// The built-in rules are used by default.
+    /// # Arguments
+    /// * `validation_policy` - The rules to check deals against
+    pub fn with_validation_policy(mut self, validation_policy: ValidationPolicy) -> Self {
+        self.validation_policy = validation_policy;
+        self
+    }
+
     /* Struct State Methods */
 
     /// Return whether theres's a signer configured
@@ -165,6 +326,11 @@ impl EthClient {
         self.signer.is_some()
     }
 
+    /// Return the version of the Banyan Contract we're talking to
+    pub fn contract_version(&self) -> &'static ContractVersion {
+        self.contract_version
+    }
+
     /* Banyan Functions */
 
     /* Deal Stuff */
@@ -190,52 +356,157 @@ impl EthClient {
     ///     let deal_id = client.propose_deal(deal, None, None).await.unwrap();
     /// }
     /// ```
-    /// # Panics
-    /// * If the Deal Proposal is invalid
+    /// # Errors
+    /// * `BanyanError::InvalidProposal` - If the deal breaks the client's validation policy.
+    ///   Nothing is sent to the chain.
     /// * If the client is not configured with a signer
+    /// * `BanyanError::Contract` - If the contract rejects the Deal Proposal
     pub async fn propose_deal(
         &self,
         deal: DealProposal,
         gas_limit: Option<u64>,
         gas_price: Option<u64>,
-    ) -> Result<DealID, Error> {
-        // TODO: Implement a general purpose wrapper for payable functions
-        if !self.has_signer() {
-            return Err(anyhow!("No signer available"));
-        }
-        // Borrow our signer and contract
-        let signer = self.signer.as_ref().unwrap();
+    ) -> Result<DealID> {
+        deal.validate_with(&self.validation_policy)?;
         // Create a new deal proposal Transaction
         let data = self.contract.encode("startOffer", deal)?;
-        let tx = TransactionRequest::new()
-            .to(self.contract.address())
-            .data(data)
-            .gas(gas_limit.unwrap_or(1_000_000u64)) // 3 million gas
-            .gas_price(gas_price.unwrap_or(80_000_000_000u64)) // 70 Gwei
-            .chain_id(self.chain_id);
-        // Sign the transaction and listen for the event
-        let pending_tx = match signer.send_transaction(tx, None).await {
-            Ok(tx) => tx,
-            Err(e) => {
-                return Err(anyhow!("Error signing transaction: {}", &e.to_string()));
-            }
-        };
-        let receipt = pending_tx.await?;
-        let tx_hash = receipt.as_ref().unwrap().transaction_hash;
-        let bn = receipt.as_ref().unwrap().block_number.unwrap();
+        let receipt = self
+            .send_contract_transaction(
+                data,
+                gas_limit,
+                Some(gas_price.unwrap_or(80_000_000_000u64)), // 80 Gwei
+            )
+            .await?;
+        let tx_hash = receipt.transaction_hash;
+        let bn = mined_in(&receipt)?;
         // TODO: More sophisticated Filter
-        let logs: Vec<NewOffer> = match self.contract.event().from_block(bn).query().await {
+        let logs: Vec<NewOffer> = match self.contract.event().from_block(bn.0).query().await {
             Ok(logs) => logs,
             Err(e) => {
-                return Err(anyhow!(
+                return Err(BanyanError::Chain(format!(
                     "Error listening for transaction ({:?}), logs: {:?} ",
-                    &tx_hash,
-                    &e
-                ))
+                    &tx_hash, &e
+                )))
             }
         };
-        let log = logs.first().ok_or_else(|| anyhow!("No logs found"))?;
-        Ok(DealID(log.offer_id.as_u64()))
+        let log = logs
+            .first()
+            .ok_or_else(|| BanyanError::Chain("No logs found".to_string()))?;
+        Ok(DealID(checked_uint(log.offer_id, "DealID")?))
+    }
+
+    /// sign_manifest - Verify a manifest, and sign it as its reviewer with this client's wallet.
+    /// Nothing is sent to the chain.
+    /// # Errors
+    /// * If the client is not configured with a signer
+    /// * As for `DealManifest::sign`
+    pub async fn sign_manifest(&self, manifest: DealManifest) -> Result<DealManifest> {
+        manifest.sign(self.wallet()?).await
+    }
+
+    /// propose_manifest - Propose the deal in a reviewed manifest, once it's checked the
+    /// proposal still rebuilds from the manifest's file and settings
+    /// # Arguments
+    /// * `manifest` - The manifest to submit the proposal of
+    /// * `reviewer` - Who must have signed the manifest, if anyone
+    /// * `gas_limit`, `gas_price` - As for `propose_deal`
+    /// # Errors
+    /// * As for `DealManifest::verify_signed`, or `DealManifest::verify` without a reviewer.
+    ///   Nothing is sent to the chain.
+    /// * As for `propose_deal`
+    pub async fn propose_manifest(
+        &self,
+        manifest: &DealManifest,
+        reviewer: Option<Address>,
+        gas_limit: Option<u64>,
+        gas_price: Option<u64>,
+    ) -> Result<DealID> {
+        let deal = match reviewer {
+            Some(reviewer) => manifest.verify_signed(reviewer).await?,
+            None => manifest.verify_async().await?,
+        };
+        self.propose_deal(deal, gas_limit, gas_price).await
+    }
+
+    /* Off-chain Offers */
+
+    /// offer_deal - Sign a deal offer as its creator, to send to the executor off-chain.
+    /// Nothing is sent to the chain, and the offer can only be proposed to this client's chain and
+    /// contract.
+    /// # Arguments
+    /// * `deal` - The deal to offer
+    /// * `nonce` - Tells apart offers of the same deal
+    /// * `expiry_block` - The block the offer expires at
+    /// # Errors
+    /// * If the client is not configured with a signer
+    /// * `BanyanError::Offer` - If signing fails
+    pub async fn offer_deal(
+        &self,
+        deal: DealProposal,
+        nonce: U256,
+        expiry_block: BlockNum,
+    ) -> Result<SignedDealOffer> {
+        let wallet = self.wallet()?;
+        let offer = DealOffer {
+            proposal: deal,
+            creator: wallet.address(),
+            nonce,
+            expiry_block,
+            chain_id: self.chain_id,
+            escrow: self.contract.address(),
+        };
+        offer.sign(wallet).await
+    }
+
+    /// accept_offer - Evaluate a signed offer as its executor, against this client's validation
+    /// policy and the latest block, and sign an acceptance to send back to the creator
+    /// # Errors
+    /// * If the client is not configured with a signer
+    /// * `BanyanError::Offer` - If the offer is for another chain or contract, or as for
+    ///   `SignedDealOffer::accept`
+    /// * `BanyanError::InvalidProposal` - If the deal breaks the client's validation policy
+    pub async fn accept_offer(&self, offer: &SignedDealOffer) -> Result<SignedOfferAcceptance> {
+        let wallet = self.wallet()?;
+        if offer.offer.chain_id != self.chain_id || offer.offer.escrow != self.contract.address() {
+            return Err(BanyanError::Offer(format!(
+                "The offer is for contract {:?} on chain {}, not {:?} on chain {}",
+                offer.offer.escrow,
+                offer.offer.chain_id,
+                self.contract.address(),
+                self.chain_id
+            )));
+        }
+        let current_block = self.get_latest_block_num().await?;
+        offer
+            .accept(wallet, current_block, &self.validation_policy)
+            .await
+    }
+
+    /// propose_accepted_offer - Propose a deal an executor accepted off-chain, with `propose_deal`
+    /// # Arguments
+    /// * `offer` - The offer this client signed
+    /// * `acceptance` - The executor's acceptance of it
+    /// * `gas_limit`, `gas_price` - As for `propose_deal`
+    /// # Errors
+    /// * As for `SignedOfferAcceptance::verify`. Nothing is sent to the chain.
+    /// * As for `propose_deal`
+    pub async fn propose_accepted_offer(
+        &self,
+        offer: &SignedDealOffer,
+        acceptance: &SignedOfferAcceptance,
+        gas_limit: Option<u64>,
+        gas_price: Option<u64>,
+    ) -> Result<DealID> {
+        let deal = acceptance.verify(offer)?;
+        self.propose_deal(deal, gas_limit, gas_price).await
+    }
+
+    /// The wallet the client signs with
+    fn wallet(&self) -> Result<&LocalWallet> {
+        self.signer
+            .as_ref()
+            .map(|signer| signer.signer())
+            .ok_or_else(|| BanyanError::Chain("No signer available".to_string()))
     }
 
     /// get_offer - get a deal from the Ethereum blockchain by its on-chain ID
@@ -243,12 +514,13 @@ impl EthClient {
     /// * `deal_id` - The Deal ID to get
     /// # Returns
     /// * `Deal` - The on chain Deal
-    pub async fn get_offer(&self, deal_id: DealID) -> Result<OnChainDealInfo, Error> {
-        Ok(self
+    pub async fn get_offer(&self, deal_id: DealID) -> Result<OnChainDealInfo> {
+        let token = self
             .contract
-            .method::<_, OnChainDealInfo>("getOffer", deal_id)?
+            .method::<_, Token>("getOffer", deal_id)?
             .call()
-            .await?)
+            .await?;
+        Ok(self.contract_version.deal_info_layout.decode(token)?)
     }
 
     /* Proof Stuff */
@@ -265,6 +537,8 @@ impl EthClient {
     /// * `gas_price` - An (Optional) Gas Price for the transaction
     /// # Returns
     /// * `BlockNum` - The block number that the proof was posted in
+    /// # Errors
+    /// * `BanyanError::InvalidDealStatus` - If the deal doesn't take proofs any more
     pub async fn post_proof(
         &self,
         deal_id: DealID,
@@ -273,46 +547,149 @@ impl EthClient {
         gas_limit: Option<u64>,
         gas_price: Option<u64>,
     ) -> Result<BlockNum> {
-        if !self.has_signer() {
-            return Err(anyhow!("No signer available"));
-        }
-        dbg!("Posting for deal: {:?}", deal_id.0);
-        // Borrow our signer and contract
-        let signer = self.signer.as_ref().unwrap();
+        self.check_deal_status(deal_id, "post a proof for", DealStatus::accepts_proofs)
+            .await?;
         // Create a new proof
-        dbg!("Initializing new Proof Request");
         let proof: Proof = Proof {
             bao_proof_data,
             deal_id,
             target_block_start,
         };
-        let data = self.contract.encode("saveProof", proof)?;
+        // Older contracts take the target block as an argument, newer ones derive it on chain
+        let data = if self.contract_version.proof_takes_target_block() {
+            self.contract.encode("saveProof", proof)?
+        } else {
+            self.contract
+                .encode("saveProof", (proof.bao_proof_data, proof.deal_id))?
+        };
+        let receipt = self
+            .send_contract_transaction(data, gas_limit, gas_price)
+            .await?;
+        mined_in(&receipt)
+    }
+
+    /// Get the status of a deal
+    /// # Arguments
+    /// * `deal_id` - The Deal ID to look up
+    /// # Returns
+    /// * `DealStatus` - Its status. Deployments that don't track status always return `Non`.
+    pub async fn get_deal_status(&self, deal_id: DealID) -> Result<DealStatus> {
+        Ok(self.get_offer(deal_id).await?.deal_status)
+    }
+
+    /// Refuse locally what the contract would refuse because of a deal's status.
+    /// Deployments that don't track status are always let through.
+    /// # Arguments
+    /// * `deal_id` - The deal about to be acted on
+    /// * `action` - What we're about to do, for the error message
+    /// * `allowed` - Whether the action is allowed in a status
+    /// # Errors
+    /// * `BanyanError::InvalidDealStatus` - If the deal doesn't exist or is in the wrong status
+    pub(crate) async fn check_deal_status(
+        &self,
+        deal_id: DealID,
+        action: &str,
+        allowed: fn(&DealStatus) -> bool,
+    ) -> Result<()> {
+        if !self.contract_version.deal_info_layout.tracks_status() {
+            return Ok(());
+        }
+        let status = self.get_deal_status(deal_id).await?;
+        if status == DealStatus::Non || !allowed(&status) {
+            return Err(BanyanError::InvalidDealStatus {
+                deal_id,
+                status,
+                action: action.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sign a transaction calling the Banyan Contract, send it, and wait for it to be mined.
+    /// The transaction is simulated with `eth_call` first, so one that would revert is never sent.
+    /// # Arguments
+    /// * `data` - The ABI encoded call
+    /// * `gas_limit` - An (Optional) Gas Limit for the transaction. Defaults to 1 million gas
+    /// * `gas_price` - An (Optional) Gas Price for the transaction. Defaults to 70 Gwei
+    /// # Returns
+    /// * `TransactionReceipt` - The receipt of the mined transaction
+    /// # Errors
+    /// * If the client is not configured with a signer
+    /// * `BanyanError::Contract` - If the contract rejects the call, in simulation or once mined
+    /// * If the transaction is dropped
+    pub(crate) async fn send_contract_transaction(
+        &self,
+        data: Bytes,
+        gas_limit: Option<u64>,
+        gas_price: Option<u64>,
+    ) -> Result<TransactionReceipt> {
+        let signer = self
+            .signer
+            .as_ref()
+            .ok_or_else(|| BanyanError::Chain("No signer available".to_string()))?;
         let tx = TransactionRequest::new()
+            .from(signer.address())
             .to(self.contract.address())
-            .data(data)
-            .gas(gas_limit.unwrap_or(1_000_000u64)) // 3 million gas
+            .data(data.clone())
+            .gas(gas_limit.unwrap_or(1_000_000u64))
             .gas_price(gas_price.unwrap_or(70_000_000_000u64)) // 70 Gwei
             .chain_id(self.chain_id);
-        // Sign the transaction and listen for the event
-        dbg!("Signing Proof");
+        // Simulate the transaction, so we don't pay gas for one that's going to revert
+        if let Err(e) = self.provider.call(&tx.clone().into(), None).await {
+            return Err(match revert_data(&e) {
+                Some(revert) => self.contract_version.decode_revert(&data, &revert).into(),
+                None => BanyanError::Chain(format!("Error simulating transaction: {}", e)),
+            });
+        }
         // Attempt to sign the transaction and log any errors
-        let pending_tx = match signer.send_transaction(tx, None).await {
+        let pending_tx = match signer.send_transaction(tx.clone(), None).await {
             Ok(tx) => tx,
             Err(e) => {
-                return Err(anyhow!("Error signing transaction: {}", &e.to_string()));
+                return Err(BanyanError::Chain(format!(
+                    "Error signing transaction: {}",
+                    &e.to_string()
+                )));
             }
         };
-        let receipt = pending_tx.await?;
-        let tx_hash = receipt.as_ref().unwrap().transaction_hash;
-        dbg!("Trxn Hash: {:?}", &tx_hash);
-        let bn = receipt.as_ref().unwrap().block_number.unwrap();
-        dbg!("Block Number: {:?}", &bn);
+        let tx_hash = pending_tx.tx_hash();
+        let receipt = pending_tx
+            .await?
+            .ok_or_else(|| BanyanError::Chain(format!("Transaction {:?} was dropped", &tx_hash)))?;
+        if receipt.status == Some(0.into()) {
+            // Something changed since the simulation. Replay it where it was mined to find out what.
+            let reverted = || BanyanError::Chain(format!("Transaction {:?} reverted", &tx_hash));
+            let block = receipt.block_number.map(|bn| (bn - 1).into());
+            return Err(match self.provider.call(&tx.into(), block).await {
+                Err(e) => match revert_data(&e) {
+                    Some(revert) => self.contract_version.decode_revert(&data, &revert).into(),
+                    None => reverted(),
+                },
+                Ok(_) => reverted(),
+            });
+        }
+        Ok(receipt)
+    }
+
+    /// The Banyan Contract, for calls that live outside this module
+    pub(crate) fn contract(&self) -> &Contract<Provider<Http>> {
+        &self.contract
+    }
 
-        Ok(BlockNum(bn.as_u64()))
+    /// The Eth Provider, for calls that live outside this module
+    pub(crate) fn provider(&self) -> &Provider<Http> {
+        &self.provider
+    }
+
+    /// The Multicall3 contract to batch reads through, if any
+    pub(crate) fn multicall_address(&self) -> Option<Address> {
+        self.multicall_address
     }
 
     pub async fn accept_deal_on_chain(&self) -> Result<OnChainDealInfo> {
-        unimplemented!("https://open.spotify.com/track/0oxYB9GoOIDrdzniNdKC44?si=71f88a0b1afa47a4")
+        // https://open.spotify.com/track/0oxYB9GoOIDrdzniNdKC44?si=71f88a0b1afa47a4
+        Err(BanyanError::Chain(
+            "Accepting deals is not implemented yet".to_string(),
+        ))
     }
 
     /* Chain Primitives */
@@ -324,13 +701,13 @@ impl EthClient {
 
     /// Get the current transaction count
     pub async fn get_current_transaction_count(&self) -> Result<u64> {
-        let signer = self.signer.as_ref().unwrap();
+        let signer = self
+            .signer
+            .as_ref()
+            .ok_or_else(|| BanyanError::Chain("No signer available".to_string()))?;
         let address = signer.address();
-        Ok(self
-            .provider
-            .get_transaction_count(address, None)
-            .await?
-            .as_u64())
+        let count = self.provider.get_transaction_count(address, None).await?;
+        checked_uint(count, "Transaction count")
     }
 
     /// Get the current block hash for a given block number
@@ -339,8 +716,10 @@ impl EthClient {
             .provider
             .get_block(block_number.0)
             .await?
-            .ok_or_else(|| anyhow!("block not found"))?;
-        block.hash.ok_or_else(|| anyhow!("block hash not found"))
+            .ok_or_else(|| BanyanError::Chain(format!("Block {} not found", block_number)))?;
+        block
+            .hash
+            .ok_or_else(|| BanyanError::Chain(format!("Block {} has no hash", block_number)))
     }
 
     /// Get ethereum logs given a filter
@@ -358,8 +737,8 @@ impl EthClient {
             .contract
             .method::<_, U256>("getProofBlock", (deal_id.0, window_num))?
             .call()
-            .await?
-            .as_u64();
+            .await?;
+        let block_num = checked_uint(block_num, "Proof block")?;
         if block_num == 0 {
             Ok(None)
         } else {
@@ -393,7 +772,10 @@ impl EthClient {
 
         // The first two 32 byte words of log data are a pointer and the size of the data.
         // TODO put this in banyan_shared!
-        let data = &block_logs[0].data;
+        let data = match block_logs.first() {
+            Some(log) => &log.data,
+            None => return Ok(None),
+        };
         if data.len() < WORD * 2 {
             return Ok(None);
         }
@@ -409,8 +791,6 @@ impl EthClient {
         let mut a = [0u8; 8];
         a.clone_from_slice(&data[(WORD * 2) - 8..WORD * 2]);
         let data_size = u64::from_be_bytes(a);
-        dbg!("data_size: {:?}", data_size);
-        dbg!("data.len(): {:?}", data.len());
         if data.len() < WORD * 2 + data_size as usize {
             return Ok(None);
         }
@@ -443,30 +823,28 @@ impl EthClient {
     /// Computes the target block number for a given window number, deal start block, and proof frequency. The API validaator uses
     /// this to determine the target_block, which it then uses to get the block hash, and then calls compute_random_block_choice_from_hash(...)
     /// to compute the correct chunk offset and size.
+    /// # Errors
+    /// * `BanyanError::Math` - If the target block would be past the largest block number
     pub fn compute_target_block_start(
         deal_start_block: BlockNum,
         proof_frequency_in_blocks: BlockNum,
         target_window_num: usize,
-    ) -> BlockNum {
-        Add::add(
-            Mul::mul(proof_frequency_in_blocks, target_window_num),
-            deal_start_block,
-        )
+    ) -> Result<BlockNum> {
+        proof_frequency_in_blocks
+            .checked_mul(target_window_num as u64)?
+            .checked_add(deal_start_block)
     }
 
     /* Function to check if the deal is over or not */
     pub fn deal_over(current_block_num: BlockNum, deal_info: OnChainDealInfo) -> bool {
-        current_block_num > Add::add(deal_info.deal_start_block, deal_info.deal_length_in_blocks)
+        current_block_num > deal_info.get_final_block()
     }
 
     // Below are a range of functions that help with our testing framework
 
     /// Helper for computing file length
-    pub fn file_len(file_name: &str) -> usize {
-        let mut file_content = Vec::new();
-        let mut file = File::open(&file_name).expect("Unable to open file");
-        file.read_to_end(&mut file_content).expect("Unable to read");
-        file_content.len()
+    pub fn file_len(file_name: &str) -> Result<usize> {
+        Ok(std::fs::metadata(file_name)?.len() as usize)
     }
 
     /// Helper for testing functions that create proofs
@@ -493,12 +871,12 @@ impl EthClient {
             obao_cursor,
             file_length,
         )
-        .await
-        .unwrap();
+        .await?;
 
         if !quality {
-            let last_index = slice.len() - 1;
-            slice[last_index] ^= 1;
+            if let Some(last) = slice.last_mut() {
+                *last ^= 1;
+            }
         }
         Ok((hash, Bytes::from(slice)))
     }
@@ -507,28 +885,68 @@ impl EthClient {
     /// # Arguments
     /// * `deal_start_block` - The block number that the deal started at
     /// * `proof_frequency_in_blocks` - The frequency at which proofs are submitted in the deal
+    /// # Errors
+    /// * `BanyanError::Math` - If the deal hasn't started yet, or the proof frequency is 0
     pub async fn compute_target_window(
         &self,
         deal_start_block: BlockNum,
         proof_frequency_in_blocks: BlockNum,
     ) -> Result<usize> {
         let current_block_num = self.get_latest_block_num().await?;
-        let offset: BlockNum = Sub::sub(current_block_num, deal_start_block);
-        //assert!(offset < deal_length_in_blocks);
-        //assert_eq!(Rem::rem(offset, proof_frequency_in_blocks), BlockNum(0));
-        let window_num = Div::div(offset, proof_frequency_in_blocks);
-        Ok(usize::try_from(window_num.0)?)
+        let offset = current_block_num.checked_sub(deal_start_block)?;
+        let window_num = offset.checked_div(proof_frequency_in_blocks)?;
+        usize::try_from(window_num.0).map_err(|e| BanyanError::Parse(e.to_string()))
+    }
+}
+
+/// The block number a mined transaction made it into
+pub(crate) fn mined_in(receipt: &TransactionReceipt) -> Result<BlockNum> {
+    receipt
+        .block_number
+        .map(|bn| BlockNum(bn.as_u64()))
+        .ok_or_else(|| {
+            BanyanError::Chain(format!(
+                "Transaction {:?} is not in a block",
+                &receipt.transaction_hash
+            ))
+        })
+}
+
+/// revert_data - Dig the data a call reverted with out of a provider error
+/// # Returns
+/// * `Bytes` - The revert data, or None if the error isn't a revert.
+///   Reverts without any data (a bare `revert()`) come back empty.
+pub(crate) fn revert_data(err: &ProviderError) -> Option<Bytes> {
+    let err = match err {
+        ProviderError::JsonRpcClientError(err) => match err.downcast_ref::<HttpClientError>() {
+            Some(HttpClientError::JsonRpcError(err)) => err,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    // Nodes disagree about where the data goes - Geth uses a hex string, others nest it
+    let data = match &err.data {
+        Some(Value::String(data)) => Some(data.as_str()),
+        Some(Value::Object(data)) => data.get("data").and_then(Value::as_str),
+        _ => None,
+    };
+    match data {
+        Some(data) => data.parse().ok(),
+        None if err.message.contains("revert") => Some(Bytes::default()),
+        None => None,
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    #[cfg(feature = "test-support")]
+    use crate::test_chain::TestChain;
 
     #[tokio::test]
     /// Test Init a new eth client from the environment.
     /// The environment variables for all fields must be set for this test to pass
-    async fn eth_client_new() -> Result<(), anyhow::Error> {
+    async fn eth_client_new() -> Result<(), BanyanError> {
         // Init a new EthClient with our environment variables
         let eth_client = EthClient::default();
         if !eth_client.has_signer() {
@@ -542,7 +960,7 @@ mod test {
 
     #[tokio::test]
     /// Test sending a deal Proposal
-    async fn send_deal_proposal() -> Result<(), anyhow::Error> {
+    async fn send_deal_proposal() -> Result<(), BanyanError> {
         use crate::deals::*;
         // Open a file to build our DealProposal
         let file = std::fs::File::open("./abi/escrow.json").unwrap();
@@ -566,7 +984,7 @@ mod test {
     }
 
     #[tokio::test]
-    async fn post_proof_to_chain() -> Result<(), anyhow::Error> {
+    async fn post_proof_to_chain() -> Result<(), BanyanError> {
         let mut file = File::open("../Rust-Chainlink-EA-API/test_files/ethereum.pdf").unwrap();
         let eth_client = EthClient::default();
 
@@ -582,10 +1000,15 @@ mod test {
             deal.deal_start_block,
             deal.proof_frequency_in_blocks,
             target_window,
-        );
+        )?;
         // create a proof using the same file we used to create the deal
         let (_hash, proof) = eth_client
-            .create_proof_helper(target_block, &mut file, deal.file_size.as_u64(), true)
+            .create_proof_helper(
+                target_block,
+                &mut file,
+                checked_uint(deal.file_size, "File size")?,
+                true,
+            )
             .await
             .expect("Failed to create proof");
 
@@ -605,8 +1028,259 @@ mod test {
         Ok(())
     }
 
+    /// Propose a deal for `test_files/ethereum.pdf` on the test chain
+    #[cfg(feature = "test-support")]
+    async fn propose_test_deal(chain: &TestChain, client: &EthClient) -> Result<DealID> {
+        use crate::deals::*;
+        let file = File::open("test_files/ethereum.pdf")?;
+        let deal = DealProposalBuilder::default()
+            .with_file(file)
+            .with_executor_address(format!("{:?}", chain.executor().address()))
+            .with_erc20_token_denomination(format!("{:?}", chain.token_address()))
+            .with_deal_length_in_blocks(100)
+            .with_price_per_tib("1".parse()?)
+            .build()?;
+        client.propose_deal(deal, None, None).await
+    }
+
+    #[tokio::test]
+    /// A node error isn't mistaken for a contract without `version()`
+    async fn detection_doesnt_guess_when_the_node_fails() {
+        use std::io::{BufRead, BufReader, Write};
+        // A node that fails every eth_call, and says there's no code anywhere
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        Some(len) => content_length = len.trim().parse().unwrap(),
+                        None if line.trim().is_empty() => break,
+                        None => {}
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let response = match request["method"].as_str() {
+                    Some("eth_call") => serde_json::json!({"jsonrpc": "2.0", "id": request["id"],
+                        "error": {"code": -32000, "message": "header not found"}}),
+                    _ => serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": "0x"}),
+                }
+                .to_string();
+                let mut stream = reader.into_inner();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+        let client = EthClient::new(url, String::new(), None, None, Address::zero()).unwrap();
+        let err = client.detect_contract_version().await.err().unwrap();
+        assert!(err.to_string().contains("header not found"), "{}", err);
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    /// Test proposing a deal against the real Escrow bytecode
+    async fn propose_deal_on_test_chain() -> Result<(), BanyanError> {
+        let chain = TestChain::spawn().await?;
+        let client = chain.creator_client().await?;
+        // The test chain runs the latest Escrow build, which predates `version()`
+        assert_eq!(client.contract_version().version, "0.3.0");
+        let deal_id = propose_test_deal(&chain, &client).await?;
+        assert_eq!(deal_id, DealID(1));
+
+        let deal = client.get_offer(deal_id).await?;
+        assert_eq!(deal.deal_length_in_blocks, BlockNum(100));
+        assert_eq!(deal.proof_frequency_in_blocks, BlockNum(5));
+        assert_eq!(
+            deal.file_size,
+            U256::from(EthClient::file_len("test_files/ethereum.pdf")?)
+        );
+        assert_eq!(deal.creator_address, chain.creator().address());
+        assert_eq!(deal.executor_address, chain.executor().address());
+        assert_eq!(deal.erc20_token_denomination, chain.token_address());
+        assert_eq!(deal.deal_status, DealStatus::DealCreated);
+
+        // Deal IDs keep counting up
+        assert_eq!(propose_test_deal(&chain, &client).await?, DealID(2));
+        Ok(())
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    /// Test an offer signed off-chain, accepted by the executor and then proposed as usual
+    async fn propose_accepted_offer_on_test_chain() -> Result<(), BanyanError> {
+        use crate::deals::*;
+        let chain = TestChain::spawn().await?;
+        let (creator, executor) = (
+            chain.creator_client().await?,
+            chain.executor_client().await?,
+        );
+        let deal = DealProposalBuilder::default()
+            .with_file(File::open("test_files/ethereum.pdf")?)
+            .with_executor_address(format!("{:?}", chain.executor().address()))
+            .with_erc20_token_denomination(format!("{:?}", chain.token_address()))
+            .with_price_per_tib("1".parse()?)
+            .build()?;
+        let expiry_block = chain.block_number().checked_add(BlockNum(10))?;
+        let offer = creator
+            .offer_deal(deal.clone(), U256::one(), expiry_block)
+            .await?;
+
+        // The creator can't accept its own offer
+        assert!(creator.accept_offer(&offer).await.is_err());
+        let acceptance = executor.accept_offer(&offer).await?;
+        let deal_id = creator
+            .propose_accepted_offer(&offer, &acceptance, None, None)
+            .await?;
+        let on_chain = executor.get_offer(deal_id).await?;
+        assert_eq!(on_chain.blake3_checksum, deal.blake3_checksum);
+        assert_eq!(on_chain.creator_address, chain.creator().address());
+        Ok(())
+    }
+
+    #[cfg(feature = "test-support")]
     #[tokio::test]
-    async fn check_good_proof() -> Result<(), anyhow::Error> {
+    /// Test a manifest signed by its reviewer, and only proposed with the reviewer's signature
+    async fn propose_signed_manifest_on_test_chain() -> Result<(), BanyanError> {
+        use crate::{deals::*, manifest::DealManifest};
+        let chain = TestChain::spawn().await?;
+        let (creator, reviewer) = (
+            chain.creator_client().await?,
+            chain.executor_client().await?,
+        );
+        let builder = DealProposalBuilder::default()
+            .with_executor_address(format!("{:?}", chain.executor().address()))
+            .with_erc20_token_denomination(format!("{:?}", chain.token_address()))
+            .with_price_per_tib("1".parse()?);
+        let manifest = DealManifest::build("test_files/ethereum.pdf", builder)?;
+        let reviewer_address = Some(chain.executor().address());
+        assert!(creator
+            .propose_manifest(&manifest, reviewer_address, None, None)
+            .await
+            .is_err());
+
+        let signed = reviewer.sign_manifest(manifest).await?;
+        let deal_id = creator
+            .propose_manifest(&signed, reviewer_address, None, None)
+            .await?;
+        let on_chain = creator.get_offer(deal_id).await?;
+        assert_eq!(on_chain.blake3_checksum, signed.proposal.blake3_checksum);
+        Ok(())
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    /// Test posting a proof against the real Escrow bytecode and reading it back from the logs
+    async fn post_proof_on_test_chain() -> Result<(), BanyanError> {
+        let chain = TestChain::spawn().await?;
+        let deal_id = propose_test_deal(&chain, &chain.creator_client().await?).await?;
+        let executor = chain.executor_client().await?;
+        let deal = executor.get_offer(deal_id).await?;
+
+        // The proof lands in the next block, so target that block's window
+        chain.mine_blocks(3);
+        let proof_block = chain.block_number().checked_add(BlockNum(1))?;
+        let target_window =
+            ((proof_block.0 - deal.deal_start_block.0) / deal.proof_frequency_in_blocks.0) as usize;
+        let target_block = EthClient::compute_target_block_start(
+            deal.deal_start_block,
+            deal.proof_frequency_in_blocks,
+            target_window,
+        )?;
+        chain.start_proof_window(deal_id, target_window as u64)?;
+
+        let mut file = File::open("test_files/ethereum.pdf")?;
+        let (_hash, proof) = executor
+            .create_proof_helper(
+                target_block,
+                &mut file,
+                checked_uint(deal.file_size, "File size")?,
+                true,
+            )
+            .await?;
+        let block_num = executor
+            .post_proof(deal_id, proof.clone(), target_block, None, None)
+            .await?;
+        assert_eq!(block_num, proof_block);
+        assert_eq!(
+            executor
+                .get_proof_block_num_from_window(deal_id, target_window as u64)
+                .await?,
+            Some(block_num)
+        );
+        assert_eq!(
+            executor.get_proof_from_logs(block_num, deal_id).await?,
+            Some(proof.to_vec())
+        );
+        // There's nothing to find in a block without a proof
+        assert_eq!(
+            executor
+                .get_proof_from_logs(block_num.checked_add(BlockNum(1))?, deal_id)
+                .await?,
+            None
+        );
+
+        // Without the workaround the next window's proof reverts, which must surface as an error
+        chain.mine_blocks(deal.proof_frequency_in_blocks.0);
+        assert!(executor
+            .post_proof(deal_id, proof, target_block, None, None)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    /// A transaction the contract would reject is decoded from a simulation, and never sent
+    async fn reverts_are_caught_before_sending() -> Result<(), BanyanError> {
+        use crate::contracts::ContractError;
+
+        let chain = TestChain::spawn().await?;
+        let deal_id = propose_test_deal(&chain, &chain.creator_client().await?).await?;
+        let executor = chain.executor_client().await?;
+        let block_num = chain.block_number();
+
+        // The Escrow contract only takes proofs for windows that already have one
+        let err = executor
+            .post_proof(deal_id, vec![0u8; 64].into(), BlockNum(0), None, None)
+            .await
+            .unwrap_err();
+        match &err {
+            BanyanError::Contract(ContractError::Reverted { call, reason }) => {
+                assert_eq!(call.function, "saveProof");
+                assert_eq!(call.args[1], Token::Uint(deal_id.0.into()));
+                assert_eq!(reason, "Proof already submitted");
+            }
+            other => panic!("Expected a revert reason, got {:?}", other),
+        }
+        assert!(err
+            .to_string()
+            .starts_with("Contract error: saveProof(0x0000"));
+
+        let err = executor.withdraw_link(None, None).await.unwrap_err();
+        assert!(err.is_unauthorized());
+        assert_eq!(
+            err.to_string(),
+            "Contract error: withdrawLink() reverted with UNAUTHORIZED()"
+        );
+
+        // Nothing was mined
+        assert_eq!(chain.block_number(), block_num);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn check_good_proof() -> Result<(), BanyanError> {
         dotenv().ok();
         let mut file = File::open("../Rust-Chainlink-EA-API/test_files/ethereum.pdf").unwrap();
         let eth_client = EthClient::default();
@@ -622,17 +1296,22 @@ mod test {
             deal.deal_start_block,
             deal.proof_frequency_in_blocks,
             target_window,
-        );
+        )?;
         // create a proof using the same file we used to create the deal
         let (hash, proof) = eth_client
-            .create_proof_helper(target_block, &mut file, deal.file_size.as_u64(), true)
+            .create_proof_helper(
+                target_block,
+                &mut file,
+                checked_uint(deal.file_size, "File size")?,
+                true,
+            )
             .await
             .expect("Failed to create proof");
 
         let target_block_hash = eth_client.get_block_hash_from_num(target_block).await?;
         let (chunk_offset, chunk_size) = proofs::compute_random_block_choice_from_hash(
             target_block_hash,
-            deal.file_size.as_u64(),
+            checked_uint(deal.file_size, "File size")?,
         );
 
         let proof_vec = proof.to_vec();
@@ -649,7 +1328,7 @@ mod test {
     }
 
     #[tokio::test]
-    async fn check_bad_proof() -> Result<(), anyhow::Error> {
+    async fn check_bad_proof() -> Result<(), BanyanError> {
         dotenv().ok();
         let mut file = File::open("../Rust-Chainlink-EA-API/test_files/ethereum.pdf").unwrap();
         let eth_client = EthClient::default();
@@ -665,17 +1344,22 @@ mod test {
             deal.deal_start_block,
             deal.proof_frequency_in_blocks,
             target_window,
-        );
+        )?;
         // create a proof using the same file we used to create the deal
         let (hash, proof) = eth_client
-            .create_proof_helper(target_block, &mut file, deal.file_size.as_u64(), false)
+            .create_proof_helper(
+                target_block,
+                &mut file,
+                checked_uint(deal.file_size, "File size")?,
+                false,
+            )
             .await
             .expect("Failed to create proof");
 
         let target_block_hash = eth_client.get_block_hash_from_num(target_block).await?;
         let (chunk_offset, chunk_size) = proofs::compute_random_block_choice_from_hash(
             target_block_hash,
-            deal.file_size.as_u64(),
+            checked_uint(deal.file_size, "File size")?,
         );
 
         let proof_vec = proof.to_vec();
@@ -0,0 +1,416 @@
+use crate::{
+    chainlink::VerificationResponse,
+    error::{BanyanError, Result},
+    eth::EthClient,
+    proofs::{compute_random_block_choice_from_hash, window::get_num_windows},
+    types::*,
+};
+use ethers::types::H256;
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::{convert::Infallible, fmt::Display, io::Cursor, net::SocketAddr, sync::Arc};
+
+/*
+ * A Chainlink External Adapter that verifies every proof window of a deal.
+ * Status: The Banyan Contract asks Chainlink to verify a deal, and Chainlink asks us.
+ * We re-derive the target block of each window, check the proof posted for it, and report
+ * how many windows passed. The response's `data` is what gets passed on to `fulfill`.
+ * `getProofBlockNumbers` only returns a single number on deployed contracts, so proof blocks are
+ * looked up window by window with `getProofBlock`, batched through Multicall3.
+ */
+
+/// AdapterRequest - The request a Chainlink node sends to an External Adapter
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AdapterRequest {
+    /// The ID of the Chainlink job run, echoed back in the response
+    pub id: String,
+    /// The parameters of the request
+    pub data: AdapterRequestData,
+}
+
+/// AdapterRequestData - The parameters of an AdapterRequest
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AdapterRequestData {
+    /// The deal to verify. The contract sends this as a string, so we accept either.
+    #[serde(alias = "offer_id", alias = "offerId", deserialize_with = "de_deal_id")]
+    pub deal_id: DealID,
+}
+
+/// AdapterResponse - What an External Adapter responds to a Chainlink node with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdapterResponse {
+    /// The ID of the Chainlink job run we're responding to
+    #[serde(rename = "jobRunID")]
+    pub job_run_id: String,
+    /// The outcome of verifying the deal, if we managed to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<VerificationResponse>,
+    /// Set to "errored" if we didn't manage to verify the deal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// What went wrong, if we didn't manage to verify the deal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The HTTP status code of the response
+    #[serde(rename = "statusCode")]
+    pub status_code: u16,
+}
+
+impl AdapterResponse {
+    /// A successful response
+    fn success(job_run_id: String, data: VerificationResponse) -> Self {
+        AdapterResponse {
+            job_run_id,
+            data: Some(data),
+            status: None,
+            error: None,
+            status_code: StatusCode::OK.as_u16(),
+        }
+    }
+
+    /// An errored response
+    fn errored(job_run_id: String, status_code: StatusCode, error: impl Display) -> Self {
+        AdapterResponse {
+            job_run_id,
+            data: None,
+            status: Some("errored".to_string()),
+            error: Some(error.to_string()),
+            status_code: status_code.as_u16(),
+        }
+    }
+
+    /// The HTTP response to send back to the Chainlink node
+    fn into_http(self) -> Response<Body> {
+        let status =
+            StatusCode::from_u16(self.status_code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        // A struct of strings and numbers always serializes
+        let body = serde_json::to_string(&self).unwrap_or_default();
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        response
+    }
+}
+
+/// Deserialize a DealID from either a number or a string
+fn de_deal_id<'de, D>(deserializer: D) -> Result<DealID, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => n
+            .as_u64()
+            .map(DealID)
+            .ok_or_else(|| de::Error::custom(format!("Invalid deal ID: {}", n))),
+        Value::String(s) => s
+            .parse::<u64>()
+            .map(DealID)
+            .map_err(|_| de::Error::custom(format!("Invalid deal ID: {:?}", s))),
+        other => Err(de::Error::custom(format!("Invalid deal ID: {}", other))),
+    }
+}
+
+/// verify_window - Check the proof posted for a single window
+/// # Arguments
+/// * `proof` - The proof posted for the window
+/// * `target_block_hash` - The hash of the window's target block, which picks the chunk to prove
+/// * `blake3_checksum` - The blake3 hash of the deal's file
+/// * `file_size` - The size of the deal's file
+/// # Returns
+/// * `bool` - Whether the proof is valid. An empty file has no chunks to prove, so never is.
+pub fn verify_window(
+    proof: &[u8],
+    target_block_hash: H256,
+    blake3_checksum: Blake3Hash,
+    file_size: u64,
+) -> bool {
+    if file_size == 0 {
+        return false;
+    }
+    let (chunk_offset, chunk_size) =
+        compute_random_block_choice_from_hash(target_block_hash, file_size);
+    EthClient::check_if_merkle_proof_is_valid(
+        Cursor::new(&proof.to_vec()),
+        blake3_checksum.hash(),
+        chunk_offset,
+        chunk_size,
+    )
+    .unwrap_or(false)
+}
+
+/// verify_deal - Check the proof posted for every window of a deal
+/// # Arguments
+/// * `client` - An EthClient connected to the chain the deal is on
+/// * `deal_id` - The deal to verify
+/// # Returns
+/// * `VerificationResponse` - How many of the deal's windows have a valid proof
+/// # Errors
+/// * `BanyanError::Parse` - If the deal's file size doesn't fit in a u64
+pub async fn verify_deal(client: &EthClient, deal_id: DealID) -> Result<VerificationResponse> {
+    let deal = client.get_offer(deal_id).await?;
+    let file_size = checked_uint(deal.file_size, "File size")?;
+    let num_windows = get_num_windows(deal.deal_length_in_blocks, deal.proof_frequency_in_blocks)?;
+    let windows: Vec<u64> = (0..num_windows as u64).collect();
+    let proof_blocks = client.get_proof_blocks(deal_id, &windows).await?;
+    let mut success_count = 0;
+    for (window, proof_block) in proof_blocks.into_iter().enumerate() {
+        // A window without a proof just doesn't count
+        let proof_block = match proof_block? {
+            Some(proof_block) => proof_block,
+            None => continue,
+        };
+        let proof = match client.get_proof_from_logs(proof_block, deal_id).await? {
+            Some(proof) => proof,
+            None => continue,
+        };
+        let target_block = EthClient::compute_target_block_start(
+            deal.deal_start_block,
+            deal.proof_frequency_in_blocks,
+            window,
+        )?;
+        let target_block_hash = client.get_block_hash_from_num(target_block).await?;
+        if verify_window(&proof, target_block_hash, deal.blake3_checksum, file_size) {
+            success_count += 1;
+        }
+    }
+    Ok(VerificationResponse {
+        deal_id,
+        success_count,
+        num_windows: num_windows as u64,
+        status: StatusCode::OK.as_u16(),
+        result: format!("{}/{} windows verified", success_count, num_windows),
+    })
+}
+
+/// ExternalAdapter - An HTTP server that verifies deals for Chainlink
+pub struct ExternalAdapter {
+    /// The client used to read deals and proofs from the chain
+    client: Arc<EthClient>,
+}
+
+impl ExternalAdapter {
+    /// Create a new ExternalAdapter
+    /// # Arguments
+    /// * `client` - An EthClient connected to the chain the deals are on
+    pub fn new(client: EthClient) -> Self {
+        ExternalAdapter {
+            client: Arc::new(client),
+        }
+    }
+
+    /// Handle a single request from a Chainlink node
+    pub async fn handle(&self, request: AdapterRequest) -> AdapterResponse {
+        match verify_deal(&self.client, request.data.deal_id).await {
+            Ok(data) => AdapterResponse::success(request.id, data),
+            Err(e) => AdapterResponse::errored(request.id, StatusCode::INTERNAL_SERVER_ERROR, e),
+        }
+    }
+
+    /// Handle the body of a request, which might not be a valid AdapterRequest
+    async fn handle_body(&self, body: &[u8]) -> AdapterResponse {
+        match serde_json::from_slice::<AdapterRequest>(body) {
+            Ok(request) => self.handle(request).await,
+            Err(e) => {
+                // Echo the job run ID back if there is one, so the node can match the error up
+                let job_run_id = serde_json::from_slice::<Value>(body)
+                    .ok()
+                    .and_then(|v| v.get("id").and_then(Value::as_str).map(str::to_string))
+                    .unwrap_or_default();
+                AdapterResponse::errored(job_run_id, StatusCode::BAD_REQUEST, e)
+            }
+        }
+    }
+
+    /// Bind to an address and serve requests in the background
+    /// # Arguments
+    /// * `addr` - The address to listen on. Use port 0 to pick any free port.
+    /// # Returns
+    /// * `SocketAddr` - The address we're listening on
+    pub fn spawn(self, addr: SocketAddr) -> Result<SocketAddr> {
+        let adapter = Arc::new(self);
+        let make_service = make_service_fn(move |_| {
+            let adapter = adapter.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let adapter = adapter.clone();
+                    async move {
+                        let response = if request.method() == Method::POST {
+                            let body = hyper::body::to_bytes(request.into_body()).await?;
+                            adapter.handle_body(&body).await
+                        } else {
+                            AdapterResponse::errored(
+                                String::new(),
+                                StatusCode::METHOD_NOT_ALLOWED,
+                                "Only POST requests are supported",
+                            )
+                        };
+                        Ok::<_, hyper::Error>(response.into_http())
+                    }
+                }))
+            }
+        });
+        let server = Server::try_bind(&addr)
+            .map_err(|e| {
+                BanyanError::Io(std::io::Error::new(
+                    std::io::ErrorKind::AddrNotAvailable,
+                    format!("Failed to bind to {}: {}", addr, e),
+                ))
+            })?
+            .serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        Ok(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::{gen_obao, gen_proof};
+    use std::fs::File;
+
+    /// Generate the proof for a file at a given target block hash
+    async fn proof_for(path: &str, target_block_hash: H256) -> Result<(Vec<u8>, Blake3Hash, u64)> {
+        let mut file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let (obao, hash) = gen_obao(&mut file)?;
+        let proof = gen_proof(
+            BlockNum(0),
+            target_block_hash,
+            File::open(path)?,
+            Cursor::new(obao),
+            file_size,
+        )
+        .await?;
+        Ok((proof, Blake3Hash(hash), file_size))
+    }
+
+    #[tokio::test]
+    async fn verify_window_with_test_files() -> Result<()> {
+        let target_block_hash = H256::from_low_u64_be(7);
+        let (proof, hash, file_size) =
+            proof_for("test_files/ethereum.pdf", target_block_hash).await?;
+        assert!(verify_window(&proof, target_block_hash, hash, file_size));
+
+        // A tampered proof fails
+        let mut tampered = proof.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(!verify_window(
+            &tampered,
+            target_block_hash,
+            hash,
+            file_size
+        ));
+        // A proof for another window's chunk fails
+        assert!(!verify_window(
+            &proof,
+            H256::from_low_u64_be(8),
+            hash,
+            file_size
+        ));
+        // A proof of another file fails
+        let (other, _, _) = proof_for("test_files/filecoin.pdf", target_block_hash).await?;
+        assert!(!verify_window(&other, target_block_hash, hash, file_size));
+        assert!(!verify_window(&[], target_block_hash, hash, file_size));
+        // An empty file has nothing to prove, rather than panicking on the chunk choice
+        assert!(!verify_window(&proof, target_block_hash, hash, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_requests() {
+        let request: AdapterRequest =
+            serde_json::from_str(r#"{"id": "run", "data": {"deal_id": "12"}}"#).unwrap();
+        assert_eq!(request.id, "run");
+        assert_eq!(request.data.deal_id, DealID(12));
+        let request: AdapterRequest =
+            serde_json::from_str(r#"{"id": "run", "data": {"offerId": 3}}"#).unwrap();
+        assert_eq!(request.data.deal_id, DealID(3));
+        assert!(serde_json::from_str::<AdapterRequest>(
+            r#"{"id": "run", "data": {"deal_id": "x"}}"#
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    /// Test verifying a deal over HTTP, with some windows proven, some not, and one proven wrong
+    async fn adapter_on_test_chain() -> Result<()> {
+        use crate::{deals::DealProposalBuilder, test_chain::TestChain};
+        use ethers::signers::Signer;
+
+        let chain = TestChain::spawn().await?;
+        let deal = DealProposalBuilder::default()
+            .with_file(File::open("test_files/ethereum.pdf")?)
+            .with_executor_address(format!("{:?}", chain.executor().address()))
+            .with_erc20_token_denomination(format!("{:?}", chain.token_address()))
+            .with_deal_length_in_blocks(20)
+            .with_price_per_tib("1".parse()?)
+            .build()?;
+        let deal_id = chain
+            .creator_client()
+            .await?
+            .propose_deal(deal, None, None)
+            .await?;
+        let executor = chain.executor_client().await?;
+        let deal = executor.get_offer(deal_id).await?;
+        let frequency = deal.proof_frequency_in_blocks;
+
+        for (window, quality) in [(1u64, true), (2, true), (3, false)] {
+            // Post in the second block of the window
+            let target_block = deal
+                .deal_start_block
+                .checked_add(frequency.checked_mul(window)?)?;
+            chain.mine_to(target_block.checked_add(BlockNum(1))?);
+            chain.start_proof_window(deal_id, window)?;
+            let mut file = File::open("test_files/ethereum.pdf")?;
+            let (_, proof) = executor
+                .create_proof_helper(
+                    target_block,
+                    &mut file,
+                    checked_uint(deal.file_size, "File size")?,
+                    quality,
+                )
+                .await?;
+            executor
+                .post_proof(deal_id, proof, target_block, None, None)
+                .await?;
+        }
+
+        let addr = ExternalAdapter::new(executor).spawn(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+        let client = reqwest::Client::new();
+        let response: AdapterResponse = client
+            .post(format!("http://{}", addr))
+            .json(&serde_json::json!({"id": "run", "data": {"deal_id": deal_id.to_string()}}))
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(response.job_run_id, "run");
+        assert_eq!(response.status_code, 200);
+        let data = response.data.unwrap();
+        assert_eq!(data.deal_id, deal_id);
+        assert_eq!(data.success_count, 2);
+        assert_eq!(data.num_windows, 4);
+
+        // Bad requests are reported back to the node
+        let response = client
+            .post(format!("http://{}", addr))
+            .body(r#"{"id": "run", "data": {}}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let response: AdapterResponse = response.json().await?;
+        assert_eq!(response.job_run_id, "run");
+        assert_eq!(response.status.as_deref(), Some("errored"));
+        Ok(())
+    }
+}
@@ -1,20 +1,29 @@
-use anyhow::Result;
+use crate::error::{BanyanError, Result};
 use cid::Cid;
 use std::fs::File;
 use std::io::BufReader;
 
 pub async fn write_bytes_to_ipfs(_bytes: Vec<u8>) -> Result<Cid> {
-    unimplemented!("idk what the right way to do this is??");
+    // idk what the right way to do this is??
+    Err(not_implemented("Writing bytes to IPFS"))
 }
 
 pub async fn get_handle_for_cid(_cid: Cid) -> Result<BufReader<File>> {
-    unimplemented!("https://open.spotify.com/track/2enPRFda84VE2wtI8c86Uf?si=714947276bc3400b")
+    // https://open.spotify.com/track/2enPRFda84VE2wtI8c86Uf?si=714947276bc3400b
+    Err(not_implemented("Opening content by CID"))
 }
 
 pub async fn _do_we_have_this_cid_locally(_cid: Cid) -> Result<bool> {
-    unimplemented!("https://open.spotify.com/track/4vjvx7Zxkb4AltGcZ0BBvI?si=3c7928800a1f4f3b")
+    // https://open.spotify.com/track/4vjvx7Zxkb4AltGcZ0BBvI?si=3c7928800a1f4f3b
+    Err(not_implemented("Checking for content locally"))
 }
 
 pub async fn download_file_from_ipfs(_cid: Cid, _length: u64) -> Result<()> {
-    unimplemented!("https://open.spotify.com/track/0oxYB9GoOIDrdzniNdKC44?si=71f88a0b1afa47a4")
+    // https://open.spotify.com/track/0oxYB9GoOIDrdzniNdKC44?si=71f88a0b1afa47a4
+    Err(not_implemented("Downloading from IPFS"))
+}
+
+/// The error for IPFS support we haven't written yet
+fn not_implemented(what: &str) -> BanyanError {
+    BanyanError::Ipfs(format!("{} is not implemented yet", what))
 }
@@ -0,0 +1,238 @@
+use crate::{
+    error::{BanyanError, Result},
+    types::{Blake3Hash, BlockNum, CidWrapper, DealID},
+};
+use cid::Cid;
+use sled::IVec;
+
+/*
+ * Encoding identifiers as sled keys.
+ * Status: Numbers are big-endian, so sled iterates them in numeric order, and hashes and CIDs
+ * are their raw bytes. A pair of keys is the first key followed by the second, so all the keys
+ * sharing a first element are next to each other: a deal's windows can be scanned by the deal's
+ * key as a prefix, or a range of them by the pairs at each end. Only a key of a fixed length can
+ * come first, or it'd be ambiguous where it ends.
+ */
+
+/// Key - Something that can be a sled key, and read back from one
+pub trait Key: Sized {
+    /// The key
+    fn to_key(&self) -> IVec;
+
+    /// from_key - Read a key back
+    /// # Errors
+    /// * `BanyanError::Parse` - If the bytes aren't a key of this type
+    fn from_key(key: &[u8]) -> Result<Self>;
+}
+
+/// FixedLengthKey - A key that's always the same length, so it can be followed by another
+pub trait FixedLengthKey: Key {
+    /// The length of every key, in bytes
+    const KEY_LEN: usize;
+}
+
+/// Check a key is the length a type's keys always are
+fn fixed<const N: usize>(name: &str, key: &[u8]) -> Result<[u8; N]> {
+    key.try_into().map_err(|_| {
+        BanyanError::Parse(format!(
+            "Invalid {} key: expected {} bytes, got {}",
+            name,
+            N,
+            key.len()
+        ))
+    })
+}
+
+impl Key for DealID {
+    fn to_key(&self) -> IVec {
+        IVec::from(&self.0.to_be_bytes())
+    }
+
+    fn from_key(key: &[u8]) -> Result<Self> {
+        fixed("DealID", key).map(|bytes| DealID(u64::from_be_bytes(bytes)))
+    }
+}
+
+impl FixedLengthKey for DealID {
+    const KEY_LEN: usize = 8;
+}
+
+impl Key for BlockNum {
+    fn to_key(&self) -> IVec {
+        IVec::from(&self.0.to_be_bytes())
+    }
+
+    fn from_key(key: &[u8]) -> Result<Self> {
+        fixed("BlockNum", key).map(|bytes| BlockNum(u64::from_be_bytes(bytes)))
+    }
+}
+
+impl FixedLengthKey for BlockNum {
+    const KEY_LEN: usize = 8;
+}
+
+impl Key for Blake3Hash {
+    fn to_key(&self) -> IVec {
+        IVec::from(self.as_bytes())
+    }
+
+    fn from_key(key: &[u8]) -> Result<Self> {
+        fixed::<32>("Blake3Hash", key).map(|bytes| Blake3Hash(bytes.into()))
+    }
+}
+
+impl FixedLengthKey for Blake3Hash {
+    const KEY_LEN: usize = 32;
+}
+
+/// CIDs vary in length with their hash, so can only come last in a pair
+impl Key for CidWrapper {
+    fn to_key(&self) -> IVec {
+        IVec::from(self.0.to_bytes())
+    }
+
+    fn from_key(key: &[u8]) -> Result<Self> {
+        Cid::try_from(key)
+            .map(CidWrapper)
+            .map_err(|e| BanyanError::Parse(format!("Invalid CID key: {}", e)))
+    }
+}
+
+/// A pair of keys, e.g. (DealID, BlockNum) for the windows of each deal, or (BlockNum, DealID)
+/// for the deals due at each block
+impl<A: FixedLengthKey, B: Key> Key for (A, B) {
+    fn to_key(&self) -> IVec {
+        let mut key = self.0.to_key().to_vec();
+        key.extend_from_slice(&self.1.to_key());
+        key.into()
+    }
+
+    fn from_key(key: &[u8]) -> Result<Self> {
+        if key.len() < A::KEY_LEN {
+            return Err(BanyanError::Parse(format!(
+                "Invalid pair key: expected at least {} bytes, got {}",
+                A::KEY_LEN,
+                key.len()
+            )));
+        }
+        let (a, b) = key.split_at(A::KEY_LEN);
+        Ok((A::from_key(a)?, B::from_key(b)?))
+    }
+}
+
+impl<A: FixedLengthKey, B: FixedLengthKey> FixedLengthKey for (A, B) {
+    const KEY_LEN: usize = A::KEY_LEN + B::KEY_LEN;
+}
+
+/// Implement the conversions between an identifier and an `IVec` with its Key encoding
+macro_rules! ivec_conversions {
+    ($($t:ty),*) => {$(
+        impl From<$t> for IVec {
+            fn from(id: $t) -> Self {
+                id.to_key()
+            }
+        }
+
+        impl TryFrom<IVec> for $t {
+            type Error = BanyanError;
+
+            fn try_from(key: IVec) -> Result<Self> {
+                <$t>::from_key(&key)
+            }
+        }
+    )*};
+}
+
+ivec_conversions!(DealID, BlockNum, Blake3Hash, CidWrapper);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_round_trip_and_short_keys_are_errors() {
+        for deal_id in [0, 1, 255, 256, u64::MAX] {
+            let key = IVec::from(DealID(deal_id));
+            assert_eq!(DealID::try_from(key).unwrap(), DealID(deal_id));
+        }
+        assert_eq!(
+            BlockNum::try_from(IVec::from(BlockNum(7))).unwrap(),
+            BlockNum(7)
+        );
+        let hash = Blake3Hash(blake3::hash(b"banyan"));
+        assert_eq!(Blake3Hash::try_from(IVec::from(hash)).unwrap(), hash);
+        let cid = CidWrapper(
+            Cid::try_from("bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy").unwrap(),
+        );
+        assert_eq!(CidWrapper::try_from(IVec::from(cid)).unwrap(), cid);
+        let pair = (DealID(3), cid);
+        assert_eq!(
+            <(DealID, CidWrapper)>::from_key(&pair.to_key()).unwrap(),
+            pair
+        );
+
+        // Short (or long) keys don't panic
+        let err = DealID::try_from(IVec::from(&[1u8, 2, 3])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse error: Invalid DealID key: expected 8 bytes, got 3"
+        );
+        assert!(BlockNum::from_key(&[0; 9]).is_err());
+        assert!(Blake3Hash::from_key(&[0; 31]).is_err());
+        assert!(CidWrapper::from_key(b"not a cid").is_err());
+        assert!(<(DealID, BlockNum)>::from_key(&[0; 4]).is_err());
+        assert!(<(DealID, BlockNum)>::from_key(&[0; 12]).is_err());
+    }
+
+    #[test]
+    fn keys_sort_numerically_for_range_queries() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let windows = db.open_tree("windows").unwrap();
+        let due = db.open_tree("due").unwrap();
+        for deal_id in [256, 1, 2] {
+            for window in [300, 10, 20] {
+                let (deal_id, window) = (DealID(deal_id), BlockNum(window));
+                windows.insert((deal_id, window).to_key(), &[]).unwrap();
+                due.insert((window, deal_id).to_key(), &[]).unwrap();
+            }
+        }
+        let read = |key: sled::Result<IVec>| <(DealID, BlockNum)>::from_key(&key.unwrap()).unwrap();
+
+        // Every deal, in order, and each deal's windows in order
+        let all: Vec<_> = windows.iter().keys().map(read).collect();
+        assert_eq!(all.first(), Some(&(DealID(1), BlockNum(10))));
+        assert_eq!(all.last(), Some(&(DealID(256), BlockNum(300))));
+        assert!(all.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // One deal's windows, by prefix or by range
+        let deal_2: Vec<_> = windows
+            .scan_prefix(DealID(2).to_key())
+            .keys()
+            .map(read)
+            .collect();
+        assert_eq!(
+            deal_2,
+            vec![
+                (DealID(2), BlockNum(10)),
+                (DealID(2), BlockNum(20)),
+                (DealID(2), BlockNum(300))
+            ]
+        );
+        let early: Vec<_> = windows
+            .range((DealID(2), BlockNum(0)).to_key()..(DealID(2), BlockNum(100)).to_key())
+            .keys()
+            .map(read)
+            .collect();
+        assert_eq!(early, deal_2[..2]);
+
+        // The deals due at or after block 20
+        let due_later: Vec<_> = due
+            .range((BlockNum(20), DealID(0)).to_key()..)
+            .keys()
+            .map(|key| <(BlockNum, DealID)>::from_key(&key.unwrap()).unwrap())
+            .collect();
+        assert_eq!(due_later.len(), 6);
+        assert_eq!(due_later[0], (BlockNum(20), DealID(1)));
+        assert_eq!(due_later[3], (BlockNum(300), DealID(1)));
+    }
+}
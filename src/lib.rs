@@ -1,9 +1,27 @@
 #![deny(unused_crate_dependencies)]
 
+pub mod chainlink;
+pub mod checkpoint;
+pub mod contracts;
 pub mod deals;
+pub mod error;
 pub mod estuary;
 pub mod eth;
+#[cfg(feature = "chainlink-ea")]
+pub mod external_adapter;
 pub mod hash;
 pub mod ipfs;
+pub mod keys;
+pub mod manifest;
+pub mod multicall;
+pub mod offer;
+pub mod pricing;
+pub mod proof_buddy;
 pub mod proofs;
+pub mod store;
+#[cfg(feature = "test-support")]
+pub mod test_chain;
+pub mod token;
 pub mod types;
+pub mod validation;
+pub mod watcher;
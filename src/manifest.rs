@@ -0,0 +1,452 @@
+use crate::{
+    deals::DealProposalBuilder,
+    error::{BanyanError, Result},
+    hash::{piece_commitment, HashFunction, PieceCommitment},
+    pricing::PricingModel,
+    token::Rounding,
+    types::{CidCodec, DealProposal},
+};
+use cid::Version;
+use ethers::{
+    signers::Signer,
+    types::{Address, Signature},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+/*
+ * Deal manifests, for reviewing a proposal before it goes to chain.
+ * Status: A manifest is the proposal, the file it was built from, and the builder settings it
+ * was built with, as TOML or JSON. Before it's submitted the proposal is rebuilt from the file
+ * and settings, and has to come out the same: the file must still hash to the reviewed Blake3
+ * checksum and piece commitment, if it has one, and any edit to the settings must have been reflected in the proposal, so what goes
+ * to chain is exactly what was reviewed. A reviewer can sign the manifest once they've checked
+ * it, and the submitter can require that signature, which breaks if anything in the manifest
+ * is changed after it was signed. Hashes are written as hex and amounts as decimal strings,
+ * so a manifest can be read and edited by hand. The source path is read as written, relative to
+ * the working directory rather than the manifest.
+ */
+
+/// ManifestFormat - How a manifest is written down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// TOML, for people to read and edit
+    Toml,
+    /// JSON, for tools
+    Json,
+}
+
+impl ManifestFormat {
+    /// from_path - The format of a manifest file, from its extension
+    /// # Errors
+    /// * `BanyanError::Parse` - If the extension isn't `.toml` or `.json`
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => Ok(ManifestFormat::Toml),
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Ok(ManifestFormat::Json),
+            _ => Err(BanyanError::Parse(format!(
+                "Can't tell the format of manifest {}: expected a .toml or .json file",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// DealSettings - The settings of a DealProposalBuilder, without its input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DealSettings {
+    /// The address of the executor to propose the deal to
+    pub executor_address: String,
+    /// The length of the deal in blocks
+    pub deal_length_in_blocks: u64,
+    /// The frequency of proofs to be submitted, in blocks
+    pub proof_frequency_in_blocks: u64,
+    /// How the amount of tokens to be paid to the executor is charged
+    pub pricing: PricingModel,
+    /// How the amount of tokens in collateral the executor must provide is charged
+    pub collateral_pricing: PricingModel,
+    /// The chain's block time, to estimate the length of the deal in days for daily rates
+    pub seconds_per_block: u64,
+    /// How to round the price and collateral to the token's smallest unit
+    pub rounding: Rounding,
+    /// The address of the token the price and collateral are in
+    pub erc20_token_denomination: String,
+    /// The hash function the CID of the data is made with
+    pub hash_function: HashFunction,
+    /// The version of the CID of the data, 0 or 1
+    pub cid_version: u64,
+    /// The codec of the CID of the data
+    pub cid_codec: CidCodec,
+}
+
+impl From<&DealProposalBuilder> for DealSettings {
+    fn from(builder: &DealProposalBuilder) -> Self {
+        DealSettings {
+            executor_address: builder.executor_address.clone(),
+            deal_length_in_blocks: builder.deal_length_in_blocks,
+            proof_frequency_in_blocks: builder.proof_frequency_in_blocks,
+            pricing: builder.pricing.clone(),
+            collateral_pricing: builder.collateral_pricing.clone(),
+            seconds_per_block: builder.seconds_per_block,
+            rounding: builder.rounding,
+            erc20_token_denomination: builder.erc20_token_denomination.clone(),
+            hash_function: builder.hash_function,
+            cid_version: builder.cid_version.into(),
+            cid_codec: builder.cid_codec,
+        }
+    }
+}
+
+impl DealSettings {
+    /// The version of the CID of the data
+    /// # Errors
+    /// * `BanyanError::Parse` - If the version isn't 0 or 1
+    pub fn cid_version(&self) -> Result<Version> {
+        Version::try_from(self.cid_version)
+            .map_err(|_| BanyanError::Parse(format!("Invalid CID version {}", self.cid_version)))
+    }
+}
+
+/// DealManifest - A deal proposal, and the file and settings to rebuild it from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DealManifest {
+    /// The file the deal is for
+    pub source: PathBuf,
+    /// The settings the proposal was built with
+    pub settings: DealSettings,
+    /// The proposal, as it'll be sent to chain
+    pub proposal: DealProposal,
+    /// The reviewer's signature of the manifest, if it's been signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
+}
+
+impl DealManifest {
+    /// build - Build a proposal for a file, and the manifest to review it with
+    /// # Arguments
+    /// * `source` - The file to build the deal for
+    /// * `builder` - The settings to build it with. Any input it has is replaced by `source`.
+    /// # Errors
+    /// * As for `DealProposalBuilder::build`
+    pub fn build(source: impl Into<PathBuf>, builder: DealProposalBuilder) -> Result<Self> {
+        let source = source.into();
+        let settings = DealSettings::from(&builder);
+        let proposal = builder.with_path(&source).build()?;
+        Ok(DealManifest {
+            source,
+            settings,
+            proposal,
+            signature: None,
+        })
+    }
+
+    /// load - Read a manifest, in the format its extension says
+    /// # Errors
+    /// * `BanyanError::Io` - If the manifest can't be read
+    /// * `BanyanError::Parse` - If it isn't a manifest
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let format = ManifestFormat::from_path(path)?;
+        Self::parse(&fs::read_to_string(path)?, format)
+    }
+
+    /// save - Write the manifest, in the format its extension says. Any file there is replaced.
+    /// # Errors
+    /// * `BanyanError::Io` - If the manifest can't be written
+    /// * `BanyanError::Parse` - If the extension isn't `.toml` or `.json`
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        Ok(fs::write(
+            path,
+            self.encode(ManifestFormat::from_path(path)?)?,
+        )?)
+    }
+
+    /// Write the manifest down in a format
+    pub fn encode(&self, format: ManifestFormat) -> Result<String> {
+        match format {
+            ManifestFormat::Toml => Ok(toml::to_string_pretty(self)?),
+            ManifestFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+
+    /// Read a manifest written down in a format
+    pub fn parse(manifest: &str, format: ManifestFormat) -> Result<Self> {
+        match format {
+            ManifestFormat::Toml => Ok(toml::from_str(manifest)?),
+            ManifestFormat::Json => Ok(serde_json::from_str(manifest)?),
+        }
+    }
+
+    /// verify - Rebuild the proposal from the source file and settings, and check it's the one
+    /// that was reviewed
+    /// # Returns
+    /// * `DealProposal` - The proposal, ready for `propose_deal`
+    /// # Errors
+    /// * `BanyanError::Io` - If the source file can't be read
+    /// * `BanyanError::Manifest` - If the file no longer hashes to the proposal's Blake3
+    ///   checksum or piece commitment, or the proposal isn't what its settings build
+    pub fn verify(&self) -> Result<DealProposal> {
+        let rebuilt = DealProposalBuilder::from_manifest(self)?.build()?;
+        self.check(rebuilt, self.piece_commitment()?)
+    }
+
+    /// verify_async - Verify the manifest like `verify`, reading the source file without blocking
+    pub async fn verify_async(&self) -> Result<DealProposal> {
+        let rebuilt = DealProposalBuilder::from_manifest(self)?
+            .build_async()
+            .await?;
+        let manifest = self.clone();
+        let piece = tokio::task::spawn_blocking(move || manifest.piece_commitment())
+            .await
+            .map_err(|e| match e.try_into_panic() {
+                Ok(panic) => std::panic::resume_unwind(panic),
+                Err(_) => BanyanError::Cancelled("Hashing was cancelled".to_string()),
+            })??;
+        self.check(rebuilt, piece)
+    }
+
+    /// The bytes the reviewer signs: the manifest as JSON without a signature, so it's the same
+    /// whichever format the manifest is saved in
+    pub fn signing_payload(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&DealManifest {
+            signature: None,
+            ..self.clone()
+        })?)
+    }
+
+    /// sign - Verify the manifest, and sign it as its reviewer, replacing any signature it had
+    /// # Arguments
+    /// * `signer` - The reviewer's signer, e.g. a `LocalWallet`
+    /// # Errors
+    /// * As for `verify`
+    /// * `BanyanError::Manifest` - If the signer fails
+    pub async fn sign<S: Signer>(mut self, signer: &S) -> Result<Self> {
+        self.verify_async().await?;
+        let signature = signer
+            .sign_message(self.signing_payload()?)
+            .await
+            .map_err(|e| BanyanError::Manifest(format!("Couldn't sign the manifest: {}", e)))?;
+        self.signature = Some(signature);
+        Ok(self)
+    }
+
+    /// signer - Who signed the manifest, if anyone did
+    /// # Errors
+    /// * `BanyanError::Manifest` - If the signature is invalid
+    pub fn signer(&self) -> Result<Option<Address>> {
+        let payload = self.signing_payload()?;
+        self.signature
+            .map(|signature| {
+                signature
+                    .recover(payload)
+                    .map_err(|e| BanyanError::Manifest(format!("Invalid signature: {}", e)))
+            })
+            .transpose()
+    }
+
+    /// verify_signed - Verify the manifest like `verify_async`, once it's checked the manifest
+    /// was signed by its reviewer
+    /// # Arguments
+    /// * `reviewer` - Who must have signed the manifest
+    /// # Errors
+    /// * `BanyanError::Manifest` - If the manifest isn't signed, or was signed by someone else or
+    ///   changed after it was signed
+    /// * As for `verify`
+    pub async fn verify_signed(&self, reviewer: Address) -> Result<DealProposal> {
+        match self.signer()? {
+            Some(signer) if signer == reviewer => self.verify_async().await,
+            Some(signer) => Err(BanyanError::Manifest(format!(
+                "The manifest was signed by {:?}, not the reviewer {:?}, or changed after it was \
+                 signed",
+                signer, reviewer
+            ))),
+            None => Err(BanyanError::Manifest(format!(
+                "The manifest isn't signed, it must be signed by the reviewer {:?}",
+                reviewer
+            ))),
+        }
+    }
+
+    /// The piece commitment of the source file, if the reviewed proposal has one to check
+    fn piece_commitment(&self) -> Result<Option<PieceCommitment>> {
+        match self.proposal.piece_commitment {
+            Some(_) => Ok(Some(piece_commitment(File::open(&self.source)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Check a rebuilt proposal, and the piece commitment computed for it, against the reviewed
+    /// one
+    fn check(
+        &self,
+        mut rebuilt: DealProposal,
+        piece: Option<PieceCommitment>,
+    ) -> Result<DealProposal> {
+        if rebuilt.blake3_checksum != self.proposal.blake3_checksum {
+            return Err(BanyanError::Manifest(format!(
+                "{} has changed: it hashes to {}, but the manifest's proposal is for {}",
+                self.source.display(),
+                rebuilt.blake3_checksum,
+                self.proposal.blake3_checksum
+            )));
+        }
+        if let (Some(piece), Some(reviewed)) = (piece, self.proposal.piece_commitment) {
+            if piece != reviewed {
+                return Err(BanyanError::Manifest(format!(
+                    "{} is piece {}, but the manifest's proposal is for piece {}",
+                    self.source.display(),
+                    piece.piece_cid,
+                    reviewed.piece_cid
+                )));
+            }
+        }
+        rebuilt.piece_commitment = piece;
+        if rebuilt != self.proposal {
+            return Err(BanyanError::Manifest(format!(
+                "The manifest's proposal isn't what its settings build, rebuild it to review the \
+                 changes. The settings build:\n{}",
+                rebuilt
+            )));
+        }
+        Ok(rebuilt)
+    }
+}
+
+impl Display for DealManifest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "Source: {}", self.source.display())?;
+        write!(f, "{}", self.proposal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash::piece_commitment, types::BlockNum};
+    use ethers::signers::LocalWallet;
+
+    fn manifest(source: &Path) -> DealManifest {
+        let builder = DealProposal::builder()
+            .with_deal_length_in_blocks(100)
+            .with_pricing(PricingModel::PerTibPerDay("2.5".parse().unwrap()))
+            .with_hash_function(HashFunction::Blake3);
+        DealManifest::build(source, builder).unwrap()
+    }
+
+    #[tokio::test]
+    async fn manifests_round_trip_and_rebuild() {
+        let source = Path::new("test_files/ethereum.pdf");
+        let piece = piece_commitment(File::open(source).unwrap()).unwrap();
+        let mut manifest = manifest(source);
+        manifest.proposal = manifest.proposal.with_piece_commitment(piece).unwrap();
+
+        for format in [ManifestFormat::Toml, ManifestFormat::Json] {
+            let encoded = manifest.encode(format).unwrap();
+            assert_eq!(DealManifest::parse(&encoded, format).unwrap(), manifest);
+        }
+        let toml = manifest.encode(ManifestFormat::Toml).unwrap();
+        assert!(toml.contains("source = \"test_files/ethereum.pdf\""));
+        assert!(toml.contains("deal_length_in_blocks = 100"));
+        // Hashes and amounts are written so people can read and edit them
+        assert!(toml.contains(&format!(
+            "blake3_checksum = \"{}\"",
+            manifest.proposal.blake3_checksum
+        )));
+        assert!(toml.contains(&format!("price = \"{}\"", manifest.proposal.price)));
+        assert!(toml.contains("amount = \"2500000000000000000\""));
+        let edited = toml.replacen("\"2500000000000000000\"", "\"3000000000000000000\"", 1);
+        let edited = DealManifest::parse(&edited, ManifestFormat::Toml).unwrap();
+        assert_eq!(edited.settings.pricing.rate().to_string(), "3");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deal.toml");
+        manifest.save(&path).unwrap();
+        assert_eq!(DealManifest::load(&path).unwrap(), manifest);
+        assert_eq!(manifest.verify().unwrap(), manifest.proposal);
+        assert_eq!(manifest.verify_async().await.unwrap(), manifest.proposal);
+
+        let err = DealManifest::load(path.with_extension("yaml")).unwrap_err();
+        assert!(err.to_string().contains("expected a .toml or .json file"));
+    }
+
+    #[tokio::test]
+    async fn edited_files_and_settings_are_caught() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("ethereum.pdf");
+        fs::copy("test_files/ethereum.pdf", &source).unwrap();
+        let manifest = manifest(&source);
+        assert!(manifest.verify().is_ok());
+
+        // Settings edited after review, without rebuilding the proposal
+        let mut edited = manifest.clone();
+        edited.settings.deal_length_in_blocks = 200;
+        let err = edited.verify().unwrap_err();
+        assert!(matches!(err, BanyanError::Manifest(_)));
+        assert!(err.to_string().contains("isn't what its settings build"));
+
+        // A piece commitment edited after review, or made from another file
+        let len = fs::metadata(&source).unwrap().len() as usize;
+        let mut edited = manifest.clone();
+        edited.proposal.piece_commitment = Some(piece_commitment(vec![0; len].as_slice()).unwrap());
+        let err = edited.verify().unwrap_err();
+        assert!(matches!(err, BanyanError::Manifest(_)));
+        assert!(err.to_string().contains("is piece"));
+        let err = edited.verify_async().await.unwrap_err();
+        assert!(matches!(err, BanyanError::Manifest(_)));
+
+        // The file changed after review
+        let mut bytes = fs::read(&source).unwrap();
+        bytes[0] ^= 1;
+        fs::write(&source, bytes).unwrap();
+        let err = manifest.verify().unwrap_err();
+        assert!(matches!(err, BanyanError::Manifest(_)));
+        assert!(err.to_string().contains("has changed"));
+    }
+
+    #[tokio::test]
+    async fn manifests_are_signed_by_their_reviewer() {
+        let reviewer: LocalWallet =
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+                .parse()
+                .unwrap();
+        let manifest = manifest(Path::new("test_files/ethereum.pdf"));
+        let err = manifest
+            .verify_signed(reviewer.address())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("isn't signed"));
+
+        let signed = manifest.clone().sign(&reviewer).await.unwrap();
+        assert_eq!(signed.signer().unwrap(), Some(reviewer.address()));
+        assert_eq!(
+            signed.verify_signed(reviewer.address()).await.unwrap(),
+            manifest.proposal
+        );
+        // The signature survives being written down in either format
+        for format in [ManifestFormat::Toml, ManifestFormat::Json] {
+            let encoded = signed.encode(format).unwrap();
+            let decoded = DealManifest::parse(&encoded, format).unwrap();
+            assert_eq!(decoded, signed);
+            assert!(decoded.verify_signed(reviewer.address()).await.is_ok());
+        }
+
+        // Only the reviewer's signature counts, and only for what they signed
+        let err = signed.verify_signed(Address::zero()).await.unwrap_err();
+        assert!(matches!(err, BanyanError::Manifest(_)));
+        let mut edited = signed.clone();
+        edited.settings.deal_length_in_blocks = 200;
+        edited.proposal.deal_length_in_blocks = BlockNum(200);
+        let err = edited.verify_signed(reviewer.address()).await.unwrap_err();
+        assert!(err.to_string().contains("not the reviewer"));
+
+        // And manifests that don't check out can't be signed
+        let mut stale = manifest.clone();
+        stale.settings.deal_length_in_blocks = 200;
+        assert!(stale.sign(&reviewer).await.is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use crate::{
+    error::{BanyanError, Result},
+    eth::{revert_data, EthClient},
+    types::*,
+};
+use ethers::{
+    abi::{self, ParamType, Token},
+    providers::Middleware,
+    types::{Address, BlockId, Bytes, TransactionRequest, H160, U256},
+};
+
+/*
+ * Batched reads of the Banyan Contract, for when we need to look at many deals (or windows) at once.
+ * Status: Calls are aggregated through Multicall3's `aggregate3`, so a whole batch is read from
+ * the same block in one request. Chains without Multicall3 (no code at its address) fall back to
+ * one call per item, pinned to the same block. Results always come back in input order, each with its own error.
+ */
+
+/// The address Multicall3 is deployed at on (almost) every chain
+/// https://github.com/mds1/multicall#multicall3-contract-addresses
+pub const MULTICALL3_ADDRESS: Address = H160([
+    0xca, 0x11, 0xbd, 0xe0, 0x59, 0x77, 0xb3, 0x63, 0x11, 0x67, 0x02, 0x88, 0x62, 0xbe, 0x2a, 0x17,
+    0x39, 0x76, 0xca, 0x11,
+]);
+
+/// How many calls to aggregate into a single `aggregate3`, to stay well under the node's gas cap
+const MULTICALL_BATCH_SIZE: usize = 100;
+
+/// The signature of the only Multicall3 function we use
+const AGGREGATE3_SIGNATURE: &str = "aggregate3((address,bool,bytes)[])";
+
+/// The ABI of a `Call3` - `(target, allowFailure, callData)`
+fn call3_param() -> ParamType {
+    ParamType::Tuple(vec![ParamType::Address, ParamType::Bool, ParamType::Bytes])
+}
+
+/// The ABI of a `Result` - `(success, returnData)`
+fn result_param() -> ParamType {
+    ParamType::Tuple(vec![ParamType::Bool, ParamType::Bytes])
+}
+
+/// encode_aggregate3 - Build the calldata for an `aggregate3` call that may partially fail
+/// # Arguments
+/// * `target` - The contract every call is made to
+/// * `calls` - The calldata of each call
+pub(crate) fn encode_aggregate3(target: Address, calls: &[Bytes]) -> Bytes {
+    let calls = calls
+        .iter()
+        .map(|data| {
+            Token::Tuple(vec![
+                Token::Address(target),
+                Token::Bool(true),
+                Token::Bytes(data.to_vec()),
+            ])
+        })
+        .collect();
+    let mut data = ethers::utils::id(AGGREGATE3_SIGNATURE).to_vec();
+    data.extend(abi::encode(&[Token::Array(calls)]));
+    data.into()
+}
+
+/// decode_aggregate3 - Read the calls out of `aggregate3` calldata
+/// # Returns
+/// * `Vec<(Address, bool, Bytes)>` - The target, whether failure is allowed, and calldata of each call
+pub(crate) fn decode_aggregate3(data: &[u8]) -> Result<Vec<(Address, bool, Bytes)>> {
+    if data.len() < 4 || data[..4] != ethers::utils::id(AGGREGATE3_SIGNATURE) {
+        return Err(BanyanError::Parse("Not an aggregate3 call".to_string()));
+    }
+    let tokens = abi::decode(&[ParamType::Array(Box::new(call3_param()))], &data[4..])?;
+    tuples(tokens)?
+        .into_iter()
+        .map(|fields| match fields.as_slice() {
+            [Token::Address(target), Token::Bool(allow_failure), Token::Bytes(data)] => {
+                Ok((*target, *allow_failure, data.clone().into()))
+            }
+            _ => Err(BanyanError::Parse(format!("Malformed Call3: {:?}", fields))),
+        })
+        .collect()
+}
+
+/// encode_aggregate3_results - Build the return data of an `aggregate3` call
+/// # Arguments
+/// * `results` - Whether each call succeeded, and what it returned
+pub(crate) fn encode_aggregate3_results(results: &[(bool, Bytes)]) -> Bytes {
+    let results = results
+        .iter()
+        .map(|(success, data)| {
+            Token::Tuple(vec![Token::Bool(*success), Token::Bytes(data.to_vec())])
+        })
+        .collect();
+    abi::encode(&[Token::Array(results)]).into()
+}
+
+/// decode_aggregate3_results - Read the return data of an `aggregate3` call
+/// # Returns
+/// * `Vec<(bool, Bytes)>` - Whether each call succeeded, and what it returned
+pub(crate) fn decode_aggregate3_results(output: &[u8]) -> Result<Vec<(bool, Bytes)>> {
+    let tokens = abi::decode(&[ParamType::Array(Box::new(result_param()))], output)?;
+    tuples(tokens)?
+        .into_iter()
+        .map(|fields| match fields.as_slice() {
+            [Token::Bool(success), Token::Bytes(data)] => Ok((*success, data.clone().into())),
+            _ => Err(BanyanError::Parse(format!(
+                "Malformed Multicall3 Result: {:?}",
+                fields
+            ))),
+        })
+        .collect()
+}
+
+/// Unpack a single decoded array of tuples
+fn tuples(mut tokens: Vec<Token>) -> Result<Vec<Vec<Token>>> {
+    match tokens.pop() {
+        Some(Token::Array(items)) if tokens.is_empty() => items
+            .into_iter()
+            .map(|item| match item {
+                Token::Tuple(fields) => Ok(fields),
+                other => Err(BanyanError::Parse(format!(
+                    "Expected a tuple, got {:?}",
+                    other
+                ))),
+            })
+            .collect(),
+        other => Err(BanyanError::Parse(format!(
+            "Expected an array of tuples, got {:?}",
+            other
+        ))),
+    }
+}
+
+impl EthClient {
+    /// get_offers - Read many deals from the Ethereum blockchain at once
+    /// # Arguments
+    /// * `deal_ids` - The Deal IDs to read
+    /// # Returns
+    /// * `Vec<Result<OnChainDealInfo>>` - Each deal, or why it couldn't be read, in the order asked for
+    pub async fn get_offers(&self, deal_ids: &[DealID]) -> Result<Vec<Result<OnChainDealInfo>>> {
+        let calls = deal_ids
+            .iter()
+            .map(|deal_id| self.contract().encode("getOffer", *deal_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let layout = self.contract_version().deal_info_layout;
+        Ok(self
+            .call_batch(calls)
+            .await?
+            .into_iter()
+            .zip(deal_ids)
+            .map(|(output, deal_id)| {
+                output.and_then(|output| {
+                    let token: Token = self.contract().decode_output("getOffer", output)?;
+                    layout
+                        .decode(token)
+                        .map_err(|e| BanyanError::Parse(format!("getOffer({}): {}", deal_id.0, e)))
+                })
+            })
+            .collect())
+    }
+
+    /// get_proof_blocks - Read the blocks the proofs for many windows of a deal landed in.
+    /// `getProofBlockNumbers` only returns a single number on deployed contracts, so this batches
+    /// one `getProofBlock` call per window instead.
+    /// # Arguments
+    /// * `deal_id` - The Deal ID to read proof blocks for
+    /// * `windows` - The window numbers to read
+    /// # Returns
+    /// * `Vec<Result<Option<BlockNum>>>` - The block each proof landed in (None if it hasn't been
+    ///   submitted), or why it couldn't be read, in the order asked for
+    pub async fn get_proof_blocks(
+        &self,
+        deal_id: DealID,
+        windows: &[u64],
+    ) -> Result<Vec<Result<Option<BlockNum>>>> {
+        let calls = windows
+            .iter()
+            .map(|window| {
+                self.contract()
+                    .encode("getProofBlock", (deal_id.0, *window))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self
+            .call_batch(calls)
+            .await?
+            .into_iter()
+            .zip(windows)
+            .map(|(output, window)| {
+                output.and_then(|output| {
+                    let block: U256 = self
+                        .contract()
+                        .decode_output("getProofBlock", output)
+                        .map_err(|e| {
+                            BanyanError::Parse(format!(
+                                "getProofBlock({}, {}): {}",
+                                deal_id.0, window, e
+                            ))
+                        })?;
+                    Ok(Some(BlockNum(checked_uint(block, "Proof block")?)).filter(|b| b.0 != 0))
+                })
+            })
+            .collect())
+    }
+
+    /// Make many read-only calls to the Banyan Contract, all against the same block.
+    /// Goes through Multicall3 if it's deployed, otherwise makes the calls one at a time.
+    /// # Returns
+    /// * The output of each call, or why it failed, in the order of `calls`.
+    ///   Calls the contract rejected fail with `BanyanError::Contract`.
+    /// # Errors
+    /// * `BanyanError::Chain` - If the node can't be reached, or Multicall3 is deployed but
+    ///   the `aggregate3` call fails
+    async fn call_batch(&self, calls: Vec<Bytes>) -> Result<Vec<Result<Bytes>>> {
+        let block: BlockId = self.provider().get_block_number().await?.into();
+        if let Some(multicall_address) = self.multicall_address() {
+            let code = self
+                .provider()
+                .get_code(multicall_address, Some(block))
+                .await?;
+            if !code.is_empty() {
+                return self.aggregate3(multicall_address, &calls, block).await;
+            }
+        }
+        let target = self.contract().address();
+        let mut outputs = Vec::with_capacity(calls.len());
+        for data in calls {
+            let tx = TransactionRequest::new().to(target).data(data.clone());
+            outputs.push(
+                self.provider()
+                    .call(&tx.into(), Some(block))
+                    .await
+                    .map_err(|e| match revert_data(&e) {
+                        Some(revert) => {
+                            self.contract_version().decode_revert(&data, &revert).into()
+                        }
+                        None => e.into(),
+                    }),
+            );
+        }
+        Ok(outputs)
+    }
+
+    /// Make the calls through `aggregate3`, in batches of MULTICALL_BATCH_SIZE
+    async fn aggregate3(
+        &self,
+        multicall_address: Address,
+        calls: &[Bytes],
+        block: BlockId,
+    ) -> Result<Vec<Result<Bytes>>> {
+        let target = self.contract().address();
+        let mut outputs = Vec::with_capacity(calls.len());
+        for batch in calls.chunks(MULTICALL_BATCH_SIZE) {
+            let tx = TransactionRequest::new()
+                .to(multicall_address)
+                .data(encode_aggregate3(target, batch));
+            let results =
+                decode_aggregate3_results(&self.provider().call(&tx.into(), Some(block)).await?)?;
+            if results.len() != batch.len() {
+                return Err(BanyanError::Chain(format!(
+                    "Multicall3 returned {} results for {} calls",
+                    results.len(),
+                    batch.len()
+                )));
+            }
+            outputs.extend(batch.iter().zip(results).map(|(call, (success, data))| {
+                if success {
+                    Ok(data)
+                } else {
+                    Err(self.contract_version().decode_revert(call, &data).into())
+                }
+            }));
+        }
+        Ok(outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate3_round_trip() {
+        let target = Address::repeat_byte(7);
+        let calls: Vec<Bytes> = vec![vec![1, 2, 3, 4].into(), Bytes::default()];
+        let data = encode_aggregate3(target, &calls);
+        // aggregate3((address,bool,bytes)[])
+        assert_eq!(data[..4], [0x82, 0xad, 0x56, 0xcb]);
+        assert_eq!(
+            decode_aggregate3(&data).unwrap(),
+            vec![
+                (target, true, calls[0].clone()),
+                (target, true, calls[1].clone())
+            ]
+        );
+        assert!(decode_aggregate3(&calls[0]).is_err());
+
+        let results = vec![(true, Bytes::from(vec![9; 32])), (false, Bytes::default())];
+        assert_eq!(
+            decode_aggregate3_results(&encode_aggregate3_results(&results)).unwrap(),
+            results
+        );
+        assert!(decode_aggregate3_results(&[]).is_err());
+    }
+
+    #[test]
+    /// The encoding matches Multicall3's ABI byte for byte, not just our own decoder
+    fn aggregate3_abi_vectors() {
+        let target = Address::repeat_byte(7);
+        let word = |hex: &str| format!("{:0>64}", hex);
+        let padded_call = format!("{:0<64}", "01020304");
+        let expected_call = [
+            "82ad56cb".to_string(),
+            word("20"),
+            word("1"),
+            word("20"),
+            word(&"07".repeat(20)),
+            word("1"),
+            word("60"),
+            word("4"),
+            padded_call.clone(),
+        ]
+        .concat();
+        let call = encode_aggregate3(target, &[vec![1, 2, 3, 4].into()]);
+        assert_eq!(ethers::utils::hex::encode(&call), expected_call);
+
+        let output = ethers::utils::hex::decode(
+            [
+                word("20"),
+                word("2"),
+                word("40"),
+                word("c0"),
+                word("1"),
+                word("40"),
+                word("4"),
+                padded_call,
+                word("0"),
+                word("40"),
+                word("0"),
+            ]
+            .concat(),
+        )
+        .unwrap();
+        assert_eq!(
+            decode_aggregate3_results(&output).unwrap(),
+            vec![
+                (true, Bytes::from(vec![1, 2, 3, 4])),
+                (false, Bytes::default())
+            ]
+        );
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    /// Batched reads come back in input order, whether or not they go through Multicall3
+    async fn batched_reads_on_test_chain() -> Result<()> {
+        use crate::{contracts::ContractError, deals::DealProposalBuilder, test_chain::TestChain};
+        use ethers::signers::Signer;
+        use std::fs::File;
+
+        let chain = TestChain::spawn().await?;
+        let creator = chain.creator_client().await?;
+        let mut deal_ids = vec![];
+        for deal_length_in_blocks in [20, 40, 60] {
+            let deal = DealProposalBuilder::default()
+                .with_file(File::open("test_files/ethereum.pdf")?)
+                .with_executor_address(format!("{:?}", chain.executor().address()))
+                .with_erc20_token_denomination(format!("{:?}", chain.token_address()))
+                .with_deal_length_in_blocks(deal_length_in_blocks)
+                .with_price_per_tib("1".parse()?)
+                .build()?;
+            deal_ids.push(creator.propose_deal(deal, None, None).await?);
+        }
+        // Ask out of order, to make sure the answers come back in the order asked
+        deal_ids.reverse();
+
+        let batched = creator.get_offers(&deal_ids).await?;
+        assert_eq!(chain.aggregate3_calls(), 1);
+        let unbatched = creator
+            .with_multicall_address(None)
+            .get_offers(&deal_ids)
+            .await?;
+        // Without code at the Multicall3 address, reads fall back to one call at a time
+        let no_multicall = chain
+            .creator_client()
+            .await?
+            .with_multicall_address(Some(Address::repeat_byte(0x42)))
+            .get_offers(&deal_ids)
+            .await?;
+        assert!(no_multicall.iter().all(|offer| offer.is_ok()));
+        // But if there is code there, a failed aggregate3 isn't hidden by falling back
+        assert!(chain
+            .creator_client()
+            .await?
+            .with_multicall_address(Some(chain.escrow_address()))
+            .get_offers(&deal_ids)
+            .await
+            .is_err());
+        assert_eq!(chain.aggregate3_calls(), 1);
+        let creator = chain.creator_client().await?;
+        assert_eq!(batched.len(), deal_ids.len());
+        for ((batched, unbatched), deal_id) in batched.into_iter().zip(unbatched).zip(&deal_ids) {
+            let batched = batched?;
+            assert_eq!(batched, unbatched?);
+            assert_eq!(batched, creator.get_offer(*deal_id).await?);
+        }
+        assert_eq!(
+            creator.get_offers(&deal_ids).await?[0]
+                .as_ref()
+                .unwrap()
+                .deal_length_in_blocks,
+            BlockNum(60)
+        );
+
+        let windows = [1, 2, 3];
+        let proof_blocks = creator.get_proof_blocks(deal_ids[0], &windows).await?;
+        assert_eq!(proof_blocks.len(), windows.len());
+        for proof_block in proof_blocks {
+            assert_eq!(proof_block?, None);
+        }
+
+        // A call that reverts fails on its own, without taking the rest of the batch with it
+        let mut calls = vec![creator.contract().encode("getOffer", deal_ids[0])?];
+        calls.push(ethers::utils::id("noSuchFunction()").to_vec().into());
+        calls.push(creator.contract().encode("getOffer", deal_ids[1])?);
+        let outputs = creator.call_batch(calls).await?;
+        assert!(outputs[0].is_ok());
+        assert!(matches!(
+            outputs[1],
+            Err(BanyanError::Contract(ContractError::Unknown { .. }))
+        ));
+        assert!(outputs[2].is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,530 @@
+use crate::{
+    error::{BanyanError, Result},
+    types::{BlockNum, DealProposal},
+    validation::ValidationPolicy,
+};
+use ethers::{
+    abi::{self, Token},
+    signers::Signer,
+    types::{
+        transaction::eip712::{EIP712Domain, Eip712},
+        Address, Signature, H256, U256,
+    },
+    utils::keccak256,
+};
+use serde::{Deserialize, Serialize};
+
+/*
+ * Deal offers a creator signs off-chain, so executors can be shopped around before anyone pays
+ * gas for `startOffer`.
+ * Status: An offer is a DealProposal, its creator, a nonce and the block it expires at, signed as
+ * EIP-712 typed data in a domain bound to the chain and the Escrow contract, so it can't be
+ * replayed elsewhere. The pricing and piece commitment the proposal carries off-chain are signed
+ * too, as the bytes of their JSON (empty if there's none), so they can't be swapped out either.
+ * An executor checks who signed it, evaluates it against its own policy, and
+ * signs an acceptance of the offer's digest. The creator checks the acceptance and proposes the
+ * deal with `propose_deal` as before; nothing about the chain side changes.
+ */
+
+/// The name of the EIP-712 signing domain offers are signed in
+pub const OFFER_DOMAIN_NAME: &str = "Banyan";
+
+/// The version of the EIP-712 signing domain. Bump it if the offer types change.
+pub const OFFER_DOMAIN_VERSION: &str = "2";
+
+/// The EIP-712 type of a deal offer
+pub const DEAL_OFFER_TYPE: &str = "DealOffer(address creator,address executor,\
+uint256 dealLengthInBlocks,uint256 proofFrequencyInBlocks,uint256 price,uint256 collateral,\
+address erc20TokenDenomination,uint256 fileSize,string ipfsFileCid,bytes32 blake3Checksum,\
+bytes pricing,bytes pieceCommitment,uint256 nonce,uint256 expiryBlock)";
+
+/// The EIP-712 type of an executor's acceptance of a deal offer
+pub const OFFER_ACCEPTANCE_TYPE: &str = "OfferAcceptance(bytes32 offer,address executor)";
+
+/// offer_domain - The EIP-712 domain offers are signed in
+/// # Arguments
+/// * `chain_id` - The chain the deal will be proposed on
+/// * `escrow` - The address of the Banyan Contract it will be proposed to
+pub fn offer_domain(chain_id: u64, escrow: Address) -> EIP712Domain {
+    EIP712Domain {
+        name: Some(OFFER_DOMAIN_NAME.to_string()),
+        version: Some(OFFER_DOMAIN_VERSION.to_string()),
+        chain_id: Some(chain_id.into()),
+        verifying_contract: Some(escrow),
+        salt: None,
+    }
+}
+
+/// DealOffer - A deal proposal its creator can sign, to offer it to an executor off-chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DealOffer {
+    /// The deal on offer. Its executor is who the offer is made to.
+    pub proposal: DealProposal,
+    /// Who's offering the deal, and will propose it on chain
+    pub creator: Address,
+    /// Tells apart offers of the same deal, e.g. at a new price
+    pub nonce: U256,
+    /// The block the offer expires at. It can't be accepted from then on.
+    pub expiry_block: BlockNum,
+    /// The chain the deal will be proposed on
+    pub chain_id: u64,
+    /// The address of the Banyan Contract the deal will be proposed to
+    pub escrow: Address,
+}
+
+impl Eip712 for DealOffer {
+    type Error = BanyanError;
+
+    fn domain(&self) -> Result<EIP712Domain> {
+        Ok(offer_domain(self.chain_id, self.escrow))
+    }
+
+    fn type_hash() -> Result<[u8; 32]> {
+        Ok(keccak256(DEAL_OFFER_TYPE))
+    }
+
+    fn struct_hash(&self) -> Result<[u8; 32]> {
+        let proposal = &self.proposal;
+        Ok(keccak256(abi::encode(&[
+            Token::FixedBytes(Self::type_hash()?.to_vec()),
+            Token::Address(self.creator),
+            Token::Address(proposal.executor_address),
+            Token::Uint(proposal.deal_length_in_blocks.0.into()),
+            Token::Uint(proposal.proof_frequency_in_blocks.0.into()),
+            Token::Uint(proposal.price),
+            Token::Uint(proposal.collateral),
+            Token::Address(proposal.erc20_token_denomination),
+            Token::Uint(proposal.file_size),
+            Token::FixedBytes(keccak256(proposal.ipfs_file_cid.to_string()).to_vec()),
+            Token::FixedBytes(proposal.blake3_checksum.as_bytes().to_vec()),
+            Token::FixedBytes(keccak256(json_bytes(&proposal.pricing)?).to_vec()),
+            Token::FixedBytes(keccak256(json_bytes(&proposal.piece_commitment)?).to_vec()),
+            Token::Uint(self.nonce),
+            Token::Uint(self.expiry_block.0.into()),
+        ])))
+    }
+}
+
+impl DealOffer {
+    /// The digest the creator signs, which acceptances refer to the offer by
+    pub fn digest(&self) -> Result<H256> {
+        Ok(H256(self.encode_eip712()?))
+    }
+
+    /// sign - Sign the offer as its creator
+    /// # Arguments
+    /// * `signer` - The creator's signer, e.g. a `LocalWallet`
+    /// # Errors
+    /// * `BanyanError::Offer` - If the signer isn't the offer's creator, or fails
+    pub async fn sign<S: Signer>(self, signer: &S) -> Result<SignedDealOffer> {
+        let signature = sign_as(&self, signer, self.creator, "creator").await?;
+        Ok(SignedDealOffer {
+            offer: self,
+            signature,
+        })
+    }
+}
+
+/// SignedDealOffer - A deal offer and its creator's signature, to send to executors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedDealOffer {
+    /// The offer
+    pub offer: DealOffer,
+    /// The creator's EIP-712 signature of the offer
+    pub signature: Signature,
+}
+
+impl SignedDealOffer {
+    /// Encode the offer as JSON, to send to executors
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Decode an offer from JSON. It still has to be verified.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// signer - Who signed the offer
+    /// # Errors
+    /// * `BanyanError::Offer` - If the signature is invalid
+    pub fn signer(&self) -> Result<Address> {
+        recover(&self.offer, &self.signature)
+    }
+
+    /// verify - Check the offer was signed by its creator, so it hasn't been changed since
+    /// # Errors
+    /// * `BanyanError::Offer` - If anyone else signed it, or the signature is invalid
+    pub fn verify(&self) -> Result<()> {
+        check_signer(self.signer()?, self.offer.creator, "creator")
+    }
+
+    /// evaluate - Check an executor can accept the offer
+    /// # Arguments
+    /// * `executor` - The executor's address
+    /// * `current_block` - The latest block, to check the offer hasn't expired
+    /// * `policy` - The executor's rules for the deals it takes
+    /// # Errors
+    /// * `BanyanError::Offer` - If the offer isn't signed by its creator, is made to another
+    ///   executor, or has expired
+    /// * `BanyanError::InvalidProposal` - If the deal breaks the policy
+    pub fn evaluate(
+        &self,
+        executor: Address,
+        current_block: BlockNum,
+        policy: &ValidationPolicy,
+    ) -> Result<()> {
+        self.verify()?;
+        let offer = &self.offer;
+        if offer.proposal.executor_address != executor {
+            return Err(BanyanError::Offer(format!(
+                "The offer is made to {:?}, not {:?}",
+                offer.proposal.executor_address, executor
+            )));
+        }
+        if current_block >= offer.expiry_block {
+            return Err(BanyanError::Offer(format!(
+                "The offer expired at block {}, it's block {}",
+                offer.expiry_block, current_block
+            )));
+        }
+        offer.proposal.validate_with(policy)
+    }
+
+    /// accept - Evaluate the offer, and sign an acceptance of it as its executor
+    /// # Arguments
+    /// * `signer` - The executor's signer
+    /// * `current_block`, `policy` - As for `evaluate`
+    /// # Errors
+    /// * As for `evaluate`, with the signer's address as the executor
+    /// * `BanyanError::Offer` - If the signer fails
+    pub async fn accept<S: Signer>(
+        &self,
+        signer: &S,
+        current_block: BlockNum,
+        policy: &ValidationPolicy,
+    ) -> Result<SignedOfferAcceptance> {
+        let executor = signer.address();
+        self.evaluate(executor, current_block, policy)?;
+        let acceptance = OfferAcceptance {
+            offer: self.offer.digest()?,
+            executor,
+            chain_id: self.offer.chain_id,
+            escrow: self.offer.escrow,
+        };
+        let signature = sign_as(&acceptance, signer, executor, "executor").await?;
+        Ok(SignedOfferAcceptance {
+            acceptance,
+            signature,
+        })
+    }
+}
+
+/// OfferAcceptance - An executor's acceptance of a deal offer, by the offer's digest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfferAcceptance {
+    /// The digest of the offer accepted
+    pub offer: H256,
+    /// The executor accepting it
+    pub executor: Address,
+    /// The chain the offer is for
+    pub chain_id: u64,
+    /// The Banyan Contract the offer is for
+    pub escrow: Address,
+}
+
+impl Eip712 for OfferAcceptance {
+    type Error = BanyanError;
+
+    fn domain(&self) -> Result<EIP712Domain> {
+        Ok(offer_domain(self.chain_id, self.escrow))
+    }
+
+    fn type_hash() -> Result<[u8; 32]> {
+        Ok(keccak256(OFFER_ACCEPTANCE_TYPE))
+    }
+
+    fn struct_hash(&self) -> Result<[u8; 32]> {
+        Ok(keccak256(abi::encode(&[
+            Token::FixedBytes(Self::type_hash()?.to_vec()),
+            Token::FixedBytes(self.offer.as_bytes().to_vec()),
+            Token::Address(self.executor),
+        ])))
+    }
+}
+
+/// SignedOfferAcceptance - An acceptance and the executor's signature, to send to the creator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedOfferAcceptance {
+    /// The acceptance
+    pub acceptance: OfferAcceptance,
+    /// The executor's EIP-712 signature of the acceptance
+    pub signature: Signature,
+}
+
+impl SignedOfferAcceptance {
+    /// verify - Check the acceptance is of an offer, signed by the executor it was made to
+    /// # Arguments
+    /// * `offer` - The offer the creator made
+    /// # Returns
+    /// * `DealProposal` - The accepted deal, ready for `propose_deal`
+    /// # Errors
+    /// * `BanyanError::Offer` - If the acceptance is of another offer, or isn't signed by the
+    ///   offer's executor
+    pub fn verify(&self, offer: &SignedDealOffer) -> Result<DealProposal> {
+        offer.verify()?;
+        let acceptance = &self.acceptance;
+        if acceptance.offer != offer.offer.digest()?
+            || acceptance.chain_id != offer.offer.chain_id
+            || acceptance.escrow != offer.offer.escrow
+        {
+            return Err(BanyanError::Offer(format!(
+                "The acceptance is of offer {:?}, not this one",
+                acceptance.offer
+            )));
+        }
+        let executor = offer.offer.proposal.executor_address;
+        check_signer(acceptance.executor, executor, "executor")?;
+        check_signer(recover(acceptance, &self.signature)?, executor, "executor")?;
+        Ok(offer.offer.proposal.clone())
+    }
+}
+
+/// The bytes an optional part of a proposal is signed as: its JSON, or nothing if it isn't there
+fn json_bytes<T: Serialize>(value: &Option<T>) -> Result<Vec<u8>> {
+    match value {
+        Some(value) => Ok(serde_json::to_vec(value)?),
+        None => Ok(vec![]),
+    }
+}
+
+/// Sign typed data, checking the signer is who's meant to sign it
+async fn sign_as<T, S>(data: &T, signer: &S, expected: Address, role: &str) -> Result<Signature>
+where
+    T: Eip712<Error = BanyanError> + Send + Sync,
+    S: Signer,
+{
+    check_signer(signer.address(), expected, role)?;
+    signer
+        .sign_typed_data(data)
+        .await
+        .map_err(|e| BanyanError::Offer(format!("Couldn't sign as the {}: {}", role, e)))
+}
+
+/// Recover who signed typed data
+fn recover<T: Eip712<Error = BanyanError>>(data: &T, signature: &Signature) -> Result<Address> {
+    signature
+        .recover(H256(data.encode_eip712()?))
+        .map_err(|e| BanyanError::Offer(format!("Invalid signature: {}", e)))
+}
+
+/// Check whoever signed something is who should have
+fn check_signer(signer: Address, expected: Address, role: &str) -> Result<()> {
+    if signer == expected {
+        Ok(())
+    } else {
+        Err(BanyanError::Offer(format!(
+            "Signed by {:?}, not the {} {:?}",
+            signer, role, expected
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hash::piece_commitment,
+        pricing::{DealPricing, PricingModel, DEFAULT_SECONDS_PER_BLOCK},
+        token::Rounding,
+        types::{Blake3Hash, CidWrapper},
+        validation::MaxFileSize,
+    };
+    use ethers::{
+        signers::LocalWallet,
+        types::{transaction::eip712::TypedData, Bytes},
+    };
+    use serde_json::json;
+
+    const CREATOR_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const EXECUTOR_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn wallet(key: &str) -> LocalWallet {
+        key.parse().unwrap()
+    }
+
+    fn offer() -> DealOffer {
+        DealOffer {
+            proposal: DealProposal {
+                executor_address: wallet(EXECUTOR_KEY).address(),
+                deal_length_in_blocks: BlockNum(100),
+                proof_frequency_in_blocks: BlockNum(10),
+                price: U256::exp10(18),
+                collateral: U256::exp10(17),
+                erc20_token_denomination: Address::from_low_u64_be(2),
+                file_size: U256::from(1024),
+                ipfs_file_cid: CidWrapper(
+                    "bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy"
+                        .parse()
+                        .unwrap(),
+                ),
+                blake3_checksum: Blake3Hash(blake3::hash(b"banyan")),
+                pricing: Some(
+                    DealPricing::quote(
+                        &PricingModel::Flat("1".parse().unwrap()),
+                        &PricingModel::Flat("0.1".parse().unwrap()),
+                        1024,
+                        BlockNum(100),
+                        DEFAULT_SECONDS_PER_BLOCK,
+                        Rounding::Nearest,
+                    )
+                    .unwrap(),
+                ),
+                piece_commitment: Some(piece_commitment([1; 1024].as_slice()).unwrap()),
+            },
+            creator: wallet(CREATOR_KEY).address(),
+            nonce: U256::from(7),
+            expiry_block: BlockNum(1000),
+            chain_id: 1,
+            escrow: Address::from_low_u64_be(3),
+        }
+    }
+
+    #[test]
+    fn digests_match_generic_typed_data() {
+        // The same offer as JSON typed data, hashed by ethers rather than by hand
+        let offer = offer();
+        let proposal = &offer.proposal;
+        let typed: TypedData = serde_json::from_value(json!({
+            "types": {
+                "EIP712Domain": [
+                    {"name": "name", "type": "string"},
+                    {"name": "version", "type": "string"},
+                    {"name": "chainId", "type": "uint256"},
+                    {"name": "verifyingContract", "type": "address"}
+                ],
+                "DealOffer": [
+                    {"name": "creator", "type": "address"},
+                    {"name": "executor", "type": "address"},
+                    {"name": "dealLengthInBlocks", "type": "uint256"},
+                    {"name": "proofFrequencyInBlocks", "type": "uint256"},
+                    {"name": "price", "type": "uint256"},
+                    {"name": "collateral", "type": "uint256"},
+                    {"name": "erc20TokenDenomination", "type": "address"},
+                    {"name": "fileSize", "type": "uint256"},
+                    {"name": "ipfsFileCid", "type": "string"},
+                    {"name": "blake3Checksum", "type": "bytes32"},
+                    {"name": "pricing", "type": "bytes"},
+                    {"name": "pieceCommitment", "type": "bytes"},
+                    {"name": "nonce", "type": "uint256"},
+                    {"name": "expiryBlock", "type": "uint256"}
+                ]
+            },
+            "primaryType": "DealOffer",
+            "domain": {
+                "name": "Banyan",
+                "version": "2",
+                "chainId": 1,
+                "verifyingContract": offer.escrow
+            },
+            "message": {
+                "creator": offer.creator,
+                "executor": proposal.executor_address,
+                "dealLengthInBlocks": 100,
+                "proofFrequencyInBlocks": 10,
+                "price": proposal.price.to_string(),
+                "collateral": proposal.collateral.to_string(),
+                "erc20TokenDenomination": proposal.erc20_token_denomination,
+                "fileSize": 1024,
+                "ipfsFileCid": proposal.ipfs_file_cid.to_string(),
+                "blake3Checksum": format!("0x{}", proposal.blake3_checksum.to_hex()),
+                "pricing": Bytes::from(serde_json::to_vec(&proposal.pricing).unwrap()),
+                "pieceCommitment": Bytes::from(
+                    serde_json::to_vec(&proposal.piece_commitment).unwrap()
+                ),
+                "nonce": 7,
+                "expiryBlock": 1000
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            offer.digest().unwrap(),
+            H256(typed.encode_eip712().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn offers_are_signed_accepted_and_checked() {
+        let (creator, executor) = (wallet(CREATOR_KEY), wallet(EXECUTOR_KEY));
+        let policy = ValidationPolicy::default();
+        let signed = offer().sign(&creator).await.unwrap();
+        assert_eq!(signed.signer().unwrap(), creator.address());
+
+        // Offers survive the trip to the executor
+        let received = SignedDealOffer::from_json(&signed.to_json().unwrap()).unwrap();
+        assert_eq!(received, signed);
+        let acceptance = received
+            .accept(&executor, BlockNum(999), &policy)
+            .await
+            .unwrap();
+        assert_eq!(acceptance.verify(&signed).unwrap(), signed.offer.proposal);
+
+        // Only the creator can sign an offer
+        let err = offer().sign(&executor).await.unwrap_err();
+        assert!(err.to_string().contains("not the creator"));
+
+        // Changing any part of a signed offer breaks the signature
+        let mut tampered = signed.clone();
+        tampered.offer.proposal.price *= 2;
+        assert!(tampered.verify().is_err());
+        assert!(tampered
+            .evaluate(executor.address(), BlockNum(0), &policy)
+            .is_err());
+        // Including the parts of the proposal that aren't sent to the contract
+        let mut repriced = signed.clone();
+        let pricing = repriced.offer.proposal.pricing.as_mut().unwrap();
+        pricing.price.rounding = Rounding::Up;
+        assert!(repriced.verify().is_err());
+        let mut unpriced = signed.clone();
+        unpriced.offer.proposal.pricing = None;
+        assert!(unpriced.verify().is_err());
+        let mut repieced = signed.clone();
+        repieced.offer.proposal.piece_commitment =
+            Some(piece_commitment([2; 1024].as_slice()).unwrap());
+        assert!(repieced.verify().is_err());
+        let mut unpieced = signed.clone();
+        unpieced.offer.proposal.piece_commitment = None;
+        assert!(unpieced.verify().is_err());
+        let mut replayed = signed.clone();
+        replayed.offer.chain_id = 5;
+        assert!(replayed.verify().is_err());
+
+        // Offers to someone else, expired offers and deals the executor won't take
+        let err = signed
+            .evaluate(creator.address(), BlockNum(0), &policy)
+            .unwrap_err();
+        assert!(err.to_string().contains("The offer is made to"));
+        let err = signed
+            .evaluate(executor.address(), BlockNum(1000), &policy)
+            .unwrap_err();
+        assert!(err.to_string().contains("expired at block 1000"));
+        let picky = ValidationPolicy::default().with_rule(MaxFileSize(512));
+        let err = signed
+            .evaluate(executor.address(), BlockNum(0), &picky)
+            .unwrap_err();
+        assert!(matches!(err, BanyanError::InvalidProposal(_)));
+
+        // An acceptance only counts for the offer it accepted, signed by its executor
+        let other = DealOffer {
+            nonce: U256::from(8),
+            ..offer()
+        }
+        .sign(&creator)
+        .await
+        .unwrap();
+        assert!(acceptance.verify(&other).is_err());
+        let mut forged = acceptance;
+        forged.signature = signed.signature;
+        assert!(forged.verify(&signed).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    error::{BanyanError, Result},
+    token::{decimal_u256, Rounding, TokenAmount},
+    types::BlockNum,
+};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/*
+ * Pricing models for the price and collateral of a deal.
+ * Status: A model is a rate plus what it's charged per: the whole deal, a TiB, a TiB per block or
+ * a TiB per day. Days are estimated from the length of the deal in blocks and the chain's block
+ * time. Every quote keeps the numbers it was derived from, so a proposal can explain its price.
+ */
+
+/// The number of bytes in a TiB, the unit of storage rates are quoted per
+pub const BYTES_PER_TIB: u64 = 1 << 40;
+
+/// The number of seconds in a day
+pub const SECONDS_PER_DAY: u64 = 86_400;
+
+/// The time between blocks on Ethereum mainnet, which we estimate days with by default
+pub const DEFAULT_SECONDS_PER_BLOCK: u64 = 12;
+
+/// PricingModel - How an amount of tokens is charged for a deal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PricingModel {
+    /// The same amount whatever the size or length of the deal
+    Flat(TokenAmount),
+    /// An amount per TiB stored, whatever the length of the deal
+    PerTib(TokenAmount),
+    /// An amount per TiB stored, for every block of the deal
+    PerTibPerBlock(TokenAmount),
+    /// An amount per TiB stored, for every day of the deal (estimated from the block time)
+    PerTibPerDay(TokenAmount),
+}
+
+/// Nothing per TiB
+impl Default for PricingModel {
+    fn default() -> Self {
+        PricingModel::PerTib(TokenAmount::default())
+    }
+}
+
+impl PricingModel {
+    /// The amount charged per unit of the model
+    pub fn rate(&self) -> &TokenAmount {
+        match self {
+            PricingModel::Flat(rate)
+            | PricingModel::PerTib(rate)
+            | PricingModel::PerTibPerBlock(rate)
+            | PricingModel::PerTibPerDay(rate) => rate,
+        }
+    }
+
+    /// quote - Price a deal with this model
+    /// # Arguments
+    /// * `file_size` - The size of the file in the deal, in bytes
+    /// * `deal_length_in_blocks` - The length of the deal in blocks
+    /// * `seconds_per_block` - The chain's block time, used to estimate the length of the deal in days
+    /// * `rounding` - How to round the total to the token's smallest unit
+    /// # Returns
+    /// * `PriceQuote` - The total, along with how it was derived
+    /// # Errors
+    /// * `BanyanError::Math` - If the total doesn't fit in a U256
+    pub fn quote(
+        &self,
+        file_size: u64,
+        deal_length_in_blocks: BlockNum,
+        seconds_per_block: u64,
+        rounding: Rounding,
+    ) -> Result<PriceQuote> {
+        let size = U256::from(file_size);
+        let blocks = U256::from(deal_length_in_blocks.0);
+        let tib = U256::from(BYTES_PER_TIB);
+        // Each factor fits in a u64, so none of these products can overflow a U256
+        let (multiplier, divisor) = match self {
+            PricingModel::Flat(_) => (U256::one(), U256::one()),
+            PricingModel::PerTib(_) => (size, tib),
+            PricingModel::PerTibPerBlock(_) => (size * blocks, tib),
+            PricingModel::PerTibPerDay(_) => (
+                size * blocks * U256::from(seconds_per_block),
+                tib * U256::from(SECONDS_PER_DAY),
+            ),
+        };
+        let total = self.rate().mul_div(multiplier, divisor, rounding)?;
+        Ok(PriceQuote {
+            model: self.clone(),
+            file_size,
+            deal_length_in_blocks,
+            seconds_per_block,
+            multiplier,
+            divisor,
+            rounding,
+            total,
+        })
+    }
+}
+
+/// Display the model as its rate and unit, e.g. "0.5 USDC per TiB per day"
+impl Display for PricingModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            PricingModel::Flat(rate) => write!(f, "{} flat", rate),
+            PricingModel::PerTib(rate) => write!(f, "{} per TiB", rate),
+            PricingModel::PerTibPerBlock(rate) => write!(f, "{} per TiB per block", rate),
+            PricingModel::PerTibPerDay(rate) => write!(f, "{} per TiB per day", rate),
+        }
+    }
+}
+
+/// PriceQuote - An amount charged for a deal, and how it was derived from a PricingModel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceQuote {
+    /// The model the amount was charged with
+    pub model: PricingModel,
+    /// The size of the file, in bytes
+    pub file_size: u64,
+    /// The length of the deal, in blocks
+    pub deal_length_in_blocks: BlockNum,
+    /// The block time days were estimated with
+    pub seconds_per_block: u64,
+    /// What the rate was multiplied by
+    #[serde(with = "decimal_u256")]
+    pub multiplier: U256,
+    /// What the product was divided by
+    #[serde(with = "decimal_u256")]
+    pub divisor: U256,
+    /// How the total was rounded to the token's smallest unit
+    pub rounding: Rounding,
+    /// The amount charged: rate * multiplier / divisor
+    pub total: TokenAmount,
+}
+
+/// Display the derivation of the total, e.g.
+/// "2 per TiB * 1048576 B / 1099511627776 B per TiB = 0.000001907348632812 (rounded Nearest)"
+impl Display for PriceQuote {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let rate = self.model.rate();
+        match &self.model {
+            PricingModel::Flat(_) => write!(f, "{} flat", rate)?,
+            PricingModel::PerTib(_) => write!(
+                f,
+                "{} per TiB * {} B / {} B per TiB",
+                rate, self.file_size, BYTES_PER_TIB
+            )?,
+            PricingModel::PerTibPerBlock(_) => write!(
+                f,
+                "{} per TiB per block * {} B / {} B per TiB * {} blocks",
+                rate, self.file_size, BYTES_PER_TIB, self.deal_length_in_blocks
+            )?,
+            PricingModel::PerTibPerDay(_) => write!(
+                f,
+                "{} per TiB per day * {} B / {} B per TiB * {} blocks * {} s per block / {} s per day",
+                rate,
+                self.file_size,
+                BYTES_PER_TIB,
+                self.deal_length_in_blocks,
+                self.seconds_per_block,
+                SECONDS_PER_DAY
+            )?,
+        }
+        write!(f, " = {} (rounded {:?})", self.total, self.rounding)
+    }
+}
+
+/// DealPricing - How the price and collateral of a deal proposal were derived
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DealPricing {
+    /// How the price paid to the executor was derived
+    pub price: PriceQuote,
+    /// How the collateral the executor must post was derived
+    pub collateral: PriceQuote,
+}
+
+impl DealPricing {
+    /// quote - Price and collateralize a deal
+    /// # Arguments
+    /// * `price` - The model the price is charged with
+    /// * `collateral` - The model the collateral is charged with
+    /// * `file_size`, `deal_length_in_blocks`, `seconds_per_block`, `rounding` - As for `PricingModel::quote`
+    /// # Errors
+    /// * `BanyanError::Math` - If the price and collateral are in tokens with different decimals
+    ///   or symbols, or don't fit in a U256
+    pub fn quote(
+        price: &PricingModel,
+        collateral: &PricingModel,
+        file_size: u64,
+        deal_length_in_blocks: BlockNum,
+        seconds_per_block: u64,
+        rounding: Rounding,
+    ) -> Result<DealPricing> {
+        let (rate, collateral_rate) = (price.rate(), collateral.rate());
+        let different_symbols = matches!(
+            (rate.symbol(), collateral_rate.symbol()),
+            (Some(a), Some(b)) if a != b
+        );
+        if rate.decimals() != collateral_rate.decimals() || different_symbols {
+            return Err(BanyanError::Math(format!(
+                "The price ({}) and collateral ({}) must be in the same token",
+                price, collateral
+            )));
+        }
+        Ok(DealPricing {
+            price: price.quote(
+                file_size,
+                deal_length_in_blocks,
+                seconds_per_block,
+                rounding,
+            )?,
+            collateral: collateral.quote(
+                file_size,
+                deal_length_in_blocks,
+                seconds_per_block,
+                rounding,
+            )?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_scale_with_size_and_length() {
+        let rate = TokenAmount::new(U256::from(BYTES_PER_TIB), 0).unwrap();
+        let quote = |model: PricingModel, blocks| {
+            model
+                .quote(1024, BlockNum(blocks), 12, Rounding::Nearest)
+                .unwrap()
+                .total
+                .amount()
+        };
+        // A short deal and a long deal only differ for the duration-aware models
+        for blocks in [10, 10_000_000] {
+            assert_eq!(
+                quote(PricingModel::Flat(rate.clone()), blocks),
+                rate.amount()
+            );
+            assert_eq!(
+                quote(PricingModel::PerTib(rate.clone()), blocks),
+                1024.into()
+            );
+        }
+        assert_eq!(
+            quote(PricingModel::PerTibPerBlock(rate.clone()), 10),
+            10_240.into()
+        );
+        assert_eq!(
+            quote(PricingModel::PerTibPerBlock(rate.clone()), 10_000_000),
+            U256::from(10_240_000_000u64)
+        );
+        // A day of 12 second blocks
+        assert_eq!(
+            quote(PricingModel::PerTibPerDay(rate.clone()), 7_200),
+            1024.into()
+        );
+        assert_eq!(
+            quote(PricingModel::PerTibPerDay(rate), 72_000),
+            10_240.into()
+        );
+    }
+
+    #[test]
+    fn quotes_explain_themselves() {
+        let rate: TokenAmount = "2 USDC".parse().unwrap();
+        let quote = PricingModel::PerTibPerDay(rate)
+            .quote(BYTES_PER_TIB / 2, BlockNum(14_400), 12, Rounding::Down)
+            .unwrap();
+        assert_eq!(quote.total.to_string(), "2 USDC");
+        assert_eq!(
+            quote.to_string(),
+            "2 USDC per TiB per day * 549755813888 B / 1099511627776 B per TiB * 14400 blocks \
+             * 12 s per block / 86400 s per day = 2 USDC (rounded Down)"
+        );
+
+        let err = DealPricing::quote(
+            &PricingModel::Flat("1 USDC".parse().unwrap()),
+            &PricingModel::Flat("1 DAI".parse().unwrap()),
+            1,
+            BlockNum(1),
+            DEFAULT_SECONDS_PER_BLOCK,
+            Rounding::Nearest,
+        )
+        .unwrap_err();
+        assert!(matches!(err, BanyanError::Math(_)));
+
+        // Tokens with the same decimals are still different tokens
+        let quote = |collateral: &str| {
+            DealPricing::quote(
+                &PricingModel::Flat("1 USDC".parse().unwrap()),
+                &PricingModel::Flat(collateral.parse().unwrap()),
+                1,
+                BlockNum(1),
+                DEFAULT_SECONDS_PER_BLOCK,
+                Rounding::Nearest,
+            )
+        };
+        assert!(matches!(quote("1 USDT"), Err(BanyanError::Math(_))));
+        assert!(quote("2 usdc").is_ok());
+    }
+    #[test]
+    fn quotes_are_written_as_decimals() {
+        let quote = PricingModel::PerTib("2 USDC".parse().unwrap())
+            .quote(BYTES_PER_TIB / 2, BlockNum(1), 12, Rounding::Down)
+            .unwrap();
+        let mut json = serde_json::to_value(&quote).unwrap();
+        assert_eq!(json["multiplier"], "549755813888");
+        assert_eq!(json["divisor"], "1099511627776");
+        assert_eq!(
+            serde_json::from_value::<PriceQuote>(json.clone()).unwrap(),
+            quote
+        );
+        // Quotes written with hex before are still read
+        json["multiplier"] = "0x8000000000".into();
+        json["divisor"] = "0x10000000000".into();
+        assert_eq!(serde_json::from_value::<PriceQuote>(json).unwrap(), quote);
+    }
+}
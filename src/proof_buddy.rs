@@ -0,0 +1,354 @@
+use crate::{
+    error::{BanyanError, Result},
+    types::{BlockNum, DealID, ProofBuddyMessageType},
+};
+use ethers::types::{Bytes, U256};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, future::Future};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/*
+ * The messages passed between the parts of a proof buddy.
+ * Status: The scheduler, the proof submitter and the withdrawal worker each handle some message
+ * types. A router hands each message to whichever worker was registered for its type, over a
+ * bounded tokio mpsc channel per worker, so a slow worker slows down whoever sends to it.
+ * The encoding is stable: `{"type": <ProofBuddyMessageType>, "payload": {...}}` in JSON or CBOR,
+ * with deal IDs and blocks as numbers and proofs and amounts as 0x-prefixed hex.
+ */
+
+/// How many messages can wait for a worker before sending to it waits too
+pub const WORKER_QUEUE_LEN: usize = 64;
+
+/// ProofBuddyMessage - Something for a proof buddy to do, with what's needed to do it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum ProofBuddyMessage {
+    /// Post a proof for a window of a deal
+    SubmitProof {
+        /// The deal the proof is for
+        deal_id: DealID,
+        /// The first block of the window the proof is for
+        window: BlockNum,
+        /// The bao slice proving the window's chunk
+        proof: Bytes,
+    },
+    /// Stop working on a deal
+    Cancel {
+        /// The deal to stop working on
+        deal_id: DealID,
+    },
+    /// Ask Chainlink to check a deal's proofs and settle it
+    InitiateChainlinkFinalization {
+        /// The deal to settle
+        deal_id: DealID,
+    },
+    /// Withdraw what a deal earned
+    WithdrawEarnings {
+        /// The deal the earnings are from
+        deal_id: DealID,
+        /// How much to withdraw, in the token's smallest unit
+        amount: U256,
+    },
+}
+
+impl ProofBuddyMessage {
+    /// The type of the message, which says who handles it
+    pub fn message_type(&self) -> ProofBuddyMessageType {
+        match self {
+            ProofBuddyMessage::SubmitProof { .. } => ProofBuddyMessageType::SubmitProof,
+            ProofBuddyMessage::Cancel { .. } => ProofBuddyMessageType::Cancel,
+            ProofBuddyMessage::InitiateChainlinkFinalization { .. } => {
+                ProofBuddyMessageType::InitiateChainlinkFinalization
+            }
+            ProofBuddyMessage::WithdrawEarnings { .. } => ProofBuddyMessageType::WithdrawEarnings,
+        }
+    }
+
+    /// The deal the message is about
+    pub fn deal_id(&self) -> DealID {
+        match self {
+            ProofBuddyMessage::SubmitProof { deal_id, .. }
+            | ProofBuddyMessage::Cancel { deal_id }
+            | ProofBuddyMessage::InitiateChainlinkFinalization { deal_id }
+            | ProofBuddyMessage::WithdrawEarnings { deal_id, .. } => *deal_id,
+        }
+    }
+
+    /// to_json - Encode the message as JSON
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// from_json - Decode a message from JSON
+    /// # Errors
+    /// * `BanyanError::Parse` - If the JSON isn't a message
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// to_cbor - Encode the message as CBOR
+    pub fn to_cbor(&self) -> Result<Vec<u8>> {
+        let mut cbor = vec![];
+        ciborium::ser::into_writer(self, &mut cbor)
+            .map_err(|e| BanyanError::Parse(format!("Couldn't encode {:?}: {}", self, e)))?;
+        Ok(cbor)
+    }
+
+    /// from_cbor - Decode a message from CBOR
+    /// # Errors
+    /// * `BanyanError::Parse` - If the CBOR isn't a message
+    pub fn from_cbor(cbor: &[u8]) -> Result<Self> {
+        ciborium::de::from_reader(cbor)
+            .map_err(|e| BanyanError::Parse(format!("Invalid proof buddy message: {}", e)))
+    }
+}
+
+/// MessageHandler - A worker that does what messages ask, e.g. the proof submitter
+pub trait MessageHandler: Send + 'static {
+    /// handle - Do what a message asks
+    /// # Errors
+    /// Any error stops the worker, and is returned by `ProofBuddyRouter::shutdown`. A worker that
+    /// should carry on after a failure must handle it itself.
+    fn handle(&mut self, message: ProofBuddyMessage) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Dispatcher - Somewhere to send messages, e.g. for the scheduler to hand proofs on to
+pub trait Dispatcher {
+    /// dispatch - Hand a message on to whoever handles it, waiting if they're busy
+    /// # Errors
+    /// * `BanyanError::Dispatch` - If nothing handles the message, or its handler has stopped
+    fn dispatch(&self, message: ProofBuddyMessage) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// A channel to a single worker
+impl Dispatcher for mpsc::Sender<ProofBuddyMessage> {
+    async fn dispatch(&self, message: ProofBuddyMessage) -> Result<()> {
+        self.send(message).await.map_err(|e| {
+            BanyanError::Dispatch(format!(
+                "The worker for {:?} has stopped",
+                e.0.message_type()
+            ))
+        })
+    }
+}
+
+/// ProofBuddyRouter - Hands each message to the worker registered for its type
+#[derive(Default)]
+pub struct ProofBuddyRouter {
+    /// The channel to the worker for each type of message
+    routes: HashMap<ProofBuddyMessageType, mpsc::Sender<ProofBuddyMessage>>,
+    /// The running workers
+    workers: Vec<JoinHandle<Result<()>>>,
+}
+
+impl ProofBuddyRouter {
+    /// Create a router with no workers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// route - Start a worker on the current tokio runtime, and send it every message of the
+    /// given types
+    /// # Arguments
+    /// * `message_types` - The types of message the worker handles
+    /// * `handler` - The worker
+    /// # Errors
+    /// * `BanyanError::Dispatch` - If another worker already handles one of the types. The
+    ///   worker isn't started.
+    pub fn route<H: MessageHandler>(
+        &mut self,
+        message_types: &[ProofBuddyMessageType],
+        mut handler: H,
+    ) -> Result<()> {
+        if let Some(taken) = message_types
+            .iter()
+            .find(|message_type| self.routes.contains_key(message_type))
+        {
+            return Err(BanyanError::Dispatch(format!(
+                "{:?} messages already have a worker",
+                taken
+            )));
+        }
+        let (sender, mut receiver) = mpsc::channel(WORKER_QUEUE_LEN);
+        for message_type in message_types {
+            self.routes.insert(*message_type, sender.clone());
+        }
+        self.workers.push(tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                handler.handle(message).await?;
+            }
+            Ok(())
+        }));
+        Ok(())
+    }
+
+    /// Whether a worker handles a type of message
+    pub fn routes(&self, message_type: ProofBuddyMessageType) -> bool {
+        self.routes.contains_key(&message_type)
+    }
+
+    /// shutdown - Stop taking messages, and wait for the workers to handle what they were sent
+    /// # Errors
+    /// The first error a worker stopped with, or `BanyanError::Dispatch` if one panicked
+    pub async fn shutdown(mut self) -> Result<()> {
+        self.routes.clear();
+        let mut result = Ok(());
+        for worker in self.workers.drain(..) {
+            let stopped = worker.await.unwrap_or_else(|e| {
+                Err(BanyanError::Dispatch(format!("A worker panicked: {}", e)))
+            });
+            if result.is_ok() {
+                result = stopped;
+            }
+        }
+        result
+    }
+}
+
+impl Dispatcher for ProofBuddyRouter {
+    async fn dispatch(&self, message: ProofBuddyMessage) -> Result<()> {
+        match self.routes.get(&message.message_type()) {
+            Some(worker) => worker.dispatch(message).await,
+            None => Err(BanyanError::Dispatch(format!(
+                "Nothing handles {:?} messages",
+                message.message_type()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn submit_proof() -> ProofBuddyMessage {
+        ProofBuddyMessage::SubmitProof {
+            deal_id: DealID(7),
+            window: BlockNum(100),
+            proof: vec![0xde, 0xad].into(),
+        }
+    }
+
+    #[test]
+    fn stable_encodings() {
+        let messages = [
+            (
+                submit_proof(),
+                r#"{"type":"SubmitProof","payload":{"deal_id":7,"window":100,"proof":"0xdead"}}"#,
+            ),
+            (
+                ProofBuddyMessage::Cancel { deal_id: DealID(7) },
+                r#"{"type":"Cancel","payload":{"deal_id":7}}"#,
+            ),
+            (
+                ProofBuddyMessage::InitiateChainlinkFinalization { deal_id: DealID(7) },
+                r#"{"type":"InitiateChainlinkFinalization","payload":{"deal_id":7}}"#,
+            ),
+            (
+                ProofBuddyMessage::WithdrawEarnings {
+                    deal_id: DealID(7),
+                    amount: U256::from(1_000_000),
+                },
+                r#"{"type":"WithdrawEarnings","payload":{"deal_id":7,"amount":"0xf4240"}}"#,
+            ),
+        ];
+        for (message, json) in messages {
+            assert_eq!(message.to_json().unwrap(), json);
+            assert_eq!(ProofBuddyMessage::from_json(json).unwrap(), message);
+            let cbor = message.to_cbor().unwrap();
+            assert_eq!(ProofBuddyMessage::from_cbor(&cbor).unwrap(), message);
+            assert_eq!(message.deal_id(), DealID(7));
+        }
+
+        // {"type": "Cancel", "payload": {"deal_id": 7}}
+        let cancel = "a264747970656643616e63656c677061796c6f6164a1676465616c5f696407";
+        assert_eq!(
+            ProofBuddyMessage::Cancel { deal_id: DealID(7) }
+                .to_cbor()
+                .unwrap(),
+            ethers::utils::hex::decode(cancel).unwrap()
+        );
+        assert!(matches!(
+            ProofBuddyMessage::from_json(r#"{"type":"Reboot","payload":{}}"#),
+            Err(BanyanError::Parse(_))
+        ));
+        assert!(ProofBuddyMessage::from_cbor(&[0xff]).is_err());
+    }
+
+    /// A worker that remembers what it was sent, and fails on deal 0
+    struct Recorder(Arc<Mutex<Vec<ProofBuddyMessage>>>);
+
+    impl MessageHandler for Recorder {
+        async fn handle(&mut self, message: ProofBuddyMessage) -> Result<()> {
+            if message.deal_id() == DealID(0) {
+                return Err(BanyanError::Chain("deal 0 doesn't exist".to_string()));
+            }
+            self.0.lock().unwrap().push(message);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_routed_by_type() {
+        let submitted = Arc::new(Mutex::new(vec![]));
+        let withdrawn = Arc::new(Mutex::new(vec![]));
+        let mut router = ProofBuddyRouter::new();
+        router
+            .route(
+                &[ProofBuddyMessageType::SubmitProof],
+                Recorder(submitted.clone()),
+            )
+            .unwrap();
+        router
+            .route(
+                &[
+                    ProofBuddyMessageType::WithdrawEarnings,
+                    ProofBuddyMessageType::Cancel,
+                ],
+                Recorder(withdrawn.clone()),
+            )
+            .unwrap();
+        assert!(matches!(
+            router.route(
+                &[ProofBuddyMessageType::Cancel],
+                Recorder(Default::default())
+            ),
+            Err(BanyanError::Dispatch(_))
+        ));
+        assert!(!router.routes(ProofBuddyMessageType::InitiateChainlinkFinalization));
+
+        let cancel = ProofBuddyMessage::Cancel { deal_id: DealID(3) };
+        router.dispatch(submit_proof()).await.unwrap();
+        router.dispatch(cancel.clone()).await.unwrap();
+        router.dispatch(submit_proof()).await.unwrap();
+        let err = router
+            .dispatch(ProofBuddyMessage::InitiateChainlinkFinalization { deal_id: DealID(3) })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Dispatch error: Nothing handles InitiateChainlinkFinalization messages"
+        );
+        router.shutdown().await.unwrap();
+        assert_eq!(*submitted.lock().unwrap(), vec![submit_proof(); 2]);
+        assert_eq!(*withdrawn.lock().unwrap(), vec![cancel]);
+
+        // A worker that fails stops, and says why
+        let mut router = ProofBuddyRouter::new();
+        router
+            .route(
+                &[ProofBuddyMessageType::Cancel],
+                Recorder(Default::default()),
+            )
+            .unwrap();
+        router
+            .dispatch(ProofBuddyMessage::Cancel { deal_id: DealID(0) })
+            .await
+            .unwrap();
+        assert!(matches!(
+            router.shutdown().await,
+            Err(BanyanError::Chain(_))
+        ));
+    }
+}
@@ -0,0 +1,518 @@
+use crate::{
+    error::{BanyanError, Result},
+    proofs::{compute_random_block_choice_from_hash, extract_slice},
+    types::{Blake3Hash, BlockNum, DealID, Proof},
+};
+use bao::decode::SliceDecoder;
+use ethers::{
+    signers::Signer,
+    types::{Address, Bytes, Signature, H256},
+};
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read, Seek};
+
+/*
+ * A versioned, self-describing binary format for proofs.
+ * Status: Version 2. An envelope carries everything needed to check a proof without the deal:
+ * which block it answers, the length of the file, which chunk that block chose from it, the Blake3
+ * root the slice was built against and the slice itself, optionally signed by the executor.
+ * Version 1 didn't have the file length, so the chunk couldn't be checked, and isn't read.
+ * Integers are big-endian.
+ *
+ * magic "BNYP" | version u16 | flags u8 | deal ID u64 | target block u64 | target block hash
+ * [32] | file length u64 | chunk offset u64 | chunk size u64 | root [32] | slice length u32 |
+ * slice | signature [65]
+ *
+ * Bit 0 of the flags says there's a signature. The signature is an Ethereum signed message of
+ * the envelope encoded without one.
+ */
+
+/// The bytes every encoded proof starts with
+pub const PROOF_MAGIC: [u8; 4] = *b"BNYP";
+
+/// The version of the format that's written, and the only one that's read
+pub const PROOF_FORMAT_VERSION: u16 = 2;
+
+/// The bytes before the slice
+const HEADER_LEN: usize = 115;
+
+/// The flag saying a signature follows the slice
+const FLAG_SIGNED: u8 = 1;
+
+/// The bytes in an encoded signature: r, s and v
+const SIGNATURE_LEN: usize = 65;
+
+/// ProofEnvelope - A proof, along with what it proves
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofEnvelope {
+    /// The deal the proof is for
+    pub deal_id: DealID,
+    /// The block whose hash chose the chunk, i.e. the start of the proof's window
+    pub target_block: BlockNum,
+    /// The hash of the target block
+    pub target_block_hash: H256,
+    /// The size of the whole file, in bytes, which the chunk was chosen from
+    pub file_length: u64,
+    /// Where the proven chunk starts in the file, in bytes
+    pub chunk_offset: u64,
+    /// The size of the proven chunk, in bytes
+    pub chunk_size: u64,
+    /// The Blake3 hash of the whole file, which the slice was built against
+    pub root: Blake3Hash,
+    /// The bao slice of the chunk
+    pub slice: Bytes,
+    /// The executor's signature of the envelope, if it's been signed
+    #[serde(default)]
+    pub signature: Option<Signature>,
+}
+
+impl ProofEnvelope {
+    /// build - Prove the chunk a block chose from a file
+    /// # Arguments
+    /// * `deal_id` - The deal the proof is for
+    /// * `target_block` - The block whose hash chooses the chunk
+    /// * `target_block_hash` - The hash of that block
+    /// * `root` - The Blake3 hash of the file
+    /// * `file_handle` - The file
+    /// * `obao_handle` - The bao outboard tree of the file
+    /// * `file_length` - The size of the file
+    /// # Errors
+    /// * `BanyanError::Proof` - If the file is empty, as it has no chunk to prove
+    /// * `BanyanError::Io` - If the file or outboard tree can't be read
+    pub fn build<R: Read + Seek, O: Read + Seek>(
+        deal_id: DealID,
+        target_block: BlockNum,
+        target_block_hash: H256,
+        root: Blake3Hash,
+        file_handle: R,
+        obao_handle: O,
+        file_length: u64,
+    ) -> Result<Self> {
+        if file_length == 0 {
+            return Err(BanyanError::Proof(
+                "There's nothing to prove of an empty file".to_string(),
+            ));
+        }
+        let (chunk_offset, chunk_size) =
+            compute_random_block_choice_from_hash(target_block_hash, file_length);
+        let slice = extract_slice(file_handle, obao_handle, chunk_offset, chunk_size)?;
+        Ok(ProofEnvelope {
+            deal_id,
+            target_block,
+            target_block_hash,
+            file_length,
+            chunk_offset,
+            chunk_size,
+            root,
+            slice: slice.into(),
+            signature: None,
+        })
+    }
+
+    /// encode - Write the envelope in the binary format
+    pub fn encode(&self) -> Vec<u8> {
+        let signature = self.signature.map(|signature| signature.to_vec());
+        let mut bytes = Vec::with_capacity(
+            HEADER_LEN + self.slice.len() + signature.as_ref().map_or(0, Vec::len),
+        );
+        bytes.extend_from_slice(&PROOF_MAGIC);
+        bytes.extend_from_slice(&PROOF_FORMAT_VERSION.to_be_bytes());
+        bytes.push(if signature.is_some() { FLAG_SIGNED } else { 0 });
+        bytes.extend_from_slice(&self.deal_id.0.to_be_bytes());
+        bytes.extend_from_slice(&self.target_block.0.to_be_bytes());
+        bytes.extend_from_slice(self.target_block_hash.as_bytes());
+        bytes.extend_from_slice(&self.file_length.to_be_bytes());
+        bytes.extend_from_slice(&self.chunk_offset.to_be_bytes());
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        bytes.extend_from_slice(self.root.as_bytes());
+        bytes.extend_from_slice(&(self.slice.len() as u32).to_be_bytes());
+        debug_assert_eq!(bytes.len(), HEADER_LEN);
+        bytes.extend_from_slice(&self.slice);
+        if let Some(signature) = signature {
+            bytes.extend_from_slice(&signature);
+        }
+        bytes
+    }
+
+    /// decode - Read an envelope in the binary format
+    /// # Arguments
+    /// * `bytes` - The encoded envelope, and nothing else
+    /// # Errors
+    /// * `BanyanError::Proof` - If the bytes aren't a proof, are a version we can't read, are
+    ///   cut short or have anything after the proof
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut input = Input(bytes);
+        if input.take(4)? != PROOF_MAGIC {
+            return Err(BanyanError::Proof(
+                "Not a proof: it doesn't start with BNYP".to_string(),
+            ));
+        }
+        let version = u16::from_be_bytes(input.array()?);
+        if version != PROOF_FORMAT_VERSION {
+            return Err(BanyanError::Proof(format!(
+                "Proof format version {} isn't supported, only {} is",
+                version, PROOF_FORMAT_VERSION
+            )));
+        }
+        let [flags] = input.array()?;
+        if flags & !FLAG_SIGNED != 0 {
+            return Err(BanyanError::Proof(format!(
+                "Unknown proof flags {:#04x}",
+                flags
+            )));
+        }
+        let deal_id = DealID(input.u64()?);
+        let target_block = BlockNum(input.u64()?);
+        let target_block_hash = H256(input.array()?);
+        let file_length = input.u64()?;
+        let chunk_offset = input.u64()?;
+        let chunk_size = input.u64()?;
+        let root = Blake3Hash(input.array::<32>()?.into());
+        let slice_len = u32::from_be_bytes(input.array()?) as usize;
+        let slice = input.take(slice_len)?.to_vec().into();
+        let signature = if flags & FLAG_SIGNED != 0 {
+            Some(
+                Signature::try_from(input.take(SIGNATURE_LEN)?)
+                    .map_err(|e| BanyanError::Proof(format!("Invalid signature: {}", e)))?,
+            )
+        } else {
+            None
+        };
+        if !input.0.is_empty() {
+            return Err(BanyanError::Proof(format!(
+                "{} bytes left over after the proof",
+                input.0.len()
+            )));
+        }
+        Ok(ProofEnvelope {
+            deal_id,
+            target_block,
+            target_block_hash,
+            file_length,
+            chunk_offset,
+            chunk_size,
+            root,
+            slice,
+            signature,
+        })
+    }
+
+    /// to_json - The envelope as pretty JSON, with its magic and version, for debugging
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&JsonEnvelope {
+            magic: String::from_utf8_lossy(&PROOF_MAGIC).into_owned(),
+            version: PROOF_FORMAT_VERSION,
+            proof: self.clone(),
+        })
+        .map_err(|e| BanyanError::Parse(e.to_string()))
+    }
+
+    /// from_json - Read an envelope back from its JSON form
+    /// # Errors
+    /// * `BanyanError::Parse` - If the JSON isn't an envelope
+    /// * `BanyanError::Proof` - If it's for another format or version
+    pub fn from_json(json: &str) -> Result<Self> {
+        let envelope: JsonEnvelope =
+            serde_json::from_str(json).map_err(|e| BanyanError::Parse(e.to_string()))?;
+        if envelope.magic.as_bytes() != PROOF_MAGIC || envelope.version != PROOF_FORMAT_VERSION {
+            return Err(BanyanError::Proof(format!(
+                "Can't read a {} proof of version {}",
+                envelope.magic, envelope.version
+            )));
+        }
+        Ok(envelope.proof)
+    }
+
+    /// verify_slice - Check the chunk is the one the target block chose, and that the slice
+    /// proves it against the root
+    /// # Returns
+    /// * `Vec<u8>` - The proven chunk of the file
+    /// # Errors
+    /// * `BanyanError::Proof` - If the file is empty, the target block chose another chunk, or
+    ///   the slice doesn't match the root
+    pub fn verify_slice(&self) -> Result<Vec<u8>> {
+        if self.file_length == 0 {
+            return Err(BanyanError::Proof(
+                "There's nothing to prove of an empty file".to_string(),
+            ));
+        }
+        let chosen =
+            compute_random_block_choice_from_hash(self.target_block_hash, self.file_length);
+        if (self.chunk_offset, self.chunk_size) != chosen {
+            return Err(BanyanError::Proof(format!(
+                "Block {} chose bytes {}..{} of the {} byte file, not {}..{}",
+                self.target_block,
+                chosen.0,
+                chosen.0 + chosen.1,
+                self.file_length,
+                self.chunk_offset,
+                self.chunk_offset.saturating_add(self.chunk_size)
+            )));
+        }
+        let mut chunk = vec![];
+        SliceDecoder::new(
+            Cursor::new(&self.slice),
+            &self.root.hash(),
+            self.chunk_offset,
+            self.chunk_size,
+        )
+        .read_to_end(&mut chunk)
+        .map_err(|e| {
+            BanyanError::Proof(format!(
+                "The slice doesn't prove bytes {}..{} of {}: {}",
+                self.chunk_offset,
+                self.chunk_offset.saturating_add(self.chunk_size),
+                self.root,
+                e
+            ))
+        })?;
+        Ok(chunk)
+    }
+
+    /// The bytes the executor signs: the envelope encoded without a signature
+    pub fn signing_payload(&self) -> Vec<u8> {
+        ProofEnvelope {
+            signature: None,
+            ..self.clone()
+        }
+        .encode()
+    }
+
+    /// sign - Sign the envelope, replacing any signature it had
+    /// # Arguments
+    /// * `signer` - The executor's signer, e.g. a `LocalWallet`
+    /// # Errors
+    /// * `BanyanError::Proof` - If the signer fails
+    pub async fn sign<S: Signer>(mut self, signer: &S) -> Result<Self> {
+        let signature = signer
+            .sign_message(self.signing_payload())
+            .await
+            .map_err(|e| BanyanError::Proof(format!("Couldn't sign the proof: {}", e)))?;
+        self.signature = Some(signature);
+        Ok(self)
+    }
+
+    /// signer - Who signed the envelope, if anyone did
+    /// # Errors
+    /// * `BanyanError::Proof` - If the signature is invalid
+    pub fn signer(&self) -> Result<Option<Address>> {
+        self.signature
+            .map(|signature| {
+                signature
+                    .recover(self.signing_payload())
+                    .map_err(|e| BanyanError::Proof(format!("Invalid signature: {}", e)))
+            })
+            .transpose()
+    }
+}
+
+/// The proof the contract takes, which is just the slice and what it's for
+impl From<ProofEnvelope> for Proof {
+    fn from(envelope: ProofEnvelope) -> Self {
+        Proof {
+            bao_proof_data: envelope.slice,
+            deal_id: envelope.deal_id,
+            target_block_start: envelope.target_block,
+        }
+    }
+}
+
+/// The JSON form of an envelope, which says what it is like the binary form does
+#[derive(Serialize, Deserialize)]
+struct JsonEnvelope {
+    magic: String,
+    version: u16,
+    #[serde(flatten)]
+    proof: ProofEnvelope,
+}
+
+/// What's left of an encoded envelope to read
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    /// Read the next `n` bytes
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(BanyanError::Proof(format!(
+                "The proof is cut short: {} more bytes were expected, but only {} are left",
+                n,
+                self.0.len()
+            )));
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    /// Read the next `N` bytes as an array
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    /// Read a big-endian u64
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::{gen_obao, CHUNK_SIZE};
+    use ethers::signers::LocalWallet;
+    use std::fs::File;
+
+    /// The golden vectors, which are written again if this is set
+    const UPDATE_GOLDEN: &str = "BANYAN_UPDATE_GOLDEN";
+    const GOLDEN_BINARY: &str = "test_files/ethereum.pdf.proof";
+    const GOLDEN_JSON: &str = "test_files/ethereum.pdf.proof.json";
+
+    /// A signed proof of test_files/ethereum.pdf, made the same way every time
+    async fn golden_proof() -> ProofEnvelope {
+        let path = "test_files/ethereum.pdf";
+        let file_length = std::fs::metadata(path).unwrap().len();
+        let (obao, root) = gen_obao(&mut File::open(path).unwrap()).unwrap();
+        let wallet: LocalWallet =
+            "0000000000000000000000000000000000000000000000000000000000000001"
+                .parse()
+                .unwrap();
+        ProofEnvelope::build(
+            DealID(42),
+            BlockNum(1_234_567),
+            H256::repeat_byte(0x5a),
+            Blake3Hash(root),
+            File::open(path).unwrap(),
+            Cursor::new(obao),
+            file_length,
+        )
+        .unwrap()
+        .sign(&wallet)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn golden_vectors() {
+        let proof = golden_proof().await;
+        let encoded = proof.encode();
+        let json = proof.to_json().unwrap();
+        if std::env::var_os(UPDATE_GOLDEN).is_some() {
+            std::fs::write(GOLDEN_BINARY, &encoded).unwrap();
+            std::fs::write(GOLDEN_JSON, &json).unwrap();
+        }
+        assert_eq!(encoded, std::fs::read(GOLDEN_BINARY).unwrap());
+        assert_eq!(json, std::fs::read_to_string(GOLDEN_JSON).unwrap());
+
+        // The header says what it is and which chunk the block chose
+        let file_length = std::fs::metadata("test_files/ethereum.pdf").unwrap().len();
+        assert_eq!(&encoded[..7], b"BNYP\x00\x02\x01");
+        assert_eq!(encoded[7..15], 42u64.to_be_bytes());
+        assert_eq!(encoded[55..63], file_length.to_be_bytes());
+        assert_eq!(proof.file_length, file_length);
+        assert_eq!(
+            (proof.chunk_offset, proof.chunk_size),
+            compute_random_block_choice_from_hash(proof.target_block_hash, file_length)
+        );
+
+        assert_eq!(ProofEnvelope::decode(&encoded).unwrap(), proof);
+        assert_eq!(ProofEnvelope::from_json(&json).unwrap(), proof);
+        let file = std::fs::read("test_files/ethereum.pdf").unwrap();
+        let start = proof.chunk_offset as usize;
+        assert_eq!(
+            proof.verify_slice().unwrap(),
+            file[start..start + proof.chunk_size as usize]
+        );
+        assert_eq!(
+            proof.signer().unwrap(),
+            Some(
+                "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"
+                    .parse()
+                    .unwrap()
+            )
+        );
+        let unsigned = ProofEnvelope {
+            signature: None,
+            ..proof.clone()
+        };
+        assert_eq!(ProofEnvelope::decode(&unsigned.encode()).unwrap(), unsigned);
+        assert_eq!(unsigned.signer().unwrap(), None);
+
+        let onchain = Proof::from(proof.clone());
+        assert_eq!(onchain.bao_proof_data, proof.slice);
+        assert_eq!(onchain.target_block_start, proof.target_block);
+    }
+
+    #[tokio::test]
+    async fn bad_proofs_are_errors() {
+        let proof = golden_proof().await;
+        let encoded = proof.encode();
+        let decode = |bytes: &[u8]| ProofEnvelope::decode(bytes).unwrap_err().to_string();
+
+        assert!(decode(b"not a proof at all").contains("BNYP"));
+        let mut future = encoded.clone();
+        future[5] = 3;
+        assert!(decode(&future).contains("version 3"));
+        let mut past = encoded.clone();
+        past[5] = 1;
+        assert!(decode(&past).contains("version 1"));
+        let mut flags = encoded.clone();
+        flags[6] = 0x81;
+        assert!(decode(&flags).contains("flags"));
+        assert!(decode(&encoded[..encoded.len() - 1]).contains("cut short"));
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(decode(&trailing).contains("left over"));
+
+        // A chunk the block didn't choose doesn't verify, even if its slice would
+        let mut moved = proof.clone();
+        moved.chunk_offset = match proof.chunk_offset.checked_sub(CHUNK_SIZE) {
+            Some(before) => before,
+            None => proof.chunk_offset + CHUNK_SIZE,
+        };
+        assert!(moved
+            .verify_slice()
+            .unwrap_err()
+            .to_string()
+            .contains("chose bytes"));
+        let mut overflowing = proof.clone();
+        overflowing.chunk_offset = u64::MAX;
+        assert!(overflowing.verify_slice().is_err());
+        let mut shrunk = proof.clone();
+        shrunk.file_length = CHUNK_SIZE;
+        assert!(shrunk.verify_slice().is_err());
+        let mut empty = proof.clone();
+        empty.file_length = 0;
+        assert!(empty
+            .verify_slice()
+            .unwrap_err()
+            .to_string()
+            .contains("empty file"));
+        // Nor can one be built
+        let (obao, root) = gen_obao(&mut Cursor::new(vec![])).unwrap();
+        assert!(matches!(
+            ProofEnvelope::build(
+                DealID(42),
+                BlockNum(1),
+                H256::repeat_byte(0x5a),
+                Blake3Hash(root),
+                Cursor::new(vec![]),
+                Cursor::new(obao),
+                0,
+            ),
+            Err(BanyanError::Proof(_))
+        ));
+        // Nor does a slice for another root
+        let mut tampered = proof.clone();
+        let mut slice = tampered.slice.to_vec();
+        *slice.last_mut().unwrap() ^= 1;
+        tampered.slice = slice.into();
+        assert!(matches!(
+            tampered.verify_slice(),
+            Err(BanyanError::Proof(_))
+        ));
+        // And a signature no longer matches once anything changes
+        assert_ne!(tampered.signer().unwrap(), proof.signer().unwrap());
+    }
+}
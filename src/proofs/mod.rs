@@ -1,14 +1,14 @@
+pub mod envelope;
 pub mod window;
 
-use crate::types::*;
-use anyhow::Result;
+use crate::{error::Result, types::*};
 use bao::encode::SliceExtractor;
 use ethers::abi::ethereum_types::BigEndianHash;
 use ethers::prelude::H256;
-use std::io::{Cursor, Read, Seek};
+use std::io::{Read, Seek};
 
 /// 1024 bytes per bao chunk
-const CHUNK_SIZE: u64 = 1024;
+pub(crate) const CHUNK_SIZE: u64 = 1024;
 
 fn get_num_chunks(size: u64) -> u64 {
     (size as f32 / CHUNK_SIZE as f32).ceil() as u64
@@ -29,23 +29,30 @@ pub fn compute_random_block_choice_from_hash(block_hash: H256, file_length: u64)
 // TODO: eventually do not load the entire file into memory.
 pub fn gen_obao<R: Read>(reader: &mut R) -> Result<(Vec<u8>, bao::Hash)> {
     let mut file_content = Vec::new();
-    reader
-        .read_to_end(&mut file_content)
-        .expect("Unable to read");
+    reader.read_to_end(&mut file_content)?;
 
     let (obao, hash) = bao::encode::outboard(&file_content);
     Ok((obao, hash)) // return the outboard encoding
 }
 
-pub async fn gen_proof<R: Read + Seek>(
+pub async fn gen_proof<R: Read + Seek, O: Read + Seek>(
     _block_number: BlockNum,
     block_hash: H256,
     file_handle: R,
-    obao_handle: Cursor<Vec<u8>>,
+    obao_handle: O,
     file_length: u64,
 ) -> Result<Vec<u8>> {
     let (chunk_offset, chunk_size) = compute_random_block_choice_from_hash(block_hash, file_length);
+    extract_slice(file_handle, obao_handle, chunk_offset, chunk_size)
+}
 
+/// The bao slice of a chunk of a file, from the file and its outboard tree
+pub(crate) fn extract_slice<R: Read + Seek, O: Read + Seek>(
+    file_handle: R,
+    obao_handle: O,
+    chunk_offset: u64,
+    chunk_size: u64,
+) -> Result<Vec<u8>> {
     let mut bao_proof_data = vec![];
     let _ = SliceExtractor::new_outboard(file_handle, obao_handle, chunk_offset, chunk_size)
         .read_to_end(&mut bao_proof_data)?;
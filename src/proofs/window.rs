@@ -1,55 +1,64 @@
-use crate::types::{BlockNum, OnChainDealInfo};
+use crate::{
+    error::{BanyanError, Result},
+    types::{BlockNum, OnChainDealInfo},
+};
 
 #[derive(Debug)]
 pub enum DealStatusError {
     Future,
     Past,
+    /// The deal's windows can't be worked out, e.g. it has a proof frequency of 0
+    Invalid(BanyanError),
 }
 
-// TODO: check this it might not be correct. write some tests. from copilot. suspect this is wrong.
+/// The first block of the window `current_block_num` falls in
 pub fn get_the_current_window(
     deal_info: &OnChainDealInfo,
     current_block_num: BlockNum,
 ) -> Result<BlockNum, DealStatusError> {
-    if current_block_num < deal_info.deal_start_block {
+    let deal_blocks = deal_info.blocks().map_err(DealStatusError::Invalid)?;
+    if current_block_num < deal_blocks.start() {
         return Err(DealStatusError::Future);
     };
-    if current_block_num >= deal_info.deal_start_block + deal_info.deal_length_in_blocks {
-        return Err(DealStatusError::Past);
-    };
-    // this should return the window start of the current block
-    let window_number = (current_block_num - deal_info.deal_start_block)
-        .0
-        .div_euclid(deal_info.proof_frequency_in_blocks.0);
-    Ok(deal_info.deal_start_block + deal_info.proof_frequency_in_blocks * window_number)
+    match deal_blocks
+        .window_of(current_block_num, deal_info.proof_frequency_in_blocks)
+        .map_err(DealStatusError::Invalid)?
+    {
+        Some((_, window)) => Ok(window.start()),
+        None => Err(DealStatusError::Past),
+    }
 }
 
-// TODO: check this it might not be correct. write some tests. from copilot. suspect this is wrong.
 /// Some(n) where n is the next window start block, or None if the deal is complete.
 pub fn get_the_next_window(
     deal_info: &OnChainDealInfo,
     last_submission: BlockNum,
 ) -> Option<BlockNum> {
-    let window_number = (last_submission - deal_info.deal_start_block)
-        .0
-        .div_euclid(deal_info.proof_frequency_in_blocks.0);
-    let elapsed_length = deal_info.proof_frequency_in_blocks * (window_number + 1);
-    if elapsed_length >= deal_info.deal_length_in_blocks {
-        None
+    let deal_blocks = deal_info.blocks().ok()?;
+    let window_size = deal_info.proof_frequency_in_blocks;
+    let next_window = if last_submission < deal_blocks.start() {
+        0
     } else {
-        Some(deal_info.deal_start_block + elapsed_length)
-    }
+        let (window_number, _) = deal_blocks.window_of(last_submission, window_size).ok()??;
+        window_number + 1
+    };
+    deal_blocks
+        .window(next_window, window_size)
+        .ok()?
+        .map(|window| window.start())
 }
 
-// should be right, haven't tested extensively
-pub fn get_num_windows(
-    deal_length: BlockNum,
-    window_size: BlockNum,
-) -> Result<usize, anyhow::Error> {
+/// The number of windows in a deal, counting a partial window at the end of the deal, the same
+/// way `BlockRange::split_into_windows` does.
+/// # Errors
+/// * `BanyanError::Math` - If the window size is 0
+pub fn get_num_windows(deal_length: BlockNum, window_size: BlockNum) -> Result<usize> {
     if window_size.0 == 0 {
-        return Err(anyhow::anyhow!("Cannot divide by zero"));
+        return Err(BanyanError::Math(
+            "Cannot divide by zero: the window size is 0".to_string(),
+        ));
     }
-    Ok(math::round::ceil((deal_length.0 / window_size.0) as f64, 0) as usize)
+    Ok(deal_length.0.div_ceil(window_size.0) as usize)
 }
 
 // tests
@@ -58,13 +67,107 @@ mod tests {
 
     // in this test, we have a deal with length 21, proof window of size 5, and start block is 3
     use super::*;
+    use crate::types::{Blake3Hash, BlockRange, CidWrapper, DealStatus};
     #[test]
     fn get_num_windows_works() {
         let (deal_length1, window_size1) = (BlockNum(20), BlockNum(2));
         let (deal_length2, window_size2) = (BlockNum(20), BlockNum(3));
-        //let (deal_length3, window_size3) = (BlockNum(20), BlockNum(0));
+        let (deal_length3, window_size3) = (BlockNum(20), BlockNum(0));
         assert_eq!(get_num_windows(deal_length1, window_size1).unwrap(), 10);
-        assert_eq!(get_num_windows(deal_length2, window_size2).unwrap(), 6);
-        //couldn't figure out how to get the error test working
+        // The partial window at the end of the deal counts too
+        assert_eq!(get_num_windows(deal_length2, window_size2).unwrap(), 7);
+        assert_eq!(get_num_windows(BlockNum(20), BlockNum(6)).unwrap(), 4);
+        assert_eq!(get_num_windows(BlockNum(0), BlockNum(6)).unwrap(), 0);
+        assert!(matches!(
+            get_num_windows(deal_length3, window_size3),
+            Err(BanyanError::Math(_))
+        ));
+    }
+
+    #[test]
+    fn get_num_windows_matches_split_into_windows() {
+        for length in 0..30 {
+            for size in 1..8 {
+                let blocks =
+                    BlockRange::from_start_and_length(BlockNum(3), BlockNum(length)).unwrap();
+                assert_eq!(
+                    get_num_windows(BlockNum(length), BlockNum(size)).unwrap(),
+                    blocks.split_into_windows(BlockNum(size)).unwrap().len(),
+                    "{} blocks in windows of {}",
+                    length,
+                    size
+                );
+            }
+        }
+    }
+
+    fn deal(start: u64, length: u64, frequency: u64) -> OnChainDealInfo {
+        OnChainDealInfo {
+            deal_start_block: BlockNum(start),
+            deal_length_in_blocks: BlockNum(length),
+            proof_frequency_in_blocks: BlockNum(frequency),
+            price: Default::default(),
+            collateral: Default::default(),
+            erc20_token_denomination: Default::default(),
+            ipfs_file_cid: CidWrapper(Default::default()),
+            file_size: Default::default(),
+            blake3_checksum: Blake3Hash(blake3::hash(b"")),
+            creator_address: Default::default(),
+            executor_address: Default::default(),
+            deal_status: DealStatus::DealActive,
+        }
+    }
+
+    #[test]
+    fn current_and_next_windows() {
+        let deal_info = deal(3, 21, 5);
+        assert!(matches!(
+            get_the_current_window(&deal_info, BlockNum(2)),
+            Err(DealStatusError::Future)
+        ));
+        assert_eq!(
+            get_the_current_window(&deal_info, BlockNum(3)).unwrap(),
+            BlockNum(3)
+        );
+        assert_eq!(
+            get_the_current_window(&deal_info, BlockNum(12)).unwrap(),
+            BlockNum(8)
+        );
+        assert_eq!(
+            get_the_current_window(&deal_info, BlockNum(23)).unwrap(),
+            BlockNum(23)
+        );
+        assert!(matches!(
+            get_the_current_window(&deal_info, BlockNum(24)),
+            Err(DealStatusError::Past)
+        ));
+
+        assert_eq!(
+            get_the_next_window(&deal_info, BlockNum(0)),
+            Some(BlockNum(3))
+        );
+        assert_eq!(
+            get_the_next_window(&deal_info, BlockNum(4)),
+            Some(BlockNum(8))
+        );
+        assert_eq!(
+            get_the_next_window(&deal_info, BlockNum(22)),
+            Some(BlockNum(23))
+        );
+        assert_eq!(get_the_next_window(&deal_info, BlockNum(23)), None);
+        assert_eq!(get_the_next_window(&deal_info, BlockNum(100)), None);
+
+        // No proof frequency is an error, not a panic
+        let broken = deal(3, 21, 0);
+        assert!(matches!(
+            get_the_current_window(&broken, BlockNum(5)),
+            Err(DealStatusError::Invalid(BanyanError::Math(_)))
+        ));
+        assert_eq!(get_the_next_window(&broken, BlockNum(5)), None);
+        let overflowing = deal(u64::MAX, 1, 5);
+        assert!(matches!(
+            get_the_current_window(&overflowing, BlockNum(5)),
+            Err(DealStatusError::Invalid(_))
+        ));
     }
 }
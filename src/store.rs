@@ -0,0 +1,440 @@
+use crate::{
+    error::{BanyanError, Result},
+    keys::Key,
+    types::{BlockNum, DealID, DealStatus, OnChainDealInfo},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sled::{
+    transaction::{abort, ConflictableTransactionResult, TransactionError, Transactional},
+    IVec, Tree,
+};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+/*
+ * Local state an executor keeps about its deals.
+ * Status: A record per deal, as JSON in a sled tree keyed by big-endian deal ID, so deals are
+ * iterated in numeric order. A second tree indexes deals by status, keyed by the status' byte
+ * then the deal ID, and is only ever written in the same transaction as the deal it indexes.
+ * Recording a proof checks the window and updates the deal in one transaction, so two proofs
+ * for the same window can't both be recorded.
+ */
+
+/// The tree of deal records
+const DEALS_TREE: &str = "deals";
+
+/// The tree indexing deals by status
+const STATUS_TREE: &str = "deals_by_status";
+
+/// DealRecord - What an executor remembers about a deal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DealRecord {
+    /// The deal
+    pub deal_id: DealID,
+    /// The deal as it was last read from chain, including its status
+    pub deal_info: OnChainDealInfo,
+    /// Where the deal's file is stored
+    pub file_path: PathBuf,
+    /// Where the bao outboard tree of the file is stored
+    pub obao_path: PathBuf,
+    /// The first block of each window a proof has been posted for
+    #[serde(default)]
+    pub proven_windows: BTreeSet<BlockNum>,
+}
+
+impl DealRecord {
+    /// Create a record of a deal nothing has been proven for yet
+    pub fn new(
+        deal_id: DealID,
+        deal_info: OnChainDealInfo,
+        file_path: impl Into<PathBuf>,
+        obao_path: impl Into<PathBuf>,
+    ) -> Self {
+        DealRecord {
+            deal_id,
+            deal_info,
+            file_path: file_path.into(),
+            obao_path: obao_path.into(),
+            proven_windows: BTreeSet::new(),
+        }
+    }
+
+    /// The deal's status when it was last read from chain
+    pub fn status(&self) -> DealStatus {
+        self.deal_info.deal_status
+    }
+}
+
+/// DealStore - Deal records in a sled database
+#[derive(Clone)]
+pub struct DealStore {
+    /// The deal records, by deal ID
+    deals: Tree,
+    /// An empty value for each deal, by status then deal ID
+    by_status: Tree,
+}
+
+impl DealStore {
+    /// open - Open, or create, a store in a directory
+    /// # Errors
+    /// * `BanyanError::Store` - If the database can't be opened
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_db(&sled::open(path)?)
+    }
+
+    /// from_db - Keep deal records in an open sled database, alongside whatever else is in it
+    /// # Errors
+    /// * `BanyanError::Store` - If the trees can't be opened
+    pub fn from_db(db: &sled::Db) -> Result<Self> {
+        Ok(DealStore {
+            deals: db.open_tree(DEALS_TREE)?,
+            by_status: db.open_tree(STATUS_TREE)?,
+        })
+    }
+
+    /// insert - Remember a new deal
+    /// # Errors
+    /// * `BanyanError::Store` - If the deal is already stored, or it can't be written
+    pub fn insert(&self, record: &DealRecord) -> Result<()> {
+        self.transact(|deals, by_status| {
+            if deals.get(deal_key(record.deal_id))?.is_some() {
+                return abort(BanyanError::Store(format!(
+                    "Deal {} is already stored",
+                    record.deal_id
+                )));
+            }
+            deals.insert(deal_key(record.deal_id), encode(record)?)?;
+            by_status.insert(status_key(record.status(), record.deal_id), IVec::default())?;
+            Ok(())
+        })
+    }
+
+    /// get - Read a deal's record
+    /// # Errors
+    /// * `BanyanError::Store` - If it can't be read
+    pub fn get(&self, deal_id: DealID) -> Result<Option<DealRecord>> {
+        self.deals
+            .get(deal_key(deal_id))?
+            .map(|value| decode(&value))
+            .transpose()
+    }
+
+    /// update - Replace a deal's record, moving it to its new status if that's changed
+    /// # Errors
+    /// * `BanyanError::Store` - If the deal isn't stored, or it can't be written
+    pub fn update(&self, record: &DealRecord) -> Result<()> {
+        self.transact(|deals, by_status| {
+            let old = read(deals.get(deal_key(record.deal_id))?, record.deal_id)?;
+            by_status.remove(status_key(old.status(), record.deal_id))?;
+            deals.insert(deal_key(record.deal_id), encode(record)?)?;
+            by_status.insert(status_key(record.status(), record.deal_id), IVec::default())?;
+            Ok(())
+        })
+    }
+
+    /// set_status - Record a deal's new status
+    /// # Returns
+    /// * `DealRecord` - The updated record
+    /// # Errors
+    /// * `BanyanError::Store` - If the deal isn't stored, or it can't be written
+    pub fn set_status(&self, deal_id: DealID, status: DealStatus) -> Result<DealRecord> {
+        self.transact(|deals, by_status| {
+            let mut record = read(deals.get(deal_key(deal_id))?, deal_id)?;
+            by_status.remove(status_key(record.status(), deal_id))?;
+            record.deal_info.deal_status = status;
+            deals.insert(deal_key(deal_id), encode(&record)?)?;
+            by_status.insert(status_key(status, deal_id), IVec::default())?;
+            Ok(record)
+        })
+    }
+
+    /// remove - Forget a deal
+    /// # Returns
+    /// * `Option<DealRecord>` - The deal's record, if it was stored
+    /// # Errors
+    /// * `BanyanError::Store` - If it can't be removed
+    pub fn remove(&self, deal_id: DealID) -> Result<Option<DealRecord>> {
+        self.transact(|deals, by_status| {
+            let removed: DealRecord = match deals.remove(deal_key(deal_id))? {
+                Some(value) => decode(&value).or_else(abort)?,
+                None => return Ok(None),
+            };
+            by_status.remove(status_key(removed.status(), deal_id))?;
+            Ok(Some(removed))
+        })
+    }
+
+    /// all - Every deal's record, in order of deal ID
+    /// # Errors
+    /// * `BanyanError::Store` - If they can't be read
+    pub fn all(&self) -> Result<Vec<DealRecord>> {
+        self.deals.iter().map(|entry| decode(&entry?.1)).collect()
+    }
+
+    /// with_status - The deals in a status, in order of deal ID
+    /// # Errors
+    /// * `BanyanError::Store` - If the index can't be read
+    pub fn with_status(&self, status: DealStatus) -> Result<Vec<DealID>> {
+        self.by_status
+            .scan_prefix([status as u8])
+            .keys()
+            .map(|key| DealID::from_key(&key?[1..]))
+            .collect()
+    }
+
+    /// record_proof - Record that a proof was posted for a window of a deal
+    /// # Arguments
+    /// * `deal_id` - The deal the proof is for
+    /// * `window` - The first block of the window the proof is for
+    /// # Returns
+    /// * `bool` - Whether the proof was recorded, i.e. no proof was already recorded for the
+    ///   window
+    /// # Errors
+    /// * `BanyanError::Store` - If the deal isn't stored, or it can't be written
+    /// * `BanyanError::Proof` - If `window` isn't the first block of one of the deal's windows
+    pub fn record_proof(&self, deal_id: DealID, window: BlockNum) -> Result<bool> {
+        self.transact(|deals, _| {
+            let mut record = read(deals.get(deal_key(deal_id))?, deal_id)?;
+            let info = &record.deal_info;
+            let starts_window = info
+                .blocks()
+                .and_then(|blocks| blocks.window_of(window, info.proof_frequency_in_blocks))
+                .or_else(abort)?
+                .is_some_and(|(_, range)| range.start() == window);
+            if !starts_window {
+                return abort(BanyanError::Proof(format!(
+                    "Block {} doesn't start one of the windows of deal {}",
+                    window, deal_id
+                )));
+            }
+            if !record.proven_windows.insert(window) {
+                return Ok(false);
+            }
+            deals.insert(deal_key(deal_id), encode(&record)?)?;
+            Ok(true)
+        })
+    }
+
+    /// flush - Wait for everything written so far to reach the disk
+    /// # Errors
+    /// * `BanyanError::Store` - If it can't be flushed
+    pub fn flush(&self) -> Result<()> {
+        self.deals.flush()?;
+        self.by_status.flush()?;
+        Ok(())
+    }
+
+    /// Run a transaction over both trees, retrying it if it conflicts with another
+    fn transact<T>(
+        &self,
+        f: impl Fn(
+            &sled::transaction::TransactionalTree,
+            &sled::transaction::TransactionalTree,
+        ) -> ConflictableTransactionResult<T, BanyanError>,
+    ) -> Result<T> {
+        (&self.deals, &self.by_status)
+            .transaction(|(deals, by_status)| f(deals, by_status))
+            .map_err(|e| match e {
+                TransactionError::Abort(e) => e,
+                TransactionError::Storage(e) => e.into(),
+            })
+    }
+}
+
+/// The key of a deal's record
+fn deal_key(deal_id: DealID) -> IVec {
+    deal_id.to_key()
+}
+
+/// The key of a deal in the status index
+fn status_key(status: DealStatus, deal_id: DealID) -> IVec {
+    let mut key = vec![status as u8];
+    key.extend_from_slice(&deal_key(deal_id));
+    key.into()
+}
+
+/// Encode a value for the store
+fn encode<T: Serialize>(value: &T) -> ConflictableTransactionResult<Vec<u8>, BanyanError> {
+    serde_json::to_vec(value).or_else(|e| abort(e.into()))
+}
+
+/// Decode a value from the store
+fn decode<T: DeserializeOwned>(value: &[u8]) -> Result<T> {
+    serde_json::from_slice(value)
+        .map_err(|e| BanyanError::Store(format!("Invalid stored value: {}", e)))
+}
+
+/// Decode a deal's record read in a transaction, or abort if it isn't stored
+fn read(
+    value: Option<IVec>,
+    deal_id: DealID,
+) -> ConflictableTransactionResult<DealRecord, BanyanError> {
+    match value {
+        Some(value) => decode(&value).or_else(abort),
+        None => abort(BanyanError::Store(format!("Deal {} isn't stored", deal_id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Blake3Hash, CidWrapper};
+
+    fn record(deal_id: u64, status: DealStatus) -> DealRecord {
+        let deal_info = OnChainDealInfo {
+            deal_start_block: BlockNum(10),
+            deal_length_in_blocks: BlockNum(100),
+            proof_frequency_in_blocks: BlockNum(20),
+            price: Default::default(),
+            collateral: Default::default(),
+            erc20_token_denomination: Default::default(),
+            ipfs_file_cid: CidWrapper(Default::default()),
+            file_size: Default::default(),
+            blake3_checksum: Blake3Hash(blake3::hash(b"")),
+            creator_address: Default::default(),
+            executor_address: Default::default(),
+            deal_status: status,
+        };
+        DealRecord::new(
+            DealID(deal_id),
+            deal_info,
+            format!("files/{}", deal_id),
+            format!("files/{}.obao", deal_id),
+        )
+    }
+
+    fn temporary() -> DealStore {
+        DealStore::from_db(&sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn crud_in_numeric_order() {
+        let store = temporary();
+        // Little-endian keys would put 256 before 1, and 2 after both
+        for deal_id in [256, 2, 1, 65_536] {
+            store
+                .insert(&record(deal_id, DealStatus::DealCreated))
+                .unwrap();
+        }
+        let ids: Vec<_> = store.all().unwrap().iter().map(|r| r.deal_id.0).collect();
+        assert_eq!(ids, vec![1, 2, 256, 65_536]);
+        assert!(matches!(
+            store.insert(&record(2, DealStatus::DealActive)),
+            Err(BanyanError::Store(_))
+        ));
+
+        let mut two = store.get(DealID(2)).unwrap().unwrap();
+        assert_eq!(two, record(2, DealStatus::DealCreated));
+        two.file_path = "elsewhere".into();
+        two.deal_info.deal_status = DealStatus::DealActive;
+        store.update(&two).unwrap();
+        assert_eq!(store.get(DealID(2)).unwrap().unwrap(), two);
+        assert!(store.update(&record(3, DealStatus::DealActive)).is_err());
+
+        assert_eq!(
+            store.remove(DealID(256)).unwrap().unwrap().deal_id,
+            DealID(256)
+        );
+        assert_eq!(store.remove(DealID(256)).unwrap(), None);
+        assert_eq!(store.get(DealID(256)).unwrap(), None);
+        assert_eq!(
+            store.with_status(DealStatus::DealCreated).unwrap(),
+            vec![DealID(1), DealID(65_536)]
+        );
+    }
+
+    #[test]
+    fn status_index_follows_the_deals() {
+        let store = temporary();
+        for deal_id in [3, 1, 2] {
+            store
+                .insert(&record(deal_id, DealStatus::DealCreated))
+                .unwrap();
+        }
+        let active = store.set_status(DealID(2), DealStatus::DealActive).unwrap();
+        assert_eq!(active.status(), DealStatus::DealActive);
+        store.set_status(DealID(3), DealStatus::DealActive).unwrap();
+        assert_eq!(
+            store.with_status(DealStatus::DealActive).unwrap(),
+            vec![DealID(2), DealID(3)]
+        );
+        assert_eq!(
+            store.with_status(DealStatus::DealCreated).unwrap(),
+            vec![DealID(1)]
+        );
+        store.remove(DealID(3)).unwrap();
+        assert_eq!(
+            store.with_status(DealStatus::DealActive).unwrap(),
+            vec![DealID(2)]
+        );
+        assert!(store
+            .with_status(DealStatus::DealFinalized)
+            .unwrap()
+            .is_empty());
+        assert!(store.set_status(DealID(9), DealStatus::DealActive).is_err());
+    }
+
+    #[test]
+    fn proofs_are_recorded_once_per_window() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let store = DealStore::open(dir.path()).unwrap();
+            store.insert(&record(1, DealStatus::DealActive)).unwrap();
+            // Windows of 20 blocks from block 10
+            assert!(store.record_proof(DealID(1), BlockNum(30)).unwrap());
+            assert!(!store.record_proof(DealID(1), BlockNum(30)).unwrap());
+            assert!(matches!(
+                store.record_proof(DealID(1), BlockNum(31)),
+                Err(BanyanError::Proof(_))
+            ));
+            assert!(store.record_proof(DealID(1), BlockNum(200)).is_err());
+            assert!(matches!(
+                store.record_proof(DealID(2), BlockNum(30)),
+                Err(BanyanError::Store(_))
+            ));
+
+            // Many workers racing to prove the same windows only record each once
+            let recorded: usize = std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..4)
+                    .map(|_| {
+                        let store = store.clone();
+                        scope.spawn(move || {
+                            (0..5)
+                                .filter(|window| {
+                                    store
+                                        .record_proof(DealID(1), BlockNum(10 + window * 20))
+                                        .unwrap()
+                                })
+                                .count()
+                        })
+                    })
+                    .collect();
+                workers.into_iter().map(|w| w.join().unwrap()).sum()
+            });
+            assert_eq!(recorded, 4);
+            store.flush().unwrap();
+        }
+
+        // And it's all still there when the store is opened again
+        let store = DealStore::open(dir.path()).unwrap();
+        let proven: Vec<_> = store
+            .get(DealID(1))
+            .unwrap()
+            .unwrap()
+            .proven_windows
+            .into_iter()
+            .collect();
+        assert_eq!(
+            proven,
+            vec![
+                BlockNum(10),
+                BlockNum(30),
+                BlockNum(50),
+                BlockNum(70),
+                BlockNum(90)
+            ]
+        );
+    }
+}
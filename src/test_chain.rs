@@ -0,0 +1,1083 @@
+use crate::{
+    error::{BanyanError, Result},
+    eth::EthClient,
+    multicall::{self, MULTICALL3_ADDRESS},
+    types::{BlockNum, DealID},
+};
+use ethers::{
+    abi::Abi,
+    contract::BaseContract,
+    signers::{LocalWallet, Signer},
+    types::{
+        Address, Block, Bytes, Filter, FilterBlockOption, Log, Transaction, TransactionReceipt,
+        ValueOrArray, H256, U256, U64,
+    },
+    utils::{
+        keccak256,
+        rlp::{Decodable, Rlp},
+    },
+};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use revm::{
+    db::{CacheDB, EmptyDB},
+    primitives::{
+        AccountInfo, Address as EvmAddress, Bytecode, Bytes as EvmBytes, ExecutionResult, Output,
+        TxKind, B256, U256 as EvmU256,
+    },
+    Evm,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+/*
+ * An in-process chain for testing against the real Banyan contracts.
+ * Status: Just enough JSON-RPC to drive an EthClient end to end.
+ * Every transaction is mined into its own block, and block hashes can be overridden so
+ * that proof challenges are reproducible.
+ */
+
+/// The Chain ID the test chain reports
+pub const TEST_CHAIN_ID: u64 = 31337;
+
+/// The well known development keys we fund at genesis - Don't use these anywhere else!
+const TEST_PRIVATE_KEYS: [&str; 3] = [
+    // Admin and Deal Creator
+    "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+    // Deal Executor
+    "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d",
+    // Chainlink Oracle
+    "5de4111afa1a4b94908f83103eb1f1706367c2e68ca870fc3fb9a766a4e9a8d5",
+];
+
+/// How much ETH (in wei) each test account starts with - 10,000 ETH
+const TEST_ACCOUNT_BALANCE: u128 = 10_000_000_000_000_000_000_000;
+
+/// How much gas a block can hold
+const TEST_BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+/// The timestamp of the genesis block. Every block after it is 12 seconds later.
+const TEST_GENESIS_TIMESTAMP: u64 = 1_660_000_000;
+
+/// How often clients of the test chain poll it. It mines every transaction instantly.
+const TEST_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The Escrow storage slot holding the only address allowed to call `requestVerification`
+const VERIFICATION_REQUESTER_SLOT: u64 = 0xa7;
+
+/// The Escrow storage slot of the `_proofblocks` mapping, of the block each window's proof is in
+const PROOF_BLOCKS_SLOT: u64 = 0xa3;
+
+/// The Escrow contract's Hardhat artifact
+const ESCROW_ARTIFACT: &str = include_str!("../abi/Escrow.json");
+/// The Treasury contract's Hardhat artifact
+const TREASURY_ARTIFACT: &str = include_str!("../abi/Treasury.json");
+
+/// Runtime code for a permissive mock ERC20 (and ERC677) token.
+/// * `balanceOf` and `allowance` return `type(uint256).max`
+/// * `decimals` returns 18
+/// * Everything else (`transfer`, `transferFrom`, `approve`, `transferAndCall`, ...) returns `true`
+const MOCK_TOKEN_RUNTIME_CODE: [u8; 68] = [
+    0x60, 0x00, 0x35, 0x60, 0xe0, 0x1c, // selector = calldata[0..4]
+    0x80, 0x63, 0x70, 0xa0, 0x82, 0x31, 0x14, 0x60, 0x2d, 0x57, // balanceOf -> max
+    0x80, 0x63, 0xdd, 0x62, 0xed, 0x3e, 0x14, 0x60, 0x2d, 0x57, // allowance -> max
+    0x63, 0x31, 0x3c, 0xe5, 0x67, 0x14, 0x60, 0x39, 0x57, // decimals -> 18
+    0x60, 0x01, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3, // return true
+    0x5b, 0x60, 0x00, 0x19, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3, // return max
+    0x5b, 0x60, 0x12, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3, // return 18
+];
+
+/// A Hardhat artifact - we only care about the ABI and the creation bytecode
+#[derive(Deserialize)]
+struct Artifact {
+    abi: Abi,
+    bytecode: Bytes,
+}
+
+/// The parts of an `eth_call` / `eth_estimateGas` request we understand
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct CallRequest {
+    from: Option<Address>,
+    to: Option<Address>,
+    gas: Option<U256>,
+    gas_price: Option<U256>,
+    value: Option<U256>,
+    data: Option<Bytes>,
+    input: Option<Bytes>,
+}
+
+/// Everything that happened on the test chain
+struct ChainState {
+    /// The EVM's world state
+    db: CacheDB<EmptyDB>,
+    /// The latest mined block
+    block_number: u64,
+    /// Any block hashes that were overridden with `TestChain::set_block_hash`
+    block_hash_overrides: BTreeMap<u64, H256>,
+    /// Every transaction we've mined, by hash
+    transactions: HashMap<H256, Transaction>,
+    /// Every receipt we've generated, by transaction hash
+    receipts: HashMap<H256, TransactionReceipt>,
+    /// Every log emitted, in order
+    logs: Vec<Log>,
+    /// How many `aggregate3` calls Multicall3 has served
+    aggregate3_calls: u64,
+}
+
+/// The result of executing a transaction or call against the test chain
+struct Execution {
+    /// Whether the execution succeeded
+    success: bool,
+    /// How much gas was used
+    gas_used: u64,
+    /// Return (or revert) data
+    output: Bytes,
+    /// Logs emitted, with only the address, topics and data filled in
+    logs: Vec<Log>,
+    /// The address of a deployed contract, if this was a deployment
+    contract_address: Option<Address>,
+}
+
+impl ChainState {
+    fn new() -> Self {
+        let mut state = ChainState {
+            db: CacheDB::new(EmptyDB::default()),
+            block_number: 0,
+            block_hash_overrides: BTreeMap::new(),
+            transactions: HashMap::new(),
+            receipts: HashMap::new(),
+            logs: vec![],
+            aggregate3_calls: 0,
+        };
+        state.sync_block_hash(0);
+        state
+    }
+
+    /// Get the hash of a block. Unless overridden this is keccak256 of the block number.
+    fn block_hash(&self, number: u64) -> H256 {
+        match self.block_hash_overrides.get(&number) {
+            Some(hash) => *hash,
+            None => H256::from(keccak256(number.to_be_bytes())),
+        }
+    }
+
+    /// Make sure the BLOCKHASH opcode sees the same hash as the RPC
+    fn sync_block_hash(&mut self, number: u64) {
+        let hash = self.block_hash(number);
+        self.db
+            .block_hashes
+            .insert(EvmU256::from(number), B256::from(hash.0));
+    }
+
+    /// Give an account a balance
+    fn fund(&mut self, address: Address, balance: U256) {
+        let mut info = self
+            .db
+            .accounts
+            .get(&to_evm_address(address))
+            .and_then(|account| account.info())
+            .unwrap_or_default();
+        info.balance = to_evm_u256(balance);
+        self.db.insert_account_info(to_evm_address(address), info);
+    }
+
+    /// Put runtime code directly at an address
+    fn set_code(&mut self, address: Address, code: &[u8]) {
+        let info = AccountInfo::new(
+            EvmU256::ZERO,
+            1,
+            B256::from(keccak256(code)),
+            Bytecode::new_raw(EvmBytes::copy_from_slice(code)),
+        );
+        self.db.insert_account_info(to_evm_address(address), info);
+    }
+
+    /// Get the nonce of an account
+    fn nonce(&self, address: Address) -> u64 {
+        self.db
+            .accounts
+            .get(&to_evm_address(address))
+            .and_then(|account| account.info())
+            .map(|info| info.nonce)
+            .unwrap_or(0)
+    }
+
+    /// Execute a transaction (or call) in the EVM
+    /// # Arguments
+    /// * `commit` - Whether to persist the state changes.
+    ///
+    /// Committed executions run in the context of the next block, calls in the latest one.
+    #[allow(clippy::too_many_arguments)]
+    fn execute(
+        &mut self,
+        caller: Address,
+        to: Option<Address>,
+        data: Bytes,
+        value: U256,
+        gas_limit: u64,
+        gas_price: U256,
+        nonce: Option<u64>,
+        commit: bool,
+    ) -> Result<Execution> {
+        let number = if commit {
+            self.block_number + 1
+        } else {
+            self.block_number
+        };
+        let mut evm = Evm::builder()
+            .with_db(&mut self.db)
+            .modify_cfg_env(|cfg| cfg.chain_id = TEST_CHAIN_ID)
+            .modify_block_env(|block| {
+                block.number = EvmU256::from(number);
+                block.timestamp = EvmU256::from(TEST_GENESIS_TIMESTAMP + number * 12);
+                block.gas_limit = EvmU256::from(TEST_BLOCK_GAS_LIMIT);
+                block.basefee = EvmU256::ZERO;
+            })
+            .modify_tx_env(|tx| {
+                tx.caller = to_evm_address(caller);
+                tx.transact_to = match to {
+                    Some(to) => TxKind::Call(to_evm_address(to)),
+                    None => TxKind::Create,
+                };
+                tx.data = EvmBytes::from(data.to_vec());
+                tx.value = to_evm_u256(value);
+                tx.gas_limit = gas_limit;
+                tx.gas_price = to_evm_u256(gas_price);
+                tx.nonce = nonce;
+                tx.chain_id = Some(TEST_CHAIN_ID);
+            })
+            .build();
+        let result = if commit {
+            evm.transact_commit()
+        } else {
+            evm.transact()
+                .map(|result_and_state| result_and_state.result)
+        }
+        .map_err(|e| BanyanError::Chain(format!("EVM error: {:?}", e)))?;
+        Ok(match result {
+            ExecutionResult::Success {
+                gas_used,
+                logs,
+                output,
+                ..
+            } => {
+                let (output, contract_address) = match output {
+                    Output::Call(bytes) => (bytes, None),
+                    Output::Create(bytes, address) => (bytes, address.map(from_evm_address)),
+                };
+                Execution {
+                    success: true,
+                    gas_used,
+                    output: Bytes::from(output.to_vec()),
+                    logs: logs
+                        .into_iter()
+                        .map(|log| Log {
+                            address: from_evm_address(log.address),
+                            topics: log.topics().iter().map(|t| H256::from(t.0)).collect(),
+                            data: Bytes::from(log.data.data.to_vec()),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    contract_address,
+                }
+            }
+            ExecutionResult::Revert { gas_used, output } => Execution {
+                success: false,
+                gas_used,
+                output: Bytes::from(output.to_vec()),
+                logs: vec![],
+                contract_address: None,
+            },
+            ExecutionResult::Halt { gas_used, .. } => Execution {
+                success: false,
+                gas_used,
+                output: Bytes::default(),
+                logs: vec![],
+                contract_address: None,
+            },
+        })
+    }
+
+    /// Serve Multicall3's `aggregate3` natively, rather than deploying its bytecode.
+    /// Each call is made from the Multicall3 address, against the latest block.
+    fn aggregate3(&mut self, data: Bytes) -> Result<Bytes, RpcError> {
+        let calls = multicall::decode_aggregate3(&data)
+            .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+        self.aggregate3_calls += 1;
+        let mut results = Vec::with_capacity(calls.len());
+        for (target, allow_failure, data) in calls {
+            let execution = self
+                .execute(
+                    MULTICALL3_ADDRESS,
+                    Some(target),
+                    data,
+                    U256::zero(),
+                    TEST_BLOCK_GAS_LIMIT,
+                    U256::zero(),
+                    None,
+                    false,
+                )
+                .map_err(RpcError::from)?;
+            if !execution.success && !allow_failure {
+                return Err(RpcError::revert(execution.output));
+            }
+            results.push((execution.success, execution.output));
+        }
+        Ok(multicall::encode_aggregate3_results(&results))
+    }
+
+    /// Mine a new block holding a single transaction and record its receipt and logs.
+    /// Returns the receipt of the mined transaction.
+    fn mine_transaction(
+        &mut self,
+        mut tx: Transaction,
+        execution: Execution,
+    ) -> TransactionReceipt {
+        self.block_number += 1;
+        self.sync_block_hash(self.block_number);
+        let block_hash = self.block_hash(self.block_number);
+        let block_number = U64::from(self.block_number);
+
+        tx.block_hash = Some(block_hash);
+        tx.block_number = Some(block_number);
+        tx.transaction_index = Some(U64::zero());
+
+        let logs: Vec<Log> = execution
+            .logs
+            .into_iter()
+            .enumerate()
+            .map(|(i, log)| Log {
+                block_hash: Some(block_hash),
+                block_number: Some(block_number),
+                transaction_hash: Some(tx.hash),
+                transaction_index: Some(U64::zero()),
+                log_index: Some(U256::from(i)),
+                transaction_log_index: Some(U256::from(i)),
+                removed: Some(false),
+                ..log
+            })
+            .collect();
+        let receipt = TransactionReceipt {
+            transaction_hash: tx.hash,
+            transaction_index: U64::zero(),
+            block_hash: Some(block_hash),
+            block_number: Some(block_number),
+            from: tx.from,
+            to: tx.to,
+            cumulative_gas_used: U256::from(execution.gas_used),
+            gas_used: Some(U256::from(execution.gas_used)),
+            contract_address: execution.contract_address,
+            logs: logs.clone(),
+            status: Some(U64::from(execution.success as u64)),
+            effective_gas_price: tx.gas_price,
+            ..Default::default()
+        };
+        self.logs.extend(logs);
+        self.receipts.insert(tx.hash, receipt.clone());
+        self.transactions.insert(tx.hash, tx);
+        receipt
+    }
+
+    /// Execute and mine a transaction that didn't come in over RPC (deployments and setup)
+    fn send(&mut self, from: Address, to: Option<Address>, data: Bytes) -> Result<Execution> {
+        let nonce = self.nonce(from);
+        let execution = self.execute(
+            from,
+            to,
+            data.clone(),
+            U256::zero(),
+            TEST_BLOCK_GAS_LIMIT,
+            U256::zero(),
+            Some(nonce),
+            true,
+        )?;
+        if !execution.success {
+            return Err(BanyanError::Chain(format!(
+                "Test chain transaction from {:?} to {:?} reverted: {}",
+                from, to, execution.output
+            )));
+        }
+        let tx = Transaction {
+            hash: H256::from(keccak256(
+                [from.as_bytes(), &nonce.to_be_bytes()[..]].concat(),
+            )),
+            nonce: U256::from(nonce),
+            from,
+            to,
+            input: data,
+            gas: U256::from(TEST_BLOCK_GAS_LIMIT),
+            gas_price: Some(U256::zero()),
+            chain_id: Some(U256::from(TEST_CHAIN_ID)),
+            ..Default::default()
+        };
+        let receipt = self.mine_transaction(tx, execution);
+        Ok(Execution {
+            success: true,
+            gas_used: receipt.gas_used.unwrap_or_default().as_u64(),
+            output: Bytes::default(),
+            logs: receipt.logs,
+            contract_address: receipt.contract_address,
+        })
+    }
+
+    /// Deploy a contract from its creation bytecode (with constructor args already appended)
+    fn deploy(&mut self, from: Address, bytecode: Bytes) -> Result<Address> {
+        self.send(from, None, bytecode)?
+            .contract_address
+            .ok_or_else(|| BanyanError::Chain("Deployment did not return an address".to_string()))
+    }
+
+    /// Build a JSON-RPC block for a block number
+    fn block(&self, number: u64) -> Option<Block<H256>> {
+        if number > self.block_number {
+            return None;
+        }
+        let transactions = self
+            .transactions
+            .values()
+            .filter(|tx| tx.block_number == Some(U64::from(number)))
+            .map(|tx| tx.hash)
+            .collect();
+        Some(Block {
+            hash: Some(self.block_hash(number)),
+            parent_hash: if number == 0 {
+                H256::zero()
+            } else {
+                self.block_hash(number - 1)
+            },
+            number: Some(U64::from(number)),
+            timestamp: U256::from(TEST_GENESIS_TIMESTAMP + number * 12),
+            gas_limit: U256::from(TEST_BLOCK_GAS_LIMIT),
+            base_fee_per_gas: Some(U256::zero()),
+            transactions,
+            ..Default::default()
+        })
+    }
+
+    /// Resolve a JSON-RPC block tag to a block number
+    fn resolve_block_tag(&self, tag: Option<&Value>) -> Result<u64> {
+        match tag.and_then(Value::as_str) {
+            None | Some("latest") | Some("pending") | Some("safe") | Some("finalized") => {
+                Ok(self.block_number)
+            }
+            Some("earliest") => Ok(0),
+            Some(number) => Ok(serde_json::from_value::<U64>(json!(number))?.as_u64()),
+        }
+    }
+
+    /// Get all the logs that match a filter
+    fn filter_logs(&self, filter: &Filter) -> Vec<Log> {
+        let (from, to) = match &filter.block_option {
+            FilterBlockOption::AtBlockHash(hash) => {
+                return self
+                    .logs
+                    .iter()
+                    .filter(|log| log.block_hash == Some(*hash) && log_matches(filter, log))
+                    .cloned()
+                    .collect()
+            }
+            FilterBlockOption::Range { .. } => (
+                filter.get_from_block().map(|n| n.as_u64()).unwrap_or(0),
+                filter
+                    .get_to_block()
+                    .map(|n| n.as_u64())
+                    .unwrap_or(self.block_number),
+            ),
+        };
+        self.logs
+            .iter()
+            .filter(|log| {
+                let number = log.block_number.unwrap_or_default().as_u64();
+                number >= from && number <= to && log_matches(filter, log)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Handle a single JSON-RPC method call
+    fn handle(&mut self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "eth_chainId" => Ok(json!(U64::from(TEST_CHAIN_ID))),
+            "net_version" => Ok(json!(TEST_CHAIN_ID.to_string())),
+            "eth_blockNumber" => Ok(json!(U64::from(self.block_number))),
+            "eth_gasPrice" => Ok(json!(U256::zero())),
+            "eth_getBalance" => {
+                let address: Address = param(params, 0)?;
+                let balance = self
+                    .db
+                    .accounts
+                    .get(&to_evm_address(address))
+                    .and_then(|account| account.info())
+                    .map(|info| from_evm_u256(info.balance))
+                    .unwrap_or_default();
+                Ok(json!(balance))
+            }
+            "eth_getTransactionCount" => {
+                let address: Address = param(params, 0)?;
+                Ok(json!(U256::from(self.nonce(address))))
+            }
+            "eth_getCode" => {
+                let address: Address = param(params, 0)?;
+                // Multicall3 is served natively, but clients only use it if it has code. It can't
+                // really have any, as its calls would then be rejected for coming from a contract.
+                if address == MULTICALL3_ADDRESS {
+                    return Ok(json!(Bytes::from(vec![0xfe])));
+                }
+                let code = self
+                    .db
+                    .accounts
+                    .get(&to_evm_address(address))
+                    .and_then(|account| account.info())
+                    .and_then(|info| info.code)
+                    .map(|code| Bytes::from(code.original_bytes().to_vec()))
+                    .unwrap_or_default();
+                Ok(json!(code))
+            }
+            "eth_getBlockByNumber" => {
+                let number = self
+                    .resolve_block_tag(params.first())
+                    .map_err(RpcError::from)?;
+                Ok(json!(self.block(number)))
+            }
+            "eth_getBlockByHash" => {
+                let hash: H256 = param(params, 0)?;
+                let number = (0..=self.block_number).find(|n| self.block_hash(*n) == hash);
+                Ok(json!(number.and_then(|n| self.block(n))))
+            }
+            "eth_call" if is_multicall(params) => {
+                let request: CallRequest = param(params, 0)?;
+                self.aggregate3(request.input.or(request.data).unwrap_or_default())
+                    .map(|output| json!(output))
+            }
+            "eth_call" | "eth_estimateGas" => {
+                let request: CallRequest = param(params, 0)?;
+                let execution = self
+                    .execute(
+                        request.from.unwrap_or_default(),
+                        request.to,
+                        request.input.or(request.data).unwrap_or_default(),
+                        request.value.unwrap_or_default(),
+                        request
+                            .gas
+                            .map(|gas| gas.as_u64())
+                            .unwrap_or(TEST_BLOCK_GAS_LIMIT),
+                        request.gas_price.unwrap_or_default(),
+                        None,
+                        false,
+                    )
+                    .map_err(RpcError::from)?;
+                if !execution.success {
+                    return Err(RpcError::revert(execution.output));
+                }
+                if method == "eth_call" {
+                    Ok(json!(execution.output))
+                } else {
+                    Ok(json!(U256::from(execution.gas_used)))
+                }
+            }
+            "eth_sendRawTransaction" => {
+                let raw: Bytes = param(params, 0)?;
+                let mut tx = Transaction::decode(&Rlp::new(raw.as_ref()))
+                    .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+                tx.hash = H256::from(keccak256(raw.as_ref()));
+                tx.recover_from_mut()
+                    .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+                let execution = self
+                    .execute(
+                        tx.from,
+                        tx.to,
+                        tx.input.clone(),
+                        tx.value,
+                        tx.gas.as_u64(),
+                        tx.gas_price.or(tx.max_fee_per_gas).unwrap_or_default(),
+                        Some(tx.nonce.as_u64()),
+                        true,
+                    )
+                    .map_err(RpcError::from)?;
+                let hash = tx.hash;
+                self.mine_transaction(tx, execution);
+                Ok(json!(hash))
+            }
+            "eth_getTransactionByHash" => {
+                let hash: H256 = param(params, 0)?;
+                Ok(json!(self.transactions.get(&hash)))
+            }
+            "eth_getTransactionReceipt" => {
+                let hash: H256 = param(params, 0)?;
+                Ok(json!(self.receipts.get(&hash)))
+            }
+            "eth_getLogs" => {
+                let filter: Filter = param(params, 0)?;
+                Ok(json!(self.filter_logs(&filter)))
+            }
+            other => Err(RpcError::method_not_found(other)),
+        }
+    }
+}
+
+/// Check whether a log matches a filter's address and topics
+fn log_matches(filter: &Filter, log: &Log) -> bool {
+    let address_matches = match &filter.address {
+        None => true,
+        Some(ValueOrArray::Value(address)) => *address == log.address,
+        Some(ValueOrArray::Array(addresses)) => addresses.contains(&log.address),
+    };
+    let topics_match = filter.topics.iter().enumerate().all(|(i, topic)| {
+        let wanted: Vec<H256> = match topic {
+            None => return true,
+            Some(ValueOrArray::Value(None)) => return true,
+            Some(ValueOrArray::Value(Some(topic))) => vec![*topic],
+            Some(ValueOrArray::Array(topics)) => topics.iter().flatten().copied().collect(),
+        };
+        wanted.is_empty()
+            || log
+                .topics
+                .get(i)
+                .map(|topic| wanted.contains(topic))
+                .unwrap_or(false)
+    });
+    address_matches && topics_match
+}
+
+/// Parse a positional JSON-RPC parameter
+fn param<T: serde::de::DeserializeOwned>(params: &[Value], index: usize) -> Result<T, RpcError> {
+    let value = params
+        .get(index)
+        .cloned()
+        .ok_or_else(|| RpcError::invalid_params(format!("Missing parameter {}", index)))?;
+    serde_json::from_value(value).map_err(|e| RpcError::invalid_params(e.to_string()))
+}
+
+/// Whether an `eth_call` is to Multicall3
+fn is_multicall(params: &[Value]) -> bool {
+    matches!(param::<CallRequest>(params, 0), Ok(request) if request.to == Some(MULTICALL3_ADDRESS))
+}
+
+/// A JSON-RPC error object
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Bytes>,
+}
+
+impl RpcError {
+    fn invalid_params(message: String) -> Self {
+        RpcError {
+            code: -32602,
+            message,
+            data: None,
+        }
+    }
+    fn method_not_found(method: &str) -> Self {
+        RpcError {
+            code: -32601,
+            message: format!("Method {} is not supported by the test chain", method),
+            data: None,
+        }
+    }
+    /// Reverts are reported the same way geth does - code 3 with the revert data attached
+    fn revert(data: Bytes) -> Self {
+        RpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: Some(data),
+        }
+    }
+    fn to_json(&self) -> Value {
+        match &self.data {
+            Some(data) => json!({"code": self.code, "message": self.message, "data": data}),
+            None => json!({"code": self.code, "message": self.message}),
+        }
+    }
+}
+
+impl From<BanyanError> for RpcError {
+    fn from(e: BanyanError) -> Self {
+        RpcError {
+            code: -32000,
+            message: e.to_string(),
+            data: None,
+        }
+    }
+}
+
+/// Handle a JSON-RPC request body (single or batched)
+fn handle_body(state: &Mutex<ChainState>, body: &[u8]) -> Value {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => {
+            return json!({"jsonrpc": "2.0", "id": null, "error": {"code": -32700, "message": e.to_string()}})
+        }
+    };
+    let handle_one = |request: &Value| {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = match request.get("params") {
+            Some(Value::Array(params)) => params.clone(),
+            _ => vec![],
+        };
+        let result = state.lock().unwrap().handle(method, &params);
+        match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => json!({"jsonrpc": "2.0", "id": id, "error": e.to_json()}),
+        }
+    };
+    match &request {
+        Value::Array(requests) => Value::Array(requests.iter().map(handle_one).collect()),
+        request => handle_one(request),
+    }
+}
+
+/// TestChain - An in-process EVM running the real Escrow and Treasury bytecode, served over
+/// JSON-RPC on localhost so an EthClient can point at it.
+pub struct TestChain {
+    /// The URL of the JSON-RPC server
+    url: String,
+    /// The chain itself
+    state: Arc<Mutex<ChainState>>,
+    /// The wallets of the funded test accounts
+    wallets: Vec<LocalWallet>,
+    /// The Escrow contract
+    escrow_address: Address,
+    /// The Treasury contract
+    treasury_address: Address,
+    /// The mock ERC20 used for payment
+    token_address: Address,
+    /// The mock LINK token
+    link_address: Address,
+    /// Stops the JSON-RPC server when dropped
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl TestChain {
+    /// Start a new test chain: fund the test accounts, deploy the mock tokens, Treasury and
+    /// Escrow, and start serving JSON-RPC on a random local port.
+    /// ```no_run
+    /// use banyan_shared::test_chain::TestChain;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let chain = TestChain::spawn().await.unwrap();
+    ///     let client = chain.creator_client().await.unwrap();
+    /// }
+    /// ```
+    pub async fn spawn() -> Result<TestChain> {
+        let wallets = TEST_PRIVATE_KEYS
+            .iter()
+            .map(|key| key.parse::<LocalWallet>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| BanyanError::Parse(format!("Invalid test private key: {}", e)))?
+            .into_iter()
+            .map(|wallet| wallet.with_chain_id(TEST_CHAIN_ID))
+            .collect::<Vec<_>>();
+        let admin = wallets[0].address();
+        let oracle = wallets[2].address();
+
+        let mut state = ChainState::new();
+        for wallet in &wallets {
+            state.fund(wallet.address(), U256::from(TEST_ACCOUNT_BALANCE));
+        }
+
+        // Mock tokens live at fixed addresses, there's no need to deploy them
+        let token_address = Address::from_low_u64_be(0xe20c);
+        let link_address = Address::from_low_u64_be(0x011c);
+        state.set_code(token_address, &MOCK_TOKEN_RUNTIME_CODE);
+        state.set_code(link_address, &MOCK_TOKEN_RUNTIME_CODE);
+
+        // Deploy and wire up the real contracts
+        let escrow: Artifact = serde_json::from_str(ESCROW_ARTIFACT)?;
+        let treasury: Artifact = serde_json::from_str(TREASURY_ARTIFACT)?;
+        let escrow_address = state.deploy(admin, escrow.bytecode)?;
+        let treasury_address = state.deploy(admin, treasury.bytecode)?;
+        let treasury_contract = BaseContract::from(treasury.abi);
+        state.send(
+            admin,
+            Some(treasury_address),
+            treasury_contract.encode("_initialize", (admin, escrow_address))?,
+        )?;
+        let escrow_contract = BaseContract::from(escrow.abi);
+        state.send(
+            admin,
+            Some(escrow_address),
+            escrow_contract.encode(
+                "_initialize",
+                (
+                    link_address,
+                    admin,
+                    treasury_address,
+                    treasury_address,
+                    oracle,
+                ),
+            )?,
+        )?;
+        // The checked-in Escrow build never assigns the address `requestVerification` is restricted
+        // to ("Only Oracle"), so nobody could request verification. Give that role to the oracle.
+        state
+            .db
+            .insert_account_storage(
+                to_evm_address(escrow_address),
+                EvmU256::from(VERIFICATION_REQUESTER_SLOT),
+                EvmU256::from_be_slice(oracle.as_bytes()),
+            )
+            .map_err(|e| BanyanError::Chain(format!("Failed to set storage: {:?}", e)))?;
+
+        // Serve JSON-RPC
+        let state = Arc::new(Mutex::new(state));
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let state = state.clone();
+                    async move {
+                        let body = hyper::body::to_bytes(request.into_body()).await?;
+                        let response = handle_body(&state, &body);
+                        Ok::<_, hyper::Error>(
+                            Response::builder()
+                                .header("Content-Type", "application/json")
+                                .body(Body::from(response.to_string()))
+                                .expect("Failed to build response"),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .map_err(|e| BanyanError::Chain(format!("Failed to start the test chain: {}", e)))?
+            .serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            shutdown_rx.await.ok();
+        }));
+
+        Ok(TestChain {
+            url,
+            state,
+            wallets,
+            escrow_address,
+            treasury_address,
+            token_address,
+            link_address,
+            _shutdown: shutdown,
+        })
+    }
+
+    /* Accessors */
+
+    /// The URL of the test chain's JSON-RPC server
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// The address of the deployed Escrow contract
+    pub fn escrow_address(&self) -> Address {
+        self.escrow_address
+    }
+
+    /// The address of the deployed Treasury contract
+    pub fn treasury_address(&self) -> Address {
+        self.treasury_address
+    }
+
+    /// The address of the mock ERC20 token to denominate deals in
+    pub fn token_address(&self) -> Address {
+        self.token_address
+    }
+
+    /// The address of the mock LINK token
+    pub fn link_address(&self) -> Address {
+        self.link_address
+    }
+
+    /// The wallet of the account that deployed the contracts and creates deals
+    pub fn creator(&self) -> &LocalWallet {
+        &self.wallets[0]
+    }
+
+    /// The wallet of the account that executes deals
+    pub fn executor(&self) -> &LocalWallet {
+        &self.wallets[1]
+    }
+
+    /// The wallet of the account registered as the Chainlink oracle
+    pub fn oracle(&self) -> &LocalWallet {
+        &self.wallets[2]
+    }
+
+    /* Clients */
+
+    /// Connect an EthClient to the test chain that signs with the given wallet
+    pub async fn client_for(&self, wallet: &LocalWallet) -> Result<EthClient> {
+        Ok(EthClient::connect(
+            self.url(),
+            String::new(),
+            Some(TEST_CHAIN_ID),
+            Some(hex_private_key(wallet)),
+            self.escrow_address,
+        )
+        .await?
+        .with_poll_interval(TEST_POLL_INTERVAL))
+    }
+
+    /// Connect an EthClient to the test chain that signs as the deal creator
+    pub async fn creator_client(&self) -> Result<EthClient> {
+        self.client_for(self.creator()).await
+    }
+
+    /// Connect an EthClient to the test chain that signs as the deal executor
+    pub async fn executor_client(&self) -> Result<EthClient> {
+        self.client_for(self.executor()).await
+    }
+
+    /// Connect an EthClient to the test chain that signs as the Chainlink oracle
+    pub async fn oracle_client(&self) -> Result<EthClient> {
+        self.client_for(self.oracle()).await
+    }
+
+    /* Chain Control */
+
+    /// Get the latest block number
+    pub fn block_number(&self) -> BlockNum {
+        BlockNum(self.state.lock().unwrap().block_number)
+    }
+
+    /// How many `aggregate3` calls Multicall3 has served, to tell batched reads from unbatched ones
+    pub fn aggregate3_calls(&self) -> u64 {
+        self.state.lock().unwrap().aggregate3_calls
+    }
+
+    /// Mine `count` empty blocks
+    pub fn mine_blocks(&self, count: u64) -> BlockNum {
+        let mut state = self.state.lock().unwrap();
+        for _ in 0..count {
+            state.block_number += 1;
+            let number = state.block_number;
+            state.sync_block_hash(number);
+        }
+        BlockNum(state.block_number)
+    }
+
+    /// Mine empty blocks until the latest block is `block_num`. Does nothing if we're already past it.
+    pub fn mine_to(&self, block_num: BlockNum) -> BlockNum {
+        let latest = self.block_number();
+        if block_num > latest {
+            self.mine_blocks(block_num.0 - latest.0)
+        } else {
+            latest
+        }
+    }
+
+    /// Get the hash of a block
+    pub fn block_hash(&self, block_num: BlockNum) -> H256 {
+        self.state.lock().unwrap().block_hash(block_num.0)
+    }
+
+    /// Override the hash of a block. Set this before the block is mined so that the
+    /// transactions, receipts and logs in it agree with the new hash.
+    pub fn set_block_hash(&self, block_num: BlockNum, hash: H256) {
+        let mut state = self.state.lock().unwrap();
+        state.block_hash_overrides.insert(block_num.0, hash);
+        state.sync_block_hash(block_num.0);
+    }
+
+    /// Overwrite a storage slot of a contract, like `anvil_setStorageAt`
+    pub fn set_storage_at(&self, address: Address, slot: H256, value: H256) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .db
+            .insert_account_storage(
+                to_evm_address(address),
+                EvmU256::from_be_bytes(slot.0),
+                EvmU256::from_be_bytes(value.0),
+            )
+            .map_err(|e| BanyanError::Chain(format!("Failed to set storage: {:?}", e)))
+    }
+
+    /// Mark a deal's proof window as started, so `saveProof` accepts a proof for it.
+    /// The checked-in Escrow build inverts its "Proof already submitted" check and only takes a
+    /// proof for a window that already has one, so the real `saveProof` path can't be driven
+    /// without this. It sets `_proofblocks[deal_id][window]` to 1.
+    pub fn start_proof_window(&self, deal_id: DealID, window: u64) -> Result<()> {
+        let deal_slot = keccak256(
+            [
+                H256::from_low_u64_be(deal_id.0).as_bytes(),
+                H256::from_low_u64_be(PROOF_BLOCKS_SLOT).as_bytes(),
+            ]
+            .concat(),
+        );
+        let window_slot =
+            keccak256([H256::from_low_u64_be(window).as_bytes(), &deal_slot].concat());
+        self.set_storage_at(
+            self.escrow_address,
+            H256::from(window_slot),
+            H256::from_low_u64_be(1),
+        )
+    }
+
+    /// Call a contract on the test chain directly, without going through JSON-RPC.
+    /// Returns the raw return data, or an error if the call reverted.
+    pub fn call(&self, from: Address, to: Address, data: Bytes) -> Result<Bytes> {
+        let execution = self.state.lock().unwrap().execute(
+            from,
+            Some(to),
+            data,
+            U256::zero(),
+            TEST_BLOCK_GAS_LIMIT,
+            U256::zero(),
+            None,
+            false,
+        )?;
+        if execution.success {
+            Ok(execution.output)
+        } else {
+            Err(BanyanError::Chain(format!(
+                "Call reverted: {}",
+                execution.output
+            )))
+        }
+    }
+
+    /// Send a transaction on the test chain directly, without going through JSON-RPC.
+    /// The transaction is mined into its own block.
+    pub fn transact(&self, from: Address, to: Address, data: Bytes) -> Result<Vec<Log>> {
+        Ok(self.state.lock().unwrap().send(from, Some(to), data)?.logs)
+    }
+}
+
+/// Hex encode a wallet's private key so it can be handed to EthClient::connect
+fn hex_private_key(wallet: &LocalWallet) -> String {
+    ethers::utils::hex::encode(wallet.signer().to_bytes())
+}
+
+/* Conversions between ethers and revm types */
+
+fn to_evm_address(address: Address) -> EvmAddress {
+    EvmAddress::from(address.0)
+}
+
+fn from_evm_address(address: EvmAddress) -> Address {
+    Address::from(address.into_array())
+}
+
+fn to_evm_u256(value: U256) -> EvmU256 {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    EvmU256::from_be_bytes(bytes)
+}
+
+fn from_evm_u256(value: EvmU256) -> U256 {
+    U256::from_big_endian(&value.to_be_bytes::<32>())
+}
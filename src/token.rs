@@ -0,0 +1,507 @@
+use crate::error::{BanyanError, Result};
+use ethers::types::{U256, U512};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+};
+
+/*
+ * Exact token amounts, for prices and collateral.
+ * Status: Amounts are kept as an integer number of the token's smallest unit, alongside its
+ * decimals, and all maths happens in U256 (through U512 for products) with an explicit rounding
+ * mode. Nothing ever goes through a float.
+ */
+
+/// The decimals most ERC20 tokens (and Ether) use, and what we assume when no token is named
+pub const DEFAULT_DECIMALS: u8 = 18;
+
+/// The most decimals a token can have and still have a whole token fit in a U256
+pub const MAX_DECIMALS: u8 = 77;
+
+/// The tokens we know the decimals of, so amounts like "12.5 USDC" can be parsed by name
+const KNOWN_TOKENS: &[(&str, u8)] = &[
+    ("ETH", 18),
+    ("WETH", 18),
+    ("DAI", 18),
+    ("LINK", 18),
+    ("FIL", 18),
+    ("USDC", 6),
+    ("USDT", 6),
+    ("WBTC", 8),
+];
+
+/// known_decimals - The decimals of a token we know by its symbol
+/// # Arguments
+/// * `symbol` - The token's symbol, in any case
+/// # Returns
+/// * `Option<u8>` - Its decimals, or None if we don't know the token
+pub fn known_decimals(symbol: &str) -> Option<u8> {
+    KNOWN_TOKENS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(symbol))
+        .map(|(_, decimals)| *decimals)
+}
+
+/// Rounding - What to do with the part of a result smaller than the token's smallest unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Rounding {
+    /// Round towards zero
+    Down,
+    /// Round away from zero
+    Up,
+    /// Round to the nearest unit, halves away from zero
+    #[default]
+    Nearest,
+}
+
+/// TokenAmount - An exact amount of an ERC20 token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenAmount {
+    /// The amount in the token's smallest unit (e.g. wei)
+    #[serde(with = "decimal_u256")]
+    amount: U256,
+    /// How many decimals the token has
+    decimals: u8,
+    /// The token's symbol, if we know it
+    symbol: Option<String>,
+}
+
+impl TokenAmount {
+    /// Create a TokenAmount from an amount in the token's smallest unit
+    /// # Arguments
+    /// * `amount` - The amount in the token's smallest unit (e.g. wei)
+    /// * `decimals` - How many decimals the token has
+    /// # Returns
+    /// * `TokenAmount` - The amount
+    /// # Errors
+    /// * `BanyanError::Math` - If the token has more than MAX_DECIMALS decimals
+    pub fn new(amount: U256, decimals: u8) -> Result<Self> {
+        if decimals > MAX_DECIMALS {
+            return Err(BanyanError::Math(format!(
+                "A token can't have {} decimals, the most is {}",
+                decimals, MAX_DECIMALS
+            )));
+        }
+        Ok(TokenAmount {
+            amount,
+            decimals,
+            symbol: None,
+        })
+    }
+
+    /// Zero of a token with `decimals` decimals
+    pub fn zero(decimals: u8) -> Result<Self> {
+        Self::new(U256::zero(), decimals)
+    }
+
+    /// Attach a symbol to the amount, used when displaying it
+    pub fn with_symbol(mut self, symbol: &str) -> Self {
+        self.symbol = Some(symbol.to_uppercase());
+        self
+    }
+
+    /// Parse an amount like "12.5" or "12.5 USDC" of a token with `decimals` decimals
+    /// # Arguments
+    /// * `s` - The amount in whole tokens, optionally followed by the token's symbol
+    /// * `decimals` - How many decimals the token has
+    /// # Returns
+    /// * `TokenAmount` - The exact amount
+    /// # Errors
+    /// * `BanyanError::Parse` - If the amount isn't a decimal number, has more decimals than the
+    ///   token, or names a token we know to have different decimals
+    /// * `BanyanError::Math` - If the amount doesn't fit in a U256
+    pub fn parse(s: &str, decimals: u8) -> Result<Self> {
+        Self::parse_rounded(s, decimals, None)
+    }
+
+    /// Parse an amount like `parse`, rounding away digits past the token's decimals
+    pub fn parse_with_rounding(s: &str, decimals: u8, rounding: Rounding) -> Result<Self> {
+        Self::parse_rounded(s, decimals, Some(rounding))
+    }
+
+    fn parse_rounded(s: &str, decimals: u8, rounding: Option<Rounding>) -> Result<Self> {
+        let invalid =
+            |why: &str| BanyanError::Parse(format!("Invalid token amount {:?}: {}", s, why));
+        let mut parts = s.split_whitespace();
+        let number = parts.next().ok_or_else(|| invalid("it is empty"))?;
+        let symbol = parts.next();
+        if parts.next().is_some() {
+            return Err(invalid("expected a number and at most a symbol"));
+        }
+        if let Some(symbol) = symbol {
+            match known_decimals(symbol) {
+                Some(known) if known != decimals => {
+                    return Err(invalid(&format!(
+                        "{} has {} decimals, not {}",
+                        symbol.to_uppercase(),
+                        known,
+                        decimals
+                    )))
+                }
+                _ => {}
+            }
+        }
+
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        if whole.is_empty() && fraction.is_empty()
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid("expected a decimal number"));
+        }
+        let digits = decimals as usize;
+        let (kept, dropped) = fraction.split_at(fraction.len().min(digits));
+        let truncated = format!("{}{}{}", whole, kept, "0".repeat(digits - kept.len()));
+        let amount = U256::from_dec_str(&truncated).map_err(|_| {
+            BanyanError::Math(format!("Token amount {:?} doesn't fit in a U256", s))
+        })?;
+        let exact = dropped.chars().all(|c| c == '0');
+        let round_up = match rounding {
+            _ if exact => false,
+            None => {
+                return Err(invalid(&format!(
+                    "the token only has {} decimals",
+                    decimals
+                )));
+            }
+            Some(Rounding::Down) => false,
+            Some(Rounding::Up) => true,
+            Some(Rounding::Nearest) => dropped.as_bytes()[0] >= b'5',
+        };
+        let amount = if round_up {
+            checked(amount.checked_add(U256::one()), "rounding the amount up")?
+        } else {
+            amount
+        };
+        let parsed = Self::new(amount, decimals)?;
+        Ok(match symbol {
+            Some(symbol) => parsed.with_symbol(symbol),
+            None => parsed,
+        })
+    }
+
+    /* Accessors */
+
+    /// The amount in the token's smallest unit (e.g. wei)
+    pub fn amount(&self) -> U256 {
+        self.amount
+    }
+
+    /// How many decimals the token has
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+
+    /// The token's symbol, if we know it
+    pub fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    /// Whether the amount is zero
+    pub fn is_zero(&self) -> bool {
+        self.amount.is_zero()
+    }
+
+    /* Arithmetic */
+
+    /// Add another amount of the same token
+    /// # Errors
+    /// * `BanyanError::Math` - If the tokens have different decimals, or the sum overflows
+    pub fn checked_add(&self, other: &TokenAmount) -> Result<TokenAmount> {
+        self.same_token(other, "add")?;
+        let amount = checked(self.amount.checked_add(other.amount), "adding amounts")?;
+        Ok(self.with_amount(amount))
+    }
+
+    /// Subtract another amount of the same token
+    /// # Errors
+    /// * `BanyanError::Math` - If the tokens have different decimals, or `other` is larger
+    pub fn checked_sub(&self, other: &TokenAmount) -> Result<TokenAmount> {
+        self.same_token(other, "subtract")?;
+        let amount = checked(self.amount.checked_sub(other.amount), "subtracting amounts")?;
+        Ok(self.with_amount(amount))
+    }
+
+    /// Multiply the amount by `numerator / denominator`, e.g. a price per TiB by the TiB in a file
+    /// # Arguments
+    /// * `numerator` - What to multiply the amount by
+    /// * `denominator` - What to divide the product by
+    /// * `rounding` - How to round the result to the token's smallest unit
+    /// # Returns
+    /// * `TokenAmount` - The scaled amount. The product is never truncated before dividing.
+    /// # Errors
+    /// * `BanyanError::Math` - If the denominator is zero or the result doesn't fit in a U256
+    pub fn mul_div(
+        &self,
+        numerator: U256,
+        denominator: U256,
+        rounding: Rounding,
+    ) -> Result<TokenAmount> {
+        if denominator.is_zero() {
+            return Err(BanyanError::Math(format!("Cannot divide {} by zero", self)));
+        }
+        let product = self.amount.full_mul(numerator);
+        let amount = div_rounded(product, U512::from(denominator), rounding);
+        let amount = U256::try_from(amount).map_err(|_| {
+            BanyanError::Math(format!(
+                "{} * {} / {} doesn't fit in a U256",
+                self, numerator, denominator
+            ))
+        })?;
+        Ok(self.with_amount(amount))
+    }
+
+    /// The same amount expressed with a different number of decimals
+    /// # Errors
+    /// * `BanyanError::Math` - If `decimals` is over MAX_DECIMALS or the amount overflows
+    pub fn rescale(&self, decimals: u8, rounding: Rounding) -> Result<TokenAmount> {
+        let from = scale(self.decimals)?;
+        let to = scale(decimals)?;
+        let rescaled = self.mul_div(to, from, rounding)?;
+        Ok(TokenAmount {
+            decimals,
+            ..rescaled
+        })
+    }
+
+    fn with_amount(&self, amount: U256) -> TokenAmount {
+        TokenAmount {
+            amount,
+            ..self.clone()
+        }
+    }
+
+    fn same_token(&self, other: &TokenAmount, action: &str) -> Result<()> {
+        if self.decimals != other.decimals {
+            return Err(BanyanError::Math(format!(
+                "Cannot {} {} and {}: they have different decimals",
+                action, self, other
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Nothing, of a token with DEFAULT_DECIMALS decimals
+impl Default for TokenAmount {
+    fn default() -> Self {
+        TokenAmount {
+            amount: U256::zero(),
+            decimals: DEFAULT_DECIMALS,
+            symbol: None,
+        }
+    }
+}
+
+/// Parse an amount like "12.5 USDC" of a token we know, or "12.5" of a token with 18 decimals
+impl FromStr for TokenAmount {
+    type Err = BanyanError;
+    fn from_str(s: &str) -> Result<Self> {
+        let decimals = match s.split_whitespace().nth(1) {
+            Some(symbol) => known_decimals(symbol).ok_or_else(|| {
+                BanyanError::Parse(format!(
+                    "Unknown token {:?}, use TokenAmount::parse with its decimals",
+                    symbol
+                ))
+            })?,
+            None => DEFAULT_DECIMALS,
+        };
+        TokenAmount::parse(s, decimals)
+    }
+}
+
+/// Display the amount in whole tokens, e.g. "12.5 USDC"
+impl Display for TokenAmount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        // Decimals are checked on construction, so the scale always fits
+        let scale = U256::exp10(self.decimals as usize);
+        let whole = self.amount / scale;
+        let fraction = format!(
+            "{:0>width$}",
+            (self.amount % scale).to_string(),
+            width = self.decimals as usize
+        );
+        let fraction = fraction.trim_end_matches('0');
+        write!(f, "{}", whole)?;
+        if !fraction.is_empty() {
+            write!(f, ".{}", fraction)?;
+        }
+        if let Some(symbol) = &self.symbol {
+            write!(f, " {}", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+/// 10^decimals, the number of smallest units in a whole token
+fn scale(decimals: u8) -> Result<U256> {
+    if decimals > MAX_DECIMALS {
+        return Err(BanyanError::Math(format!(
+            "A token can't have {} decimals, the most is {}",
+            decimals, MAX_DECIMALS
+        )));
+    }
+    Ok(U256::exp10(decimals as usize))
+}
+
+/// Divide, rounding the remainder as asked
+fn div_rounded(numerator: U512, denominator: U512, rounding: Rounding) -> U512 {
+    let (quotient, remainder) = numerator.div_mod(denominator);
+    let round_up = match rounding {
+        _ if remainder.is_zero() => false,
+        Rounding::Down => false,
+        Rounding::Up => true,
+        // remainder >= denominator / 2, without overflowing
+        Rounding::Nearest => remainder >= denominator - remainder,
+    };
+    if round_up {
+        quotient + 1
+    } else {
+        quotient
+    }
+}
+
+fn checked(amount: Option<U256>, doing: &str) -> Result<U256> {
+    amount.ok_or_else(|| BanyanError::Math(format!("Overflow {}", doing)))
+}
+
+/// U256s as decimal strings rather than ethers' hex, so people can read and edit them.
+/// Hex starting with "0x", which is how they used to be written, is still read.
+pub(crate) mod decimal_u256 {
+    use ethers::types::U256;
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(amount: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&amount.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        let amount = String::deserialize(deserializer)?;
+        let parsed = match amount.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).map_err(|e| e.to_string()),
+            None => U256::from_dec_str(&amount).map_err(|e| e.to_string()),
+        };
+        parsed.map_err(|e| D::Error::custom(format!("Invalid amount {:?}: {}", amount, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let usdc: TokenAmount = "12.5 USDC".parse().unwrap();
+        assert_eq!(usdc.amount(), U256::from(12_500_000));
+        assert_eq!(usdc.decimals(), 6);
+        assert_eq!(usdc.to_string(), "12.5 USDC");
+
+        let ether: TokenAmount = "1000".parse().unwrap();
+        assert_eq!(ether.amount(), U256::exp10(21));
+        assert_eq!(ether.to_string(), "1000");
+        assert_eq!(
+            "0.000000000000000001"
+                .parse::<TokenAmount>()
+                .unwrap()
+                .amount(),
+            U256::one()
+        );
+        assert_eq!(".5".parse::<TokenAmount>().unwrap().to_string(), "0.5");
+        assert_eq!(TokenAmount::parse("7 xyz", 2).unwrap().to_string(), "7 XYZ");
+
+        // Way past what fits in a u64
+        let big: TokenAmount = "123456789012345678901234567890.5 DAI".parse().unwrap();
+        assert_eq!(big.to_string(), "123456789012345678901234567890.5 DAI");
+
+        assert!("".parse::<TokenAmount>().is_err());
+        assert!("1.2.3".parse::<TokenAmount>().is_err());
+        assert!("-1".parse::<TokenAmount>().is_err());
+        assert!("1 NOPE".parse::<TokenAmount>().is_err());
+        assert!(TokenAmount::parse("1 USDC", 18).is_err());
+        assert!(matches!(
+            "1.0000001 USDC".parse::<TokenAmount>(),
+            Err(BanyanError::Parse(_))
+        ));
+        assert!(TokenAmount::new(U256::one(), 78).is_err());
+    }
+
+    #[test]
+    fn rounding() {
+        let parse = |s, rounding| {
+            TokenAmount::parse_with_rounding(s, 2, rounding)
+                .unwrap()
+                .amount()
+        };
+        assert_eq!(parse("1.005", Rounding::Down), U256::from(100));
+        assert_eq!(parse("1.001", Rounding::Up), U256::from(101));
+        assert_eq!(parse("1.005", Rounding::Nearest), U256::from(101));
+        assert_eq!(parse("1.0049", Rounding::Nearest), U256::from(100));
+        assert_eq!(parse("1.0000", Rounding::Up), U256::from(100));
+
+        let amount = TokenAmount::new(U256::from(10), 0).unwrap();
+        let third = |rounding| {
+            amount
+                .mul_div(1.into(), 3.into(), rounding)
+                .unwrap()
+                .amount()
+        };
+        assert_eq!(third(Rounding::Down), U256::from(3));
+        assert_eq!(third(Rounding::Up), U256::from(4));
+        assert_eq!(third(Rounding::Nearest), U256::from(3));
+        let half = amount
+            .mul_div(1.into(), 4.into(), Rounding::Nearest)
+            .unwrap();
+        assert_eq!(half.amount(), U256::from(3));
+    }
+
+    #[test]
+    fn arithmetic() {
+        // Zero stays zero, it doesn't become 1 wei
+        let zero = TokenAmount::zero(18).unwrap();
+        let scaled = zero.mul_div(1000.into(), 3.into(), Rounding::Up).unwrap();
+        assert!(scaled.is_zero());
+
+        // The product can exceed a U256 as long as the result doesn't
+        let max = TokenAmount::new(U256::MAX, 18).unwrap();
+        let same = max.mul_div(U256::MAX, U256::MAX, Rounding::Down).unwrap();
+        assert_eq!(same, max);
+        assert!(matches!(
+            max.mul_div(2.into(), 1.into(), Rounding::Down),
+            Err(BanyanError::Math(_))
+        ));
+        assert!(max.mul_div(1.into(), 0.into(), Rounding::Down).is_err());
+
+        let a: TokenAmount = "1.5 USDC".parse().unwrap();
+        let b: TokenAmount = "2.25 USDC".parse().unwrap();
+        assert_eq!(a.checked_add(&b).unwrap().to_string(), "3.75 USDC");
+        assert_eq!(b.checked_sub(&a).unwrap().to_string(), "0.75 USDC");
+        assert!(a.checked_sub(&b).is_err());
+        assert!(a.checked_add(&"1 DAI".parse().unwrap()).is_err());
+
+        let rescaled = a.rescale(18, Rounding::Down).unwrap();
+        assert_eq!(rescaled.amount(), U256::from(15) * U256::exp10(17));
+        let back = TokenAmount::new(U256::from(1_234_567), 6)
+            .unwrap()
+            .rescale(2, Rounding::Nearest)
+            .unwrap();
+        assert_eq!(back.amount(), U256::from(123));
+    }
+    #[test]
+    fn amounts_are_written_as_decimals() {
+        let amount: TokenAmount = "2.5 USDC".parse().unwrap();
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, r#"{"amount":"2500000","decimals":6,"symbol":"USDC"}"#);
+        assert_eq!(serde_json::from_str::<TokenAmount>(&json).unwrap(), amount);
+        // Amounts written as hex before are still read
+        let hex = r#"{"amount":"0x2625a0","decimals":6,"symbol":"USDC"}"#;
+        assert_eq!(serde_json::from_str::<TokenAmount>(hex).unwrap(), amount);
+        let max = TokenAmount::new(U256::MAX, 18).unwrap();
+        let json = serde_json::to_string(&max).unwrap();
+        assert_eq!(serde_json::from_str::<TokenAmount>(&json).unwrap(), max);
+        let bad = r#"{"amount":"2.5","decimals":6,"symbol":null}"#;
+        assert!(serde_json::from_str::<TokenAmount>(bad).is_err());
+    }
+}
@@ -1,14 +1,19 @@
+use crate::{
+    error::{BanyanError, Result},
+    hash::PieceCommitment,
+    pricing::DealPricing,
+    token::decimal_u256,
+};
 use blake3::Hash as B3Hash;
-use cid::Cid;
+use cid::{multibase::Base, multihash::Multihash, Cid, Version};
 use ethers::{
     abi::{InvalidOutputType, Token, Tokenizable, Tokenize},
     // TODO: Can we import this somewhere / do we need this?
     types::{Address, Bytes, U256},
 };
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use sled::IVec;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::ops::{Add, Div, Mul, Rem, Sub};
+use std::str::FromStr;
 
 /// A Wrapper around the CID struct from the cid crate
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -21,9 +26,117 @@ impl Display for CidWrapper {
 }
 
 impl CidWrapper {
+    /// new - Make a CID for a Multihash
+    /// # Arguments
+    /// * `version` - The CID version. CIDv0 can only be dag-pb over a Sha2-256 Multihash.
+    /// * `codec` - What the hashed data is
+    /// * `multihash` - The hash of the data
+    /// # Errors
+    /// * `BanyanError::Parse` - If CIDv0 is asked for with another codec or hash function
+    pub fn new(version: Version, codec: CidCodec, multihash: Multihash) -> Result<Self> {
+        Cid::new(version, codec.code(), multihash)
+            .map(CidWrapper)
+            .map_err(|e| BanyanError::Parse(format!("Can't make a {:?} CID: {}", version, e)))
+    }
+
     pub fn cid(&self) -> Cid {
         self.0
     }
+
+    /// The version of the CID
+    pub fn version(&self) -> Version {
+        self.0.version()
+    }
+
+    /// The same content as a CIDv1, which every gateway understands
+    pub fn to_v1(&self) -> Self {
+        CidWrapper(Cid::new_v1(self.0.codec(), *self.0.hash()))
+    }
+
+    /// to_v0 - The same content as a CIDv0, for older tools that only understand "Qm..." CIDs
+    /// # Errors
+    /// * `BanyanError::Parse` - If the CID isn't dag-pb over a Sha2-256 Multihash
+    pub fn to_v0(&self) -> Result<Self> {
+        CidWrapper::new(
+            Version::V0,
+            CidCodec::try_from(self.0.codec())?,
+            *self.0.hash(),
+        )
+    }
+
+    /// to_string_of_base - Render the CID in a multibase
+    /// CIDv0 can only be written in base58btc, so it's rendered as CIDv1 in any other base.
+    /// # Arguments
+    /// * `base` - The multibase to write the CID in
+    pub fn to_string_of_base(&self, base: CidBase) -> String {
+        match (self.version(), base) {
+            (Version::V0, CidBase::Base58Btc) => self.0.to_string(),
+            _ => cid::multibase::encode(base.base(), self.to_v1().0.to_bytes()),
+        }
+    }
+}
+
+/// CidCodec - The multicodec of the data a CID points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum CidCodec {
+    /// The bytes of the file as they are
+    #[default]
+    #[serde(rename = "raw")]
+    Raw,
+    /// A UnixFS DAG, as `ipfs add` makes
+    #[serde(rename = "dag-pb")]
+    DagPb,
+    /// A DAG-CBOR IPLD node
+    #[serde(rename = "dag-cbor")]
+    DagCbor,
+}
+
+impl CidCodec {
+    /// The multicodec code of the codec
+    pub fn code(&self) -> u64 {
+        match self {
+            CidCodec::Raw => 0x55,
+            CidCodec::DagPb => 0x70,
+            CidCodec::DagCbor => 0x71,
+        }
+    }
+}
+
+impl TryFrom<u64> for CidCodec {
+    type Error = BanyanError;
+
+    fn try_from(code: u64) -> Result<Self> {
+        [CidCodec::Raw, CidCodec::DagPb, CidCodec::DagCbor]
+            .into_iter()
+            .find(|codec| codec.code() == code)
+            .ok_or_else(|| BanyanError::Parse(format!("Unsupported CID codec {:#x}", code)))
+    }
+}
+
+/// CidBase - A multibase to render CIDs in, for gateways that want a particular one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum CidBase {
+    /// Lowercase base32, "b...", the default for CIDv1 and subdomain gateways
+    #[default]
+    #[serde(rename = "base32")]
+    Base32,
+    /// Base58btc, "z..." for CIDv1 and "Qm..." for CIDv0
+    #[serde(rename = "base58btc")]
+    Base58Btc,
+    /// Lowercase base36, "k...", short enough for a DNS label
+    #[serde(rename = "base36")]
+    Base36,
+}
+
+impl CidBase {
+    /// The multibase the CID is written in
+    pub fn base(&self) -> Base {
+        match self {
+            CidBase::Base32 => Base::Base32Lower,
+            CidBase::Base58Btc => Base::Base58Btc,
+            CidBase::Base36 => Base::Base36Lower,
+        }
+    }
 }
 
 /// Impl Tokenizable for CidToken - This allows us to use CidToken as a Token in the ethers crate
@@ -31,7 +144,9 @@ impl Tokenizable for CidWrapper {
     /// Convert a Token::String to a CidToken
     fn from_token(token: Token) -> Result<Self, InvalidOutputType> {
         match token {
-            Token::String(s) => Ok(CidWrapper(Cid::try_from(s).unwrap())),
+            Token::String(s) => Cid::try_from(s.as_str())
+                .map(CidWrapper)
+                .map_err(|e| InvalidOutputType(format!("Invalid CID {:?}: {}", s, e))),
             other => Err(InvalidOutputType(format!(
                 "Expected `String`, got {:?}",
                 other
@@ -59,7 +174,9 @@ impl<'de> Deserialize<'de> for CidWrapper {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Ok(CidWrapper(Cid::try_from(s).unwrap()))
+        Cid::try_from(s.as_str())
+            .map(CidWrapper)
+            .map_err(|e| D::Error::custom(format!("Invalid CID {:?}: {}", s, e)))
     }
 }
 
@@ -95,7 +212,9 @@ impl Tokenizable for Blake3Hash {
     }
     fn from_token(token: Token) -> Result<Self, InvalidOutputType> {
         match token {
-            Token::String(s) => Ok(Blake3Hash(B3Hash::from_hex(s).unwrap())),
+            Token::String(s) => B3Hash::from_hex(&s)
+                .map(Blake3Hash)
+                .map_err(|e| InvalidOutputType(format!("Invalid Blake3 hash {:?}: {}", s, e))),
             other => Err(InvalidOutputType(format!(
                 "Expected `String`, got {:?}",
                 other
@@ -104,12 +223,17 @@ impl Tokenizable for Blake3Hash {
     }
 }
 
+/// Blake3 hashes are hex in formats meant for people (JSON, TOML), and bytes otherwise
 impl Serialize for Blake3Hash {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        serializer.serialize_bytes(self.as_bytes())
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_hex())
+        } else {
+            serializer.serialize_bytes(self.as_bytes())
+        }
     }
 }
 
@@ -118,11 +242,43 @@ impl<'de> Deserialize<'de> for Blake3Hash {
     where
         D: Deserializer<'de>,
     {
-        let b: [u8; 32] = <[u8; 32]>::deserialize(deserializer)?;
-        Ok(Blake3Hash(B3Hash::from(b)))
+        if !deserializer.is_human_readable() {
+            let b: [u8; 32] = <[u8; 32]>::deserialize(deserializer)?;
+            return Ok(Blake3Hash(B3Hash::from(b)));
+        }
+        // Hashes used to be written as an array of bytes, so those are still read
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Hex(String),
+            Bytes([u8; 32]),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Hex(hex) => B3Hash::from_hex(&hex)
+                .map(Blake3Hash)
+                .map_err(|e| D::Error::custom(format!("Invalid Blake3 hash {:?}: {}", hex, e))),
+            Repr::Bytes(b) => Ok(Blake3Hash(B3Hash::from(b))),
+        }
     }
 }
 
+/// checked_uint - Narrow a number read from chain, rather than silently truncating it
+/// # Arguments
+/// * `value` - The number, as the chain returned it
+/// * `what` - What the number is, for the error message
+/// # Errors
+/// * `BanyanError::Parse` - If the number doesn't fit in a `T`
+pub fn checked_uint<T: TryFrom<U256>>(value: U256, what: &str) -> Result<T> {
+    T::try_from(value).map_err(|_| {
+        BanyanError::Parse(format!(
+            "{} {} doesn't fit in a {}",
+            what,
+            value,
+            std::any::type_name::<T>()
+        ))
+    })
+}
+
 /// DealIDs - The onChain ID of a deal submitted to Ethereum
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, PartialOrd, Ord)]
 pub struct DealID(pub u64);
@@ -144,7 +300,7 @@ impl Display for DealID {
 impl Tokenizable for DealID {
     fn from_token(token: Token) -> Result<Self, InvalidOutputType> {
         match token {
-            Token::Uint(u) => Ok(DealID(u.as_u64())),
+            Token::Uint(u) => Ok(DealID(checked_uint(u, "DealID")?)),
             other => Err(InvalidOutputType(format!(
                 "Expected `Token::Uint()`, got {:?} for DealID",
                 other
@@ -156,22 +312,6 @@ impl Tokenizable for DealID {
     }
 }
 
-#[allow(clippy::from_over_into)]
-impl Into<IVec> for DealID {
-    fn into(self) -> IVec {
-        IVec::from(&self.0.to_le_bytes())
-    }
-}
-
-impl From<IVec> for DealID {
-    fn from(iv: IVec) -> Self {
-        let bytes = iv.as_ref();
-        let mut deal_id_bytes = [0u8; 8];
-        deal_id_bytes.copy_from_slice(&bytes[..8]);
-        DealID(u64::from_le_bytes(deal_id_bytes))
-    }
-}
-
 /// Block Number - a wrapper around u64 to specify an Ethereum block number
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, PartialOrd, Ord)]
 pub struct BlockNum(pub u64);
@@ -186,7 +326,7 @@ impl Display for BlockNum {
 impl Tokenizable for BlockNum {
     fn from_token(token: Token) -> Result<Self, InvalidOutputType> {
         match token {
-            Token::Uint(u) => Ok(BlockNum(u.as_u64())),
+            Token::Uint(u) => Ok(BlockNum(checked_uint(u, "BlockNum")?)),
             other => Err(InvalidOutputType(format!(
                 "Expected `Token::Uint()`, got {:?} for BlockNum",
                 other
@@ -198,83 +338,196 @@ impl Tokenizable for BlockNum {
     }
 }
 
-impl Add for BlockNum {
-    type Output = Self;
-    fn add(self, other: Self) -> Self {
-        BlockNum(self.0 + other.0)
+/* Checked and saturating arithmetic, for block numbers that come from the chain or from users */
+impl BlockNum {
+    /// Add two block numbers, or a Math error if the sum overflows
+    pub fn checked_add(self, other: BlockNum) -> Result<BlockNum> {
+        self.0.checked_add(other.0).map(BlockNum).ok_or_else(|| {
+            BanyanError::Math(format!("Block number overflow: {} + {}", self, other))
+        })
     }
-}
 
-impl Sub for BlockNum {
-    type Output = Self;
-    fn sub(self, other: Self) -> Self {
-        BlockNum(self.0 - other.0)
+    /// Subtract a block number, or a Math error if `other` is larger
+    pub fn checked_sub(self, other: BlockNum) -> Result<BlockNum> {
+        self.0.checked_sub(other.0).map(BlockNum).ok_or_else(|| {
+            BanyanError::Math(format!("Block number underflow: {} - {}", self, other))
+        })
     }
-}
 
-impl Mul for BlockNum {
-    type Output = Self;
-    fn mul(self, other: Self) -> Self {
-        BlockNum(self.0 * other.0)
+    /// Multiply by a count, or a Math error if the product overflows
+    pub fn checked_mul(self, count: u64) -> Result<BlockNum> {
+        self.0.checked_mul(count).map(BlockNum).ok_or_else(|| {
+            BanyanError::Math(format!("Block number overflow: {} * {}", self, count))
+        })
     }
-}
 
-impl Mul<u64> for BlockNum {
-    type Output = Self;
-    fn mul(self, other: u64) -> Self {
-        BlockNum(self.0 * other)
+    /// Divide by a number of blocks, or a Math error if it's zero
+    pub fn checked_div(self, other: BlockNum) -> Result<BlockNum> {
+        self.0
+            .checked_div(other.0)
+            .map(BlockNum)
+            .ok_or_else(|| BanyanError::Math(format!("Cannot divide {} blocks by zero", self)))
     }
-}
 
-impl Mul<usize> for BlockNum {
-    type Output = Self;
-    fn mul(self, other: usize) -> Self {
-        let u = other as u64;
-        BlockNum(self.0 * u)
+    /// The remainder of dividing by a number of blocks, or a Math error if it's zero
+    pub fn checked_rem(self, other: BlockNum) -> Result<BlockNum> {
+        self.0
+            .checked_rem(other.0)
+            .map(BlockNum)
+            .ok_or_else(|| BanyanError::Math(format!("Cannot divide {} blocks by zero", self)))
     }
-}
 
-impl Div for BlockNum {
-    type Output = Self;
-    fn div(self, other: Self) -> Self {
-        BlockNum(self.0 / other.0)
+    /// Add two block numbers, stopping at the largest block number
+    pub fn saturating_add(self, other: BlockNum) -> BlockNum {
+        BlockNum(self.0.saturating_add(other.0))
+    }
+
+    /// Subtract a block number, stopping at block 0
+    pub fn saturating_sub(self, other: BlockNum) -> BlockNum {
+        BlockNum(self.0.saturating_sub(other.0))
     }
-}
 
-impl Rem for BlockNum {
-    type Output = Self;
-    fn rem(self, other: Self) -> Self {
-        BlockNum(self.0 % other.0)
+    /// Multiply by a count, stopping at the largest block number
+    pub fn saturating_mul(self, count: u64) -> BlockNum {
+        BlockNum(self.0.saturating_mul(count))
     }
 }
 
-/// Token Multiplier - a wrapper around u64 to specify a multiplier for a token
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, PartialOrd, Ord)]
-pub struct TokenMultiplier(pub u64);
+/// BlockRange - The blocks from `start` up to, but not including, `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRange {
+    start: BlockNum,
+    end: BlockNum,
+}
 
-// Our default multiplier is 1e18
-impl Default for TokenMultiplier {
-    fn default() -> Self {
-        TokenMultiplier(1_000_000_000_000_000_000)
+impl BlockRange {
+    /// Create a BlockRange of the blocks from `start` up to, but not including, `end`
+    /// # Errors
+    /// * `BanyanError::Math` - If `end` is before `start`
+    pub fn new(start: BlockNum, end: BlockNum) -> Result<BlockRange> {
+        if end < start {
+            return Err(BanyanError::Math(format!(
+                "A block range can't end ({}) before it starts ({})",
+                end, start
+            )));
+        }
+        Ok(BlockRange { start, end })
     }
-}
 
-/// Multiply a TokenMultiplier as a float and return the result as U256
-/// Warning: Non-Deterministic
-impl Mul<f64> for TokenMultiplier {
-    type Output = U256;
-    fn mul(self, other: f64) -> U256 {
-        let amount = (self.0 as f64 * other).round() as u64;
-        if amount == 0 {
-            U256::from(1) // This is the smallest a U256 can be
-        } else {
-            U256::from(amount)
+    /// Create a BlockRange of `length` blocks, starting at `start`
+    /// # Errors
+    /// * `BanyanError::Math` - If the range would end past the largest block number
+    pub fn from_start_and_length(start: BlockNum, length: BlockNum) -> Result<BlockRange> {
+        Ok(BlockRange {
+            start,
+            end: start.checked_add(length)?,
+        })
+    }
+
+    /// The first block in the range
+    pub fn start(&self) -> BlockNum {
+        self.start
+    }
+
+    /// The first block after the range
+    pub fn end(&self) -> BlockNum {
+        self.end
+    }
+
+    /// The number of blocks in the range
+    pub fn len(&self) -> BlockNum {
+        BlockNum(self.end.0 - self.start.0)
+    }
+
+    /// Whether the range has no blocks
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Whether a block is in the range
+    pub fn contains(&self, block_num: BlockNum) -> bool {
+        self.start <= block_num && block_num < self.end
+    }
+
+    /// Every block in the range, in order
+    pub fn iter(&self) -> impl Iterator<Item = BlockNum> {
+        (self.start.0..self.end.0).map(BlockNum)
+    }
+
+    /// window - The `index`th window of `window_size` blocks, counting from the start of the range
+    /// # Returns
+    /// * `Option<BlockRange>` - The window, cut short at the end of the range, or None if it
+    ///   starts after the range ends
+    /// # Errors
+    /// * `BanyanError::Math` - If the window size is zero
+    pub fn window(&self, index: u64, window_size: BlockNum) -> Result<Option<BlockRange>> {
+        if window_size.0 == 0 {
+            return Err(zero_window_size());
         }
+        let start = match window_size
+            .checked_mul(index)
+            .and_then(|offset| self.start.checked_add(offset))
+        {
+            Ok(start) if start < self.end => start,
+            _ => return Ok(None),
+        };
+        let end = start.saturating_add(window_size).min(self.end);
+        Ok(Some(BlockRange { start, end }))
+    }
+
+    /// window_of - The window of `window_size` blocks a block falls in
+    /// # Returns
+    /// * `Option<(u64, BlockRange)>` - The index of the window and the window itself, or None if
+    ///   the block isn't in the range
+    /// # Errors
+    /// * `BanyanError::Math` - If the window size is zero
+    pub fn window_of(
+        &self,
+        block_num: BlockNum,
+        window_size: BlockNum,
+    ) -> Result<Option<(u64, BlockRange)>> {
+        if window_size.0 == 0 {
+            return Err(zero_window_size());
+        }
+        if !self.contains(block_num) {
+            return Ok(None);
+        }
+        let index = block_num
+            .checked_sub(self.start)?
+            .checked_div(window_size)?
+            .0;
+        Ok(self
+            .window(index, window_size)?
+            .map(|window| (index, window)))
+    }
+
+    /// split_into_windows - Split the range into consecutive windows of `window_size` blocks.
+    /// The last window is cut short if the range doesn't divide evenly.
+    /// # Errors
+    /// * `BanyanError::Math` - If the window size is zero
+    pub fn split_into_windows(&self, window_size: BlockNum) -> Result<Vec<BlockRange>> {
+        let mut windows = vec![];
+        while let Some(window) = self.window(windows.len() as u64, window_size)? {
+            windows.push(window);
+        }
+        Ok(windows)
+    }
+}
+
+fn zero_window_size() -> BanyanError {
+    BanyanError::Math("Cannot split blocks into windows of 0 blocks".to_string())
+}
+
+impl Display for BlockRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "[{}, {})", self.start, self.end)
     }
 }
 
-/// An Enum describing the different states a deal can be in
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Copy)]
+/// An Enum describing the different states a deal can be in.
+/// A deal moves Created -> Accepted -> Active -> Completed -> Finalized, unless it times out or
+/// is cancelled before it's accepted.
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash)]
 pub enum DealStatus {
     /// The deal does not exist
     Non = 0,
@@ -294,6 +547,73 @@ pub enum DealStatus {
     DealCancelled = 7,
 }
 
+/// Every status, in on-chain order
+const DEAL_STATUSES: [DealStatus; 8] = [
+    DealStatus::Non,
+    DealStatus::DealCreated,
+    DealStatus::DealAccepted,
+    DealStatus::DealActive,
+    DealStatus::DealCompleted,
+    DealStatus::DealFinalized,
+    DealStatus::DealTimedOut,
+    DealStatus::DealCancelled,
+];
+
+impl DealStatus {
+    /// The statuses a deal in this status can move to next
+    pub fn next_statuses(&self) -> &'static [DealStatus] {
+        match self {
+            DealStatus::Non => &[DealStatus::DealCreated],
+            DealStatus::DealCreated => &[
+                DealStatus::DealAccepted,
+                DealStatus::DealTimedOut,
+                DealStatus::DealCancelled,
+            ],
+            DealStatus::DealAccepted => &[DealStatus::DealActive],
+            DealStatus::DealActive => &[DealStatus::DealCompleted],
+            DealStatus::DealCompleted => &[DealStatus::DealFinalized],
+            DealStatus::DealFinalized | DealStatus::DealTimedOut | DealStatus::DealCancelled => &[],
+        }
+    }
+
+    /// Whether a deal can move straight from this status to `next`
+    pub fn can_transition_to(&self, next: DealStatus) -> bool {
+        self.next_statuses().contains(&next)
+    }
+
+    /// Whether a deal can end up in `later` through any number of transitions. Someone polling
+    /// the chain can see a deal skip statuses, but never see it go backwards.
+    pub fn can_reach(&self, later: DealStatus) -> bool {
+        self.next_statuses()
+            .iter()
+            .any(|next| *next == later || next.can_reach(later))
+    }
+
+    /// Whether the deal is over, and nothing more can happen to it
+    pub fn is_terminal(&self) -> bool {
+        self.next_statuses().is_empty()
+    }
+
+    /// Whether proofs can be posted for the deal. The deployed contract starts a deal's clock
+    /// when it's created, so proofs are taken from then until the deal is completed.
+    pub fn accepts_proofs(&self) -> bool {
+        matches!(
+            self,
+            DealStatus::DealCreated | DealStatus::DealAccepted | DealStatus::DealActive
+        )
+    }
+}
+
+impl TryFrom<u64> for DealStatus {
+    type Error = InvalidOutputType;
+    fn try_from(n: u64) -> Result<Self, Self::Error> {
+        DEAL_STATUSES
+            .get(n as usize)
+            .copied()
+            .ok_or_else(|| InvalidOutputType(format!("Invalid Deal Status {}", n)))
+    }
+}
+
 /// Impl Tokenizable for DealStatus - this allows us to treat it like a Token with with Ethers Crate
 impl Tokenizable for DealStatus {
     fn into_token(self) -> Token {
@@ -301,22 +621,9 @@ impl Tokenizable for DealStatus {
     }
     fn from_token(token: Token) -> Result<Self, InvalidOutputType> {
         match token {
-            Token::Uint(u) => match u.as_u64() {
-                0 => Ok(DealStatus::Non),
-                1 => Ok(DealStatus::DealCreated),
-                2 => Ok(DealStatus::DealAccepted),
-                3 => Ok(DealStatus::DealActive),
-                4 => Ok(DealStatus::DealCompleted),
-                5 => Ok(DealStatus::DealFinalized),
-                6 => Ok(DealStatus::DealTimedOut),
-                7 => Ok(DealStatus::DealCancelled),
-                _ => Err(InvalidOutputType(format!(
-                    "Expected `Token::Uint()`, got {:?} for Deal Status",
-                    token
-                ))),
-            },
+            Token::Uint(u) => DealStatus::try_from(checked_uint::<u64>(u, "Deal Status")?),
             other => Err(InvalidOutputType(format!(
-                "Expected `Token::Uint()`, got {:?} for Deal Status 2",
+                "Expected `Token::Uint()`, got {:?} for Deal Status",
                 other
             ))),
         }
@@ -338,6 +645,50 @@ impl Display for DealStatus {
     }
 }
 
+/// Parse a status from its name, with or without the `Deal` prefix and in any case,
+/// e.g. "DealActive", "active" or "timed_out"
+impl FromStr for DealStatus {
+    type Err = BanyanError;
+    fn from_str(s: &str) -> Result<Self> {
+        let wanted: String = s
+            .chars()
+            .filter(|c| *c != '_' && *c != '-')
+            .collect::<String>()
+            .to_lowercase();
+        DEAL_STATUSES
+            .iter()
+            .find(|status| {
+                let name = status.to_string().to_lowercase();
+                name == wanted || name.strip_prefix("deal") == Some(wanted.as_str())
+            })
+            .copied()
+            .ok_or_else(|| BanyanError::Parse(format!("Invalid Deal Status {:?}", s)))
+    }
+}
+
+/// Serialize a DealStatus as its name
+impl Serialize for DealStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserialize a DealStatus from its name, or from its on-chain number
+impl<'de> Deserialize<'de> for DealStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Name(String),
+            Number(u64),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Name(name) => name.parse().map_err(D::Error::custom),
+            Repr::Number(n) => DealStatus::try_from(n).map_err(D::Error::custom),
+        }
+    }
+}
+
 /// DealProposal - What is submitted to the Ethereum contract to create a deal
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DealProposal {
@@ -348,17 +699,27 @@ pub struct DealProposal {
     /// The frequency with which to submit proofs to chain
     pub proof_frequency_in_blocks: BlockNum,
     /// The amount of tokens to pay to the executor
+    #[serde(with = "decimal_u256")]
     pub price: U256,
     /// The amount of collateral the executor must post
+    #[serde(with = "decimal_u256")]
     pub collateral: U256,
     /// The token to use for payment
     pub erc20_token_denomination: Address,
     /// The File size of the data to be stored
+    #[serde(with = "decimal_u256")]
     pub file_size: U256, // TODO: Change this to a U64
     /// The CID of the data to be stored
     pub ipfs_file_cid: CidWrapper,
     /// The blake3 hash of the data to be stored
     pub blake3_checksum: Blake3Hash,
+    /// How the price and collateral were derived. Not sent to the contract.
+    #[serde(default)]
+    pub pricing: Option<DealPricing>,
+    /// The Filecoin piece commitment of the data, to check Filecoin deals for it against. Not
+    /// sent to the contract.
+    #[serde(default)]
+    pub piece_commitment: Option<PieceCommitment>,
 }
 
 impl Display for DealProposal {
@@ -371,7 +732,15 @@ impl Display for DealProposal {
         writeln!(f, "Token Denomination: {}", self.erc20_token_denomination)?;
         writeln!(f, "File Size: {}", self.file_size)?;
         writeln!(f, "File CID: {}", self.ipfs_file_cid)?;
-        write!(f, "File Blake3 Checksum: {}", self.blake3_checksum)
+        write!(f, "File Blake3 Checksum: {}", self.blake3_checksum)?;
+        if let Some(pricing) = &self.pricing {
+            write!(f, "\nBounty Breakdown: {}", pricing.price)?;
+            write!(f, "\nCollateral Breakdown: {}", pricing.collateral)?;
+        }
+        if let Some(piece_commitment) = &self.piece_commitment {
+            write!(f, "\nPiece CID: {}", piece_commitment)?;
+        }
+        Ok(())
     }
 }
 
@@ -391,7 +760,6 @@ impl Tokenize for DealProposal {
     }
 }
 
-// TODO: Re-incorporate DealStatus
 /// OnChainDealInfo - Information about a deal that is stored on chain
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct OnChainDealInfo {
@@ -433,18 +801,18 @@ impl Tokenizable for OnChainDealInfo {
             Token::Tuple(tokens) => {
                 let mut tokens = tokens.into_iter();
                 Ok(OnChainDealInfo {
-                    deal_start_block: BlockNum::from_token(tokens.next().unwrap())?,
-                    deal_length_in_blocks: BlockNum::from_token(tokens.next().unwrap())?,
-                    proof_frequency_in_blocks: BlockNum::from_token(tokens.next().unwrap())?,
-                    price: U256::from_token(tokens.next().unwrap())?,
-                    collateral: U256::from_token(tokens.next().unwrap())?,
-                    erc20_token_denomination: Address::from_token(tokens.next().unwrap())?,
-                    ipfs_file_cid: CidWrapper::from_token(tokens.next().unwrap())?,
-                    file_size: U256::from_token(tokens.next().unwrap())?,
-                    blake3_checksum: Blake3Hash::from_token(tokens.next().unwrap())?,
-                    creator_address: Address::from_token(tokens.next().unwrap())?,
-                    executor_address: Address::from_token(tokens.next().unwrap())?,
-                    deal_status: DealStatus::from_token(tokens.next().unwrap())?,
+                    deal_start_block: BlockNum::from_token(next_token(&mut tokens)?)?,
+                    deal_length_in_blocks: BlockNum::from_token(next_token(&mut tokens)?)?,
+                    proof_frequency_in_blocks: BlockNum::from_token(next_token(&mut tokens)?)?,
+                    price: U256::from_token(next_token(&mut tokens)?)?,
+                    collateral: U256::from_token(next_token(&mut tokens)?)?,
+                    erc20_token_denomination: Address::from_token(next_token(&mut tokens)?)?,
+                    ipfs_file_cid: CidWrapper::from_token(next_token(&mut tokens)?)?,
+                    file_size: U256::from_token(next_token(&mut tokens)?)?,
+                    blake3_checksum: Blake3Hash::from_token(next_token(&mut tokens)?)?,
+                    creator_address: Address::from_token(next_token(&mut tokens)?)?,
+                    executor_address: Address::from_token(next_token(&mut tokens)?)?,
+                    deal_status: DealStatus::from_token(next_token(&mut tokens)?)?,
                 })
             }
             other => Err(InvalidOutputType(format!(
@@ -471,9 +839,24 @@ impl Tokenizable for OnChainDealInfo {
     }
 }
 
+/// Take the next field of a tuple being decoded, or complain that there aren't enough
+fn next_token(tokens: &mut impl Iterator<Item = Token>) -> Result<Token, InvalidOutputType> {
+    tokens
+        .next()
+        .ok_or_else(|| InvalidOutputType("Not enough tokens in tuple".to_string()))
+}
+
 impl OnChainDealInfo {
     pub fn get_final_block(&self) -> BlockNum {
-        self.deal_start_block + self.deal_length_in_blocks
+        self.deal_start_block
+            .saturating_add(self.deal_length_in_blocks)
+    }
+
+    /// The blocks the deal runs for
+    /// # Errors
+    /// * `BanyanError::Math` - If the deal would end past the largest block number
+    pub fn blocks(&self) -> Result<BlockRange> {
+        BlockRange::from_start_and_length(self.deal_start_block, self.deal_length_in_blocks)
     }
 }
 
@@ -490,9 +873,9 @@ impl Tokenizable for Proof {
             Token::Tuple(tokens) => {
                 let mut tokens = tokens.into_iter();
                 Ok(Proof {
-                    bao_proof_data: Bytes::from_token(tokens.next().unwrap())?,
-                    deal_id: DealID::from_token(tokens.next().unwrap())?,
-                    target_block_start: BlockNum::from_token(tokens.next().unwrap())?,
+                    bao_proof_data: Bytes::from_token(next_token(&mut tokens)?)?,
+                    deal_id: DealID::from_token(next_token(&mut tokens)?)?,
+                    target_block_start: BlockNum::from_token(next_token(&mut tokens)?)?,
                 })
             }
             other => Err(InvalidOutputType(format!(
@@ -510,10 +893,221 @@ impl Tokenizable for Proof {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+/// ProofBuddyMessageType - The kinds of `proof_buddy::ProofBuddyMessage`, which workers are routed by
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ProofBuddyMessageType {
     SubmitProof,
     Cancel,
     InitiateChainlinkFinalization,
     WithdrawEarnings,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Proposals are written with decimal amounts, but still read the hex they used to have
+    fn proposal_amounts_are_decimals() {
+        let proposal = DealProposal::builder()
+            .with_path("test_files/ethereum.pdf")
+            .with_price_per_tib("2.5".parse().unwrap())
+            .with_collateral_per_tib("1".parse().unwrap())
+            .build()
+            .unwrap();
+        let mut json = serde_json::to_value(&proposal).unwrap();
+        for (field, amount) in [
+            ("price", proposal.price),
+            ("collateral", proposal.collateral),
+            ("file_size", proposal.file_size),
+        ] {
+            assert_eq!(json[field], amount.to_string());
+            json[field] = format!("{:#x}", amount).into();
+        }
+        assert_eq!(
+            serde_json::from_value::<DealProposal>(json).unwrap(),
+            proposal
+        );
+    }
+
+    #[test]
+    /// Hashes are hex for people, but still read as the arrays of bytes they used to be written as
+    fn blake3_hashes_are_hex_for_people() {
+        let hash = Blake3Hash(blake3::hash(b"banyan"));
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", hash.to_hex()));
+        assert_eq!(serde_json::from_str::<Blake3Hash>(&json).unwrap(), hash);
+        let bytes = serde_json::to_string(hash.as_bytes()).unwrap();
+        assert_eq!(serde_json::from_str::<Blake3Hash>(&bytes).unwrap(), hash);
+        assert!(serde_json::from_str::<Blake3Hash>("\"abc\"").is_err());
+        // Formats that aren't meant for people still get the bytes
+        let mut cbor = vec![];
+        ciborium::ser::into_writer(&hash, &mut cbor).unwrap();
+        assert_eq!(cbor.len(), 2 + 32);
+        assert_eq!(
+            ciborium::de::from_reader::<Blake3Hash, _>(cbor.as_slice()).unwrap(),
+            hash
+        );
+    }
+
+    #[test]
+    fn cid_versions_and_bases() {
+        use multihash::{Code, MultihashDigest};
+
+        let multihash = Code::Sha2_256.digest(b"hello world");
+        let v0 = CidWrapper::new(Version::V0, CidCodec::DagPb, multihash).unwrap();
+        assert!(v0.to_string().starts_with("Qm"));
+        let v1 = v0.to_v1();
+        assert_eq!(v1.version(), Version::V1);
+        assert_eq!(v1.to_v0().unwrap(), v0);
+        assert_eq!(v1.to_v1(), v1);
+
+        // Every rendering parses back to the same content
+        assert_eq!(v0.to_string_of_base(CidBase::Base58Btc), v0.to_string());
+        for (base, prefix) in [
+            (CidBase::Base32, "bafy"),
+            (CidBase::Base58Btc, "z"),
+            (CidBase::Base36, "k"),
+        ] {
+            let rendered = v1.to_string_of_base(base);
+            assert!(rendered.starts_with(prefix), "{}", rendered);
+            assert_eq!(Cid::try_from(rendered.as_str()).unwrap(), v1.cid());
+            if base != CidBase::Base58Btc {
+                assert_eq!(v0.to_string_of_base(base), rendered);
+            }
+        }
+        assert_eq!(v1.to_string_of_base(CidBase::Base32), v1.to_string());
+
+        // CIDv0 is only dag-pb over Sha2-256
+        let raw = CidWrapper::new(Version::V1, CidCodec::Raw, multihash).unwrap();
+        assert!(matches!(raw.to_v0(), Err(BanyanError::Parse(_))));
+        assert!(CidWrapper::new(Version::V0, CidCodec::DagCbor, multihash).is_err());
+        let blake3 = Code::Blake3_256.digest(b"hello world");
+        assert!(CidWrapper::new(Version::V0, CidCodec::DagPb, blake3).is_err());
+        assert_eq!(CidCodec::try_from(0x71).unwrap(), CidCodec::DagCbor);
+        assert!(CidCodec::try_from(0x12).is_err());
+    }
+
+    #[test]
+    /// Garbage from the chain is an error, not a panic
+    fn invalid_tokens_are_errors() {
+        assert!(CidWrapper::from_token(Token::String("not a cid".to_string())).is_err());
+        assert!(CidWrapper::from_token(Token::String(String::new())).is_err());
+        assert!(Blake3Hash::from_token(Token::String("abc".to_string())).is_err());
+        assert!(serde_json::from_str::<CidWrapper>(r#""not a cid""#).is_err());
+        assert!(OnChainDealInfo::from_token(Token::Tuple(vec![Token::Uint(1.into())])).is_err());
+        assert!(Proof::from_token(Token::Tuple(vec![])).is_err());
+
+        // Numbers too big for their type aren't truncated
+        let too_big = U256::from(u64::MAX) + 1;
+        assert!(DealID::from_token(Token::Uint(too_big)).is_err());
+        assert!(BlockNum::from_token(Token::Uint(too_big)).is_err());
+        assert!(DealStatus::from_token(Token::Uint(too_big)).is_err());
+        assert_eq!(checked_uint::<u16>(U256::from(7), "Status").unwrap(), 7);
+        let err = checked_uint::<u16>(U256::from(1 << 16), "Status").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse error: Status 65536 doesn't fit in a u16"
+        );
+    }
+
+    #[test]
+    fn checked_block_maths() {
+        assert_eq!(BlockNum(3).checked_add(BlockNum(4)).unwrap(), BlockNum(7));
+        assert!(matches!(
+            BlockNum(u64::MAX).checked_add(BlockNum(1)),
+            Err(BanyanError::Math(_))
+        ));
+        assert!(BlockNum(3).checked_sub(BlockNum(5)).is_err());
+        assert!(BlockNum(u64::MAX).checked_mul(2).is_err());
+        assert!(BlockNum(3).checked_div(BlockNum(0)).is_err());
+        assert!(BlockNum(3).checked_rem(BlockNum(0)).is_err());
+        assert_eq!(BlockNum(7).checked_rem(BlockNum(5)).unwrap(), BlockNum(2));
+        assert_eq!(BlockNum(3).saturating_sub(BlockNum(5)), BlockNum(0));
+        assert_eq!(
+            BlockNum(u64::MAX).saturating_add(BlockNum(1)),
+            BlockNum(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn block_ranges() {
+        assert!(BlockRange::new(BlockNum(5), BlockNum(3)).is_err());
+        assert!(BlockRange::from_start_and_length(BlockNum(u64::MAX), BlockNum(1)).is_err());
+
+        // A deal of 20 blocks starting at block 3, with proofs every 6 blocks
+        let range = BlockRange::from_start_and_length(BlockNum(3), BlockNum(20)).unwrap();
+        assert_eq!(range.to_string(), "[3, 23)");
+        assert_eq!(range.len(), BlockNum(20));
+        assert!(range.contains(BlockNum(3)));
+        assert!(range.contains(BlockNum(22)));
+        assert!(!range.contains(BlockNum(23)));
+        assert!(!range.contains(BlockNum(2)));
+        assert_eq!(range.iter().count(), 20);
+        assert_eq!(range.iter().last(), Some(BlockNum(22)));
+
+        let windows = range.split_into_windows(BlockNum(6)).unwrap();
+        let starts: Vec<_> = windows.iter().map(|w| w.start().0).collect();
+        assert_eq!(starts, vec![3, 9, 15, 21]);
+        assert_eq!(windows[3].len(), BlockNum(2));
+        assert_eq!(
+            range.window_of(BlockNum(16), BlockNum(6)).unwrap(),
+            Some((2, windows[2]))
+        );
+        assert_eq!(range.window_of(BlockNum(23), BlockNum(6)).unwrap(), None);
+        assert_eq!(range.window(4, BlockNum(6)).unwrap(), None);
+        assert!(range.split_into_windows(BlockNum(0)).is_err());
+        assert!(range.window_of(BlockNum(23), BlockNum(0)).is_err());
+
+        let empty = BlockRange::new(BlockNum(4), BlockNum(4)).unwrap();
+        assert!(empty.is_empty());
+        assert!(empty.split_into_windows(BlockNum(1)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn deal_status_transitions() {
+        use DealStatus::*;
+        assert!(DealCreated.can_transition_to(DealAccepted));
+        assert!(DealCreated.can_transition_to(DealCancelled));
+        assert!(!DealCreated.can_transition_to(DealActive));
+        assert!(DealCreated.can_reach(DealFinalized));
+        assert!(!DealActive.can_reach(DealCancelled));
+        assert!(!DealFinalized.can_reach(DealActive));
+        assert!(!DealActive.can_reach(DealActive));
+        for status in DEAL_STATUSES {
+            assert_eq!(status.is_terminal(), status.next_statuses().is_empty());
+            assert_eq!(DealStatus::try_from(status as u64).unwrap(), status);
+            assert_eq!(status.to_string().parse::<DealStatus>().unwrap(), status);
+        }
+        assert!(DealTimedOut.is_terminal());
+        assert!(!DealCompleted.is_terminal());
+        assert!(DealActive.accepts_proofs());
+        assert!(!DealCompleted.accepts_proofs());
+        assert!(!DealCancelled.accepts_proofs());
+    }
+
+    #[test]
+    fn deal_status_strings() {
+        assert_eq!(
+            "active".parse::<DealStatus>().unwrap(),
+            DealStatus::DealActive
+        );
+        assert_eq!(
+            "timed_out".parse::<DealStatus>().unwrap(),
+            DealStatus::DealTimedOut
+        );
+        assert!("Deal".parse::<DealStatus>().is_err());
+        assert!("nope".parse::<DealStatus>().is_err());
+        assert!(DealStatus::try_from(8).is_err());
+
+        assert_eq!(
+            serde_json::to_string(&DealStatus::DealCancelled).unwrap(),
+            "\"DealCancelled\""
+        );
+        let status: DealStatus = serde_json::from_str("\"DealActive\"").unwrap();
+        assert_eq!(status, DealStatus::DealActive);
+        let status: DealStatus = serde_json::from_str("5").unwrap();
+        assert_eq!(status, DealStatus::DealFinalized);
+        assert!(serde_json::from_str::<DealStatus>("9").is_err());
+    }
+}
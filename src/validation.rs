@@ -0,0 +1,458 @@
+use crate::{
+    error::{BanyanError, Result},
+    pricing::BYTES_PER_TIB,
+    token::TokenAmount,
+    types::{BlockNum, DealProposal},
+};
+use ethers::types::{Address, U256};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/*
+ * Checking a deal proposal before it's sent to the chain.
+ * Status: A ValidationPolicy is a list of rules. The built-in rules catch proposals the contract
+ * would take but nobody could honour (no executor, no proofs, an empty file). Configurable rules
+ * (per-token price bounds, allowed tokens, a max file size) and any closure can be added on top.
+ * Every rule is checked, so all of a proposal's problems are reported together.
+ */
+
+/// Violation - A rule a deal proposal breaks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The name of the rule
+    pub rule: String,
+    /// What's wrong with the proposal
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}: {}", self.rule, self.message)
+    }
+}
+
+/// ValidationRule - Something a deal proposal must satisfy
+pub trait ValidationRule: Send + Sync {
+    /// A short name for the rule, used in violations
+    fn name(&self) -> &str;
+    /// Check a proposal, returning what's wrong with it if it breaks the rule
+    fn check(&self, proposal: &DealProposal) -> Option<String>;
+}
+
+/// A rule made from a closure, see `ValidationPolicy::with_check`
+struct CheckRule<F> {
+    name: String,
+    check: F,
+}
+
+impl<F> ValidationRule for CheckRule<F>
+where
+    F: Fn(&DealProposal) -> Option<String> + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn check(&self, proposal: &DealProposal) -> Option<String> {
+        (self.check)(proposal)
+    }
+}
+
+/* Built-in rules */
+
+/// The deal must be proposed to someone
+pub struct NonZeroExecutor;
+
+impl ValidationRule for NonZeroExecutor {
+    fn name(&self) -> &str {
+        "non-zero executor"
+    }
+    fn check(&self, proposal: &DealProposal) -> Option<String> {
+        (proposal.executor_address == Address::zero())
+            .then(|| "The executor address is the zero address".to_string())
+    }
+}
+
+/// The deal must be paid in a token
+pub struct NonZeroToken;
+
+impl ValidationRule for NonZeroToken {
+    fn name(&self) -> &str {
+        "non-zero token"
+    }
+    fn check(&self, proposal: &DealProposal) -> Option<String> {
+        (proposal.erc20_token_denomination == Address::zero())
+            .then(|| "The ERC20 token address is the zero address".to_string())
+    }
+}
+
+/// Proofs must be asked for
+pub struct NonZeroProofFrequency;
+
+impl ValidationRule for NonZeroProofFrequency {
+    fn name(&self) -> &str {
+        "non-zero proof frequency"
+    }
+    fn check(&self, proposal: &DealProposal) -> Option<String> {
+        (proposal.proof_frequency_in_blocks == BlockNum(0))
+            .then(|| "The proof frequency is 0 blocks".to_string())
+    }
+}
+
+/// There must be at least one proof window in the deal
+pub struct ProofFrequencyWithinDeal;
+
+impl ValidationRule for ProofFrequencyWithinDeal {
+    fn name(&self) -> &str {
+        "proof frequency within deal"
+    }
+    fn check(&self, proposal: &DealProposal) -> Option<String> {
+        (proposal.proof_frequency_in_blocks > proposal.deal_length_in_blocks).then(|| {
+            format!(
+                "The proof frequency ({} blocks) is longer than the deal ({} blocks)",
+                proposal.proof_frequency_in_blocks, proposal.deal_length_in_blocks
+            )
+        })
+    }
+}
+
+/// There must be something to store
+pub struct NonEmptyFile;
+
+impl ValidationRule for NonEmptyFile {
+    fn name(&self) -> &str {
+        "non-empty file"
+    }
+    fn check(&self, proposal: &DealProposal) -> Option<String> {
+        proposal
+            .file_size
+            .is_zero()
+            .then(|| "The file is empty".to_string())
+    }
+}
+
+/* Configurable rules */
+
+/// PricePerTib - The price per TiB of deals paid in a token must be within bounds.
+/// Deals paid in other tokens aren't checked: add a PricePerTib for each token you take, and
+/// AllowedTokens to turn the rest away.
+pub struct PricePerTib {
+    /// The token the bounds are in
+    pub token: Address,
+    /// The least the deal may pay per TiB
+    pub min: Option<TokenAmount>,
+    /// The most the deal may pay per TiB
+    pub max: Option<TokenAmount>,
+}
+
+impl ValidationRule for PricePerTib {
+    fn name(&self) -> &str {
+        "price per TiB"
+    }
+    fn check(&self, proposal: &DealProposal) -> Option<String> {
+        if proposal.erc20_token_denomination != self.token {
+            return None;
+        }
+        // The proposal only has the price in the token's smallest unit, but if we know how it
+        // was priced we can make sure the bounds are in the same unit
+        if let Some(pricing) = &proposal.pricing {
+            let decimals = pricing.price.model.rate().decimals();
+            let mut bounds = self.min.iter().chain(&self.max);
+            if let Some(bound) = bounds.find(|bound| bound.decimals() != decimals) {
+                return Some(format!(
+                    "The deal was priced in a token with {} decimals, but the bound of {} has {}",
+                    decimals,
+                    bound,
+                    bound.decimals()
+                ));
+            }
+        }
+        // Compare price / size with bound / TiB, without dividing
+        let price = proposal.price.full_mul(U256::from(BYTES_PER_TIB));
+        let bound = |bound: &TokenAmount| bound.amount().full_mul(proposal.file_size);
+        match (&self.min, &self.max) {
+            (Some(min), _) if price < bound(min) => Some(format!(
+                "The price of {} for {} bytes is less than {} per TiB",
+                proposal.price, proposal.file_size, min
+            )),
+            (_, Some(max)) if price > bound(max) => Some(format!(
+                "The price of {} for {} bytes is more than {} per TiB",
+                proposal.price, proposal.file_size, max
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// AllowedTokens - The deal must be paid in one of these tokens
+pub struct AllowedTokens(pub Vec<Address>);
+
+impl ValidationRule for AllowedTokens {
+    fn name(&self) -> &str {
+        "allowed tokens"
+    }
+    fn check(&self, proposal: &DealProposal) -> Option<String> {
+        (!self.0.contains(&proposal.erc20_token_denomination)).then(|| {
+            format!(
+                "The token {:?} is not one of the allowed tokens",
+                proposal.erc20_token_denomination
+            )
+        })
+    }
+}
+
+/// MaxFileSize - The file must be at most this many bytes
+pub struct MaxFileSize(pub u64);
+
+impl ValidationRule for MaxFileSize {
+    fn name(&self) -> &str {
+        "max file size"
+    }
+    fn check(&self, proposal: &DealProposal) -> Option<String> {
+        (proposal.file_size > U256::from(self.0)).then(|| {
+            format!(
+                "The file is {} bytes, more than the {} allowed",
+                proposal.file_size, self.0
+            )
+        })
+    }
+}
+
+/// ValidationPolicy - The rules a deal proposal is checked against
+pub struct ValidationPolicy {
+    rules: Vec<Box<dyn ValidationRule>>,
+}
+
+/// The built-in rules
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        ValidationPolicy::empty()
+            .with_rule(NonZeroExecutor)
+            .with_rule(NonZeroToken)
+            .with_rule(NonZeroProofFrequency)
+            .with_rule(ProofFrequencyWithinDeal)
+            .with_rule(NonEmptyFile)
+    }
+}
+
+impl ValidationPolicy {
+    /// A policy without any rules, which lets every proposal through
+    pub fn empty() -> Self {
+        ValidationPolicy { rules: vec![] }
+    }
+
+    /// Add a rule to the policy
+    pub fn with_rule(mut self, rule: impl ValidationRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Add a rule to the policy from a closure
+    /// # Arguments
+    /// * `name` - A short name for the rule, used in violations
+    /// * `check` - Returns what's wrong with a proposal if it breaks the rule
+    pub fn with_check<F>(self, name: &str, check: F) -> Self
+    where
+        F: Fn(&DealProposal) -> Option<String> + Send + Sync + 'static,
+    {
+        self.with_rule(CheckRule {
+            name: name.to_string(),
+            check,
+        })
+    }
+
+    /// The names of the rules in the policy
+    pub fn rule_names(&self) -> Vec<&str> {
+        self.rules.iter().map(|rule| rule.name()).collect()
+    }
+
+    /// Every rule a proposal breaks, in the order the rules were added
+    pub fn violations(&self, proposal: &DealProposal) -> Vec<Violation> {
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                rule.check(proposal).map(|message| Violation {
+                    rule: rule.name().to_string(),
+                    message,
+                })
+            })
+            .collect()
+    }
+
+    /// validate - Check a proposal against every rule
+    /// # Errors
+    /// * `BanyanError::InvalidProposal` - With every rule the proposal breaks
+    pub fn validate(&self, proposal: &DealProposal) -> Result<()> {
+        let violations = self.violations(proposal);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(BanyanError::InvalidProposal(violations))
+        }
+    }
+}
+
+impl DealProposal {
+    /// validate - Check the proposal against the built-in rules
+    /// # Errors
+    /// * `BanyanError::InvalidProposal` - With every rule the proposal breaks
+    pub fn validate(&self) -> Result<()> {
+        self.validate_with(&ValidationPolicy::default())
+    }
+
+    /// validate_with - Check the proposal against a policy
+    /// # Errors
+    /// * `BanyanError::InvalidProposal` - With every rule the proposal breaks
+    pub fn validate_with(&self, policy: &ValidationPolicy) -> Result<()> {
+        policy.validate(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pricing::{DealPricing, PricingModel},
+        token::Rounding,
+        types::{Blake3Hash, CidWrapper},
+    };
+
+    fn proposal() -> DealProposal {
+        DealProposal {
+            executor_address: Address::from_low_u64_be(1),
+            deal_length_in_blocks: BlockNum(100),
+            proof_frequency_in_blocks: BlockNum(10),
+            price: U256::one(),
+            collateral: U256::zero(),
+            erc20_token_denomination: Address::from_low_u64_be(2),
+            file_size: U256::from(BYTES_PER_TIB / 2),
+            ipfs_file_cid: CidWrapper(Default::default()),
+            blake3_checksum: Blake3Hash(blake3::hash(b"")),
+            pricing: None,
+            piece_commitment: None,
+        }
+    }
+
+    #[test]
+    fn built_in_rules() {
+        assert!(proposal().validate().is_ok());
+
+        let mut bad = proposal();
+        bad.executor_address = Address::zero();
+        bad.erc20_token_denomination = Address::zero();
+        bad.proof_frequency_in_blocks = BlockNum(0);
+        bad.file_size = U256::zero();
+        let violations = ValidationPolicy::default().violations(&bad);
+        let rules: Vec<_> = violations.iter().map(|v| v.rule.as_str()).collect();
+        assert_eq!(
+            rules,
+            vec![
+                "non-zero executor",
+                "non-zero token",
+                "non-zero proof frequency",
+                "non-empty file"
+            ]
+        );
+
+        let mut bad = proposal();
+        bad.proof_frequency_in_blocks = BlockNum(101);
+        match bad.validate() {
+            Err(BanyanError::InvalidProposal(violations)) => {
+                assert_eq!(violations.len(), 1);
+                assert_eq!(
+                    violations[0].to_string(),
+                    "proof frequency within deal: The proof frequency (101 blocks) is longer than the deal (100 blocks)"
+                );
+            }
+            other => panic!("Expected an invalid proposal, got {:?}", other),
+        }
+        assert!(bad.validate_with(&ValidationPolicy::empty()).is_ok());
+    }
+
+    #[test]
+    fn configurable_rules() {
+        // The proposal pays 2 units per TiB
+        let unit = |n: u64| TokenAmount::new(U256::from(n), 0).unwrap();
+        let price = |min: Option<u64>, max: Option<u64>| {
+            ValidationPolicy::empty().with_rule(PricePerTib {
+                token: Address::from_low_u64_be(2),
+                min: min.map(unit),
+                max: max.map(unit),
+            })
+        };
+        assert!(proposal().validate_with(&price(Some(2), Some(2))).is_ok());
+        assert!(proposal().validate_with(&price(Some(3), None)).is_err());
+        assert!(proposal().validate_with(&price(None, Some(1))).is_err());
+        // Bounds for one token say nothing about deals paid in another
+        let mut other_token = proposal();
+        other_token.erc20_token_denomination = Address::from_low_u64_be(3);
+        assert!(other_token.validate_with(&price(Some(3), None)).is_ok());
+        // Nor can bounds in whole units be compared with a price in USDC's smallest unit
+        let mut priced = proposal();
+        priced.pricing = Some(
+            DealPricing::quote(
+                &PricingModel::Flat("1 USDC".parse().unwrap()),
+                &PricingModel::Flat("0 USDC".parse().unwrap()),
+                BYTES_PER_TIB / 2,
+                BlockNum(100),
+                12,
+                Rounding::Nearest,
+            )
+            .unwrap(),
+        );
+        let err = priced.validate_with(&price(Some(2), Some(2))).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("priced in a token with 6 decimals"));
+
+        let policy = ValidationPolicy::default()
+            .with_rule(AllowedTokens(vec![Address::from_low_u64_be(3)]))
+            .with_rule(MaxFileSize(1024))
+            .with_check("short deals", |proposal| {
+                (proposal.deal_length_in_blocks > BlockNum(50))
+                    .then(|| "The deal is too long".to_string())
+            });
+        assert_eq!(
+            policy.rule_names()[5..],
+            ["allowed tokens", "max file size", "short deals"]
+        );
+        let err = proposal().validate_with(&policy).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid deal proposal: allowed tokens: The token 0x0000000000000000000000000000000000000002 \
+             is not one of the allowed tokens; max file size: The file is 549755813888 bytes, more \
+             than the 1024 allowed; short deals: The deal is too long"
+        );
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    /// Make sure an invalid deal never reaches the chain
+    async fn propose_checks_the_deal() -> Result<()> {
+        use crate::{deals::DealProposalBuilder, test_chain::TestChain};
+        use ethers::signers::Signer;
+        use std::fs::File;
+
+        let chain = TestChain::spawn().await?;
+        let creator = chain.creator_client().await?;
+        let deal = DealProposalBuilder::default()
+            .with_file(File::open("test_files/ethereum.pdf")?)
+            .with_executor_address(format!("{:?}", chain.executor().address()))
+            .with_erc20_token_denomination(format!("{:?}", chain.token_address()))
+            .with_price_per_tib("1".parse()?)
+            .with_proof_frequency_in_blocks(0)
+            .build()?;
+        let block = chain.block_number();
+        match creator.propose_deal(deal.clone(), None, None).await {
+            Err(BanyanError::InvalidProposal(violations)) => {
+                assert_eq!(violations[0].rule, "non-zero proof frequency")
+            }
+            other => panic!("Expected an invalid proposal, got {:?}", other),
+        }
+        assert_eq!(chain.block_number(), block);
+
+        // The same deal goes through with the rules turned off
+        let creator = creator.with_validation_policy(ValidationPolicy::empty());
+        assert!(creator.propose_deal(deal, None, None).await.is_ok());
+        assert!(chain.block_number() > block);
+        Ok(())
+    }
+}
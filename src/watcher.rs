@@ -0,0 +1,249 @@
+use crate::{
+    error::{BanyanError, Result},
+    eth::EthClient,
+    types::{BlockNum, DealID, DealStatus},
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/*
+ * Watching a deal's status change on chain.
+ * Status: Polls `getOffer`, and reports each change of status along with the block it was seen
+ * in. A deal may skip statuses between polls, but a change the status machine doesn't allow
+ * (e.g. a finalized deal becoming active again) is reported as an error rather than trusted.
+ */
+
+/// How often to check a deal's status by default
+pub const DEAL_POLL_INTERVAL: Duration = Duration::from_secs(7);
+
+/// StatusChange - A deal's status changing, as seen by a DealWatcher
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusChange {
+    /// The deal whose status changed
+    pub deal_id: DealID,
+    /// The status it was in when last checked
+    pub from: DealStatus,
+    /// The status it's in now
+    pub to: DealStatus,
+    /// The latest block when the change was seen
+    pub seen_in: BlockNum,
+}
+
+/// DealWatcher - Follows a deal through its statuses
+pub struct DealWatcher<'a> {
+    /// The client used to read the deal
+    client: &'a EthClient,
+    /// The deal being watched
+    deal_id: DealID,
+    /// The status the deal was in when last checked
+    status: Option<DealStatus>,
+    /// How long to wait between checks
+    poll_interval: Duration,
+}
+
+impl EthClient {
+    /// watch_deal - Follow a deal's status as it changes on chain
+    /// # Arguments
+    /// * `deal_id` - The deal to watch
+    /// # Returns
+    /// * `DealWatcher` - A watcher that hasn't read the deal yet
+    pub fn watch_deal(&self, deal_id: DealID) -> DealWatcher<'_> {
+        DealWatcher {
+            client: self,
+            deal_id,
+            status: None,
+            poll_interval: DEAL_POLL_INTERVAL,
+        }
+    }
+}
+
+impl<'a> DealWatcher<'a> {
+    /// Set how long to wait between checks of the deal's status
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// The status the deal was in when last checked, if it has been
+    pub fn status(&self) -> Option<DealStatus> {
+        self.status
+    }
+
+    /// poll - Check the deal's status once
+    /// # Returns
+    /// * `Option<StatusChange>` - The change since the last check, if there was one.
+    ///   The first check only records the deal's status.
+    /// # Errors
+    /// * `BanyanError::Chain` - If the deployment doesn't track status, or the deal's status
+    ///   changed in a way the status machine doesn't allow
+    pub async fn poll(&mut self) -> Result<Option<StatusChange>> {
+        if !self
+            .client
+            .contract_version()
+            .deal_info_layout
+            .tracks_status()
+        {
+            return Err(BanyanError::Chain(format!(
+                "Contract version {} doesn't track the status of deals",
+                self.client.contract_version().version
+            )));
+        }
+        let to = self.client.get_deal_status(self.deal_id).await?;
+        let from = match self.status.replace(to) {
+            Some(from) if from != to => from,
+            _ => return Ok(None),
+        };
+        if !from.can_reach(to) {
+            return Err(BanyanError::Chain(format!(
+                "Deal {} went from {} to {}, which its status can't do",
+                self.deal_id, from, to
+            )));
+        }
+        Ok(Some(StatusChange {
+            deal_id: self.deal_id,
+            from,
+            to,
+            seen_in: self.client.get_latest_block_num().await?,
+        }))
+    }
+
+    /// next_change - Wait for the deal's status to change
+    /// # Arguments
+    /// * `timeout` - How long to wait before giving up
+    /// # Returns
+    /// * `StatusChange` - The change
+    /// # Errors
+    /// * `BanyanError::Chain` - If the deal is already over, the timeout passes, or `poll` fails
+    pub async fn next_change(&mut self, timeout: Duration) -> Result<StatusChange> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(change) = self.poll().await? {
+                return Ok(change);
+            }
+            match self.status {
+                Some(status) if status.is_terminal() => {
+                    return Err(BanyanError::Chain(format!(
+                        "Deal {} is {}, its status won't change again",
+                        self.deal_id, status
+                    )))
+                }
+                _ if Instant::now() >= deadline => {
+                    return Err(BanyanError::Chain(format!(
+                        "Timed out waiting for the status of deal {} to change",
+                        self.deal_id
+                    )))
+                }
+                _ => tokio::time::sleep(self.poll_interval.min(timeout)).await,
+            }
+        }
+    }
+
+    /// wait_for - Wait for the deal to reach a status
+    /// # Arguments
+    /// * `status` - The status to wait for
+    /// * `timeout` - How long to wait before giving up
+    /// # Returns
+    /// * `Vec<StatusChange>` - Every change seen on the way
+    /// # Errors
+    /// * `BanyanError::InvalidDealStatus` - If the deal can no longer reach `status`
+    /// * `BanyanError::Chain` - If the timeout passes, or `poll` fails
+    pub async fn wait_for(
+        &mut self,
+        status: DealStatus,
+        timeout: Duration,
+    ) -> Result<Vec<StatusChange>> {
+        let deadline = Instant::now() + timeout;
+        let mut changes = vec![];
+        if self.status.is_none() {
+            self.poll().await?;
+        }
+        loop {
+            match self.status {
+                Some(current) if current == status => return Ok(changes),
+                Some(current) if !current.can_reach(status) => {
+                    return Err(BanyanError::InvalidDealStatus {
+                        deal_id: self.deal_id,
+                        status: current,
+                        action: format!("wait to become {} for", status),
+                    })
+                }
+                _ => {}
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            changes.push(self.next_change(remaining).await?);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    /// Watch a deal get cancelled, and make sure a cancelled deal is refused locally
+    async fn watch_a_cancelled_deal() -> Result<(), crate::error::BanyanError> {
+        use super::*;
+        use crate::{deals::DealProposalBuilder, test_chain::TestChain};
+        use ethers::{abi::Tokenizable, signers::Signer};
+        use std::fs::File;
+
+        let chain = TestChain::spawn().await?;
+        let creator = chain.creator_client().await?;
+        let deal = DealProposalBuilder::default()
+            .with_file(File::open("test_files/ethereum.pdf")?)
+            .with_executor_address(format!("{:?}", chain.executor().address()))
+            .with_erc20_token_denomination(format!("{:?}", chain.token_address()))
+            .with_price_per_tib("1".parse()?)
+            .build()?;
+        let deal_id = creator.propose_deal(deal, None, None).await?;
+
+        let mut watcher = creator
+            .watch_deal(deal_id)
+            .with_poll_interval(Duration::from_millis(10));
+        assert_eq!(watcher.poll().await?, None);
+        assert_eq!(watcher.status(), Some(DealStatus::DealCreated));
+        assert!(matches!(
+            watcher.next_change(Duration::from_millis(50)).await,
+            Err(BanyanError::Chain(_))
+        ));
+
+        // The creator takes the offer back before anyone accepts it
+        let rescind = creator.contract_version().abi.function("rescindOffer")?;
+        chain.transact(
+            chain.creator().address(),
+            chain.escrow_address(),
+            rescind.encode_input(&[deal_id.into_token()])?.into(),
+        )?;
+        let changes = watcher
+            .wait_for(DealStatus::DealCancelled, Duration::from_secs(5))
+            .await?;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].from, DealStatus::DealCreated);
+        assert_eq!(changes[0].to, DealStatus::DealCancelled);
+        assert_eq!(changes[0].seen_in, chain.block_number());
+
+        // Nothing more can happen to a cancelled deal
+        assert!(watcher
+            .wait_for(DealStatus::DealActive, Duration::from_secs(5))
+            .await
+            .is_err());
+        let err = chain
+            .executor_client()
+            .await?
+            .post_proof(deal_id, vec![0u8; 64].into(), BlockNum(0), None, None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Deal status error: cannot post a proof for deal {}, it is DealCancelled",
+                deal_id
+            )
+        );
+        assert!(matches!(
+            creator.complete_deal(deal_id, 75, None, None).await,
+            Err(BanyanError::InvalidDealStatus { .. })
+        ));
+        Ok(())
+    }
+}